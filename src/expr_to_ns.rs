@@ -5,10 +5,64 @@ use hash_cons::Hc;
 use crate::deterministic_map::{HashMap, HashSet};
 use std::cmp::Ordering;
 use std::hash::{Hash, Hasher};
+use std::sync::Arc;
 
-#[derive(Clone, Eq, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+/// Constants of the enum each variable (or array) was declared with, so that
+/// states print `C=RED` instead of `C=0`
+pub type EnumNames = HashMap<String, Vec<String>>;
+
+/// Settings of `try_program_to_ns` that the command line can change
+#[derive(Clone, Debug)]
+pub struct ConvertOptions {
+    /// Number of states a loop may go through without yielding before `run_expr` gives up on it
+    pub loop_budget: usize,
+    /// Sessions given with `--session`, as the names of the requests each one issues
+    pub sessions: Vec<Vec<String>>,
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        Self {
+            loop_budget: 100_000,
+            sessions: Vec::new(),
+        }
+    }
+}
+
+/// Symbolic name of the value of `var`, if it was declared with an enum type
+fn enum_name<'a>(names: &'a EnumNames, var: &str, value: i64) -> Option<&'a String> {
+    if var.ends_with("@held") {
+        return None;
+    }
+    // Array elements `A[i]` and procedure locals `x@f` share the declaration of `A` and `x`
+    let declared = var.split(['[', '@']).next()?;
+    let index = usize::try_from(value).ok()?;
+    names.get(declared)?.get(index)
+}
+
+/// Variable assignment of a request (locals) or of the shared state (globals).
+/// Only non-zero values are stored, and each array element `A[i]` is a separate
+/// entry, so a sparse array costs only its non-zero elements and equal states
+/// always have equal representations.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub struct Env {
     vars: HashMap<String, i64>,
+    /// Enum constants of the program the state belongs to, only used for printing
+    #[serde(skip)]
+    names: Option<Arc<EnumNames>>,
+}
+
+impl PartialEq for Env {
+    fn eq(&self, other: &Self) -> bool {
+        self.vars == other.vars
+    }
+}
+impl Eq for Env {}
+
+impl std::fmt::Debug for Env {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Env").field("vars", &self.vars).finish()
+    }
 }
 
 impl PartialOrd for Env {
@@ -35,7 +89,10 @@ impl std::fmt::Display for Env {
         // Format each variable assignment
         let formatted = pairs
             .iter()
-            .map(|(k, v)| format!("{}={}", k, v))
+            .map(|(k, v)| match self.names.as_deref().and_then(|names| enum_name(names, k, **v)) {
+                Some(name) => format!("{}={}", k, name),
+                None => format!("{}={}", k, v),
+            })
             .collect::<Vec<_>>()
             .join(",");
 
@@ -59,6 +116,14 @@ impl Env {
     fn new() -> Self {
         Self {
             vars: HashMap::default(),
+            names: None,
+        }
+    }
+    /// Empty state that prints the values of enum variables with their constants
+    fn with_names(names: &Arc<EnumNames>) -> Self {
+        Self {
+            vars: HashMap::default(),
+            names: Some(names.clone()),
         }
     }
     fn insert(self, var: String, value: i64) -> Self {
@@ -68,7 +133,7 @@ impl Env {
         } else {
             vars.insert(var, value);
         }
-        Self { vars }
+        Self { vars, ..self }
     }
     fn get(&self, var: &str) -> i64 {
         // Variables are initialized to 0
@@ -78,19 +143,294 @@ impl Env {
 
 pub type Local = Env;
 pub type Global = Env;
+/// The network system of a program
+pub type ProgramNS = NS<Global, LocalExpr, ExprRequest, Response>;
+/// Value of an expression. Tuples only occur as the result of a request,
+/// which the parser checks, so operators only ever see numbers.
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub enum Value {
+    Number(i64),
+    Tuple(Vec<i64>),
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
 pub enum ExprResult {
     Yielding(Hc<Expr>),
-    Returning(i64),
+    Returning(Value),
+    /// The request stopped with a runtime error (e.g. an out-of-range write)
+    Failing(String),
+    /// The request ran `exit`: it stops at once and keeps the writes made so far
+    Exiting,
+    /// A transaction ran `abort`: its writes are undone and the request stops
+    Aborting,
+    /// An `assert` failed; the string is the source text of its condition
+    Violating(String),
+    /// A `break`, `continue` or `return` on its way to the loop or request it leaves
+    Jumping(Jump),
+    /// The loop went through more states than the loop budget without yielding;
+    /// it probably never ends, so the conversion stops
+    Diverging(Hc<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub enum Jump {
+    Break,
+    Continue,
+    Return(Value),
+}
+
+/// Whether `expr` may `break` or `continue` the loop it sits in (not a nested one)
+fn jumps_out_of_loop(expr: &Expr) -> bool {
+    match expr {
+        Expr::Break | Expr::Continue => true,
+        // Jumps in a nested loop body stay in that loop
+        Expr::While(cond, _) | Expr::Iterate(_, cond, _) => jumps_out_of_loop(cond),
+        Expr::Assign(_, e)
+        | Expr::Atomic(e)
+        | Expr::Transaction(e)
+        | Expr::Return(e)
+        | Expr::Assume(e)
+        | Expr::Assert(_, e)
+        | Expr::Await(e)
+        | Expr::Send(_, _, e)
+        | Expr::Not(e)
+        | Expr::Load(_, _, e)
+        | Expr::Bounded(_, _, e) => jumps_out_of_loop(e),
+        Expr::Equal(e1, e2)
+        | Expr::Add(e1, e2)
+        | Expr::Subtract(e1, e2)
+        | Expr::Multiply(e1, e2)
+        | Expr::Divide(e1, e2)
+        | Expr::Modulo(e1, e2)
+        | Expr::NotEqual(e1, e2)
+        | Expr::Less(e1, e2)
+        | Expr::LessEqual(e1, e2)
+        | Expr::Greater(e1, e2)
+        | Expr::GreaterEqual(e1, e2)
+        | Expr::Sequence(e1, e2)
+        | Expr::And(e1, e2)
+        | Expr::Or(e1, e2)
+        | Expr::Store(_, _, e1, e2) => jumps_out_of_loop(e1) || jumps_out_of_loop(e2),
+        Expr::If(cond, then_branch, else_branch) => {
+            jumps_out_of_loop(cond) || jumps_out_of_loop(then_branch) || jumps_out_of_loop(else_branch)
+        }
+        Expr::Choice(first, second) => jumps_out_of_loop(first) || jumps_out_of_loop(second),
+        Expr::Tuple(items) => items.iter().any(|item| jumps_out_of_loop(item)),
+        Expr::Typed(_, _, body) => jumps_out_of_loop(body),
+        Expr::Choose(_, _)
+        | Expr::Lock(_)
+        | Expr::Unlock(_)
+        | Expr::Acquire(_, _)
+        | Expr::Release(_)
+        | Expr::Recv(_, _)
+        | Expr::Error(_)
+        | Expr::Violation(_)
+        | Expr::Yield
+        | Expr::Exit
+        | Expr::Crashed
+        | Expr::Abort
+        | Expr::Unknown
+        | Expr::Symbol(_, _)
+        | Expr::Number(_)
+        | Expr::Variable(_) => false,
+    }
+}
+
+/// Continue with `rest` and then the loop `while(cond){body}`. A plain sequence
+/// does when `rest` cannot jump, so loops without jumps keep their usual states.
+fn iteration_rest(exprhc: &mut ExprHc, rest: Hc<Expr>, cond: &Hc<Expr>, body: &Hc<Expr>) -> Hc<Expr> {
+    if jumps_out_of_loop(&rest) {
+        exprhc.iterate(rest, cond.clone(), body.clone())
+    } else {
+        let while_expr = exprhc.while_expr(cond.clone(), body.clone());
+        exprhc.sequence(rest, while_expr)
+    }
+}
+
+/// Response of a request in the Network System generated from a program
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, serde::Serialize, serde::Deserialize)]
+pub enum Response {
+    /// The request finished with this value
+    Value(i64),
+    /// The request stopped with a runtime error
+    Error(String),
+    /// The request was aborted with `exit`
+    Exit,
+    /// A transaction of the request ran `abort`; its writes were undone
+    Aborted,
+    /// An assertion failed; the string is the source text of its condition
+    AssertionFailed(String),
+    /// The request finished with this enum constant
+    Symbol(String),
+    /// The request finished with this tuple of values
+    Tuple(Vec<i64>),
+    /// A crashable request crashed; it keeps its writes but never really responds
+    Crashed,
 }
 
-fn is_local(var: &str) -> bool {
+impl std::fmt::Display for Response {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Response::Value(n) => write!(f, "{}", n),
+            Response::Error(message) => write!(f, "error({})", message),
+            Response::Exit => write!(f, "exit"),
+            Response::Aborted => write!(f, "abort"),
+            Response::Crashed => write!(f, "crashed"),
+            Response::AssertionFailed(cond) => write!(f, "assertion failed({})", cond),
+            Response::Symbol(name) => write!(f, "{}", name),
+            Response::Tuple(values) => {
+                let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                write!(f, "({})", values.join(", "))
+            }
+        }
+    }
+}
+
+/// Local variable that counts how often this request holds the lock or semaphore `var`.
+/// The `@` keeps it apart from the variables of the program.
+fn held_marker(var: &str) -> String {
+    format!("{}@held", var)
+}
+
+/// Messages in the channel `name`, oldest first; a bag keeps them sorted instead.
+/// The global `name` counts the messages and `name[i]` holds the i-th of them.
+fn messages(global: &Global, name: &str) -> Vec<i64> {
+    (0..global.get(name)).map(|i| global.get(&element(name, i))).collect()
+}
+
+/// Replace the messages in the channel `name`
+fn set_messages(global: Global, name: &str, old_len: usize, messages: &[i64]) -> Global {
+    let global = global.insert(name.to_string(), messages.len() as i64);
+    (0..old_len.max(messages.len())).fold(global, |global, i| {
+        let value = messages.get(i).copied().unwrap_or(0);
+        global.insert(element(name, i as i64), value)
+    })
+}
+
+/// Local that is set while the request runs a transaction
+const IN_TRANSACTION: &str = "@transaction";
+
+/// Local that marks every state of a crashable request, so that it never shares
+/// a state with a request that cannot crash
+const CRASHABLE: &str = "@crashable";
+
+/// Locals of the write log of a transaction: `X@logged` marks that the global `X` was
+/// written since the transaction began and `X@undo` holds its value from before.
+/// The marker is needed because a value of 0 takes no entry.
+fn logged_marker(var: &str) -> String {
+    format!("{}@logged", var)
+}
+
+fn undo_marker(var: &str) -> String {
+    format!("{}@undo", var)
+}
+
+/// Record the value of the global `var` before its first write in the running transaction
+fn log_write(local: Local, global: &Global, var: &str) -> Local {
+    if local.get(IN_TRANSACTION) == 0 || local.get(&logged_marker(var)) != 0 {
+        return local;
+    }
+    local.insert(logged_marker(var), 1).insert(undo_marker(var), global.get(var))
+}
+
+/// Drop the write log when the transaction ends
+fn forget_writes(local: Local) -> Local {
+    let log: Vec<String> = local
+        .vars
+        .keys()
+        .filter(|var| var.ends_with("@logged") || var.ends_with("@undo") || *var == IN_TRANSACTION)
+        .cloned()
+        .collect();
+    log.into_iter().fold(local, |local, var| local.insert(var, 0))
+}
+
+/// Give every global written in the running transaction its value from before
+fn roll_back(local: Local, global: Global) -> (Local, Global) {
+    let written: Vec<String> = local
+        .vars
+        .keys()
+        .filter_map(|var| var.strip_suffix("@logged"))
+        .map(|var| var.to_string())
+        .collect();
+    let global = written.into_iter().fold(global, |global, var| {
+        let value = local.get(&undo_marker(&var));
+        global.insert(var, value)
+    });
+    (forget_writes(local), global)
+}
+
+/// A request must give back its locks and permits before it responds
+fn check_released(result: ExprResult, local: &Local) -> ExprResult {
+    if !matches!(result, ExprResult::Returning(_)) {
+        return result;
+    }
+    let held = local.vars.keys().filter_map(|var| var.strip_suffix("@held")).min();
+    match held {
+        Some(var) => ExprResult::Failing(format!("finished while holding {}", var)),
+        None => result,
+    }
+}
+
+/// Name of the entry that stores element `index` of a global array
+fn element(array: &str, index: i64) -> String {
+    format!("{}[{}]", array, index)
+}
+
+pub(crate) fn is_local(var: &str) -> bool {
     // Variables that start with a lowercase letter are local
     var.chars().next().unwrap().is_lowercase()
 }
 
+/// Evaluate a strict binary operator: `e1` first, then `e2`, then combine the values with `op`.
+/// If an operand yields, `rebuild` reconstructs the remaining operator expression around it.
+fn run_binary(
+    exprhc: &mut ExprHc,
+    budget: usize,
+    e1: &Hc<Expr>,
+    e2: &Hc<Expr>,
+    local: Local,
+    global: Global,
+    rebuild: fn(&mut ExprHc, Hc<Expr>, Hc<Expr>) -> Hc<Expr>,
+    op: fn(i64, i64) -> i64,
+) -> Vec<(ExprResult, Local, Global)> {
+    let mut results = Vec::new();
+    for (expr_result1, local1, global1) in run_expr(exprhc, budget, e1, local, global) {
+        match expr_result1 {
+            ExprResult::Yielding(e) => {
+                results.push((
+                    ExprResult::Yielding(rebuild(exprhc, e, e2.clone())),
+                    local1,
+                    global1,
+                ));
+            }
+            ExprResult::Returning(Value::Number(n1)) => {
+                for (expr_result2, local2, global2) in run_expr(exprhc, budget, e2, local1, global1) {
+                    match expr_result2 {
+                        ExprResult::Yielding(e) => {
+                            let e1 = exprhc.number(n1);
+                            let e = rebuild(exprhc, e1, e);
+                            results.push((ExprResult::Yielding(e), local2, global2));
+                        }
+                        ExprResult::Returning(Value::Number(n2)) => {
+                            results.push((ExprResult::Returning(Value::Number(op(n1, n2))), local2, global2));
+                        }
+                        // Errors propagate unchanged
+                        halted => results.push((halted, local2, global2)),
+                    }
+                }
+            }
+            // Errors propagate unchanged
+            halted => results.push((halted, local1, global1)),
+        }
+    }
+    results
+}
+
+/// Run `expr` until it yields or finishes; a loop that goes through more than `budget`
+/// states without yielding stops with `ExprResult::Diverging`
 pub fn run_expr(
     exprhc: &mut ExprHc,
+    budget: usize,
     expr: &Expr,
     local: Local,
     global: Global,
@@ -98,7 +438,7 @@ pub fn run_expr(
     let mut results = Vec::new();
     match expr {
         Expr::Assign(var, e) => {
-            for (expr_result, local, global) in run_expr(exprhc, e, local, global) {
+            for (expr_result, local, global) in run_expr(exprhc, budget, e, local, global) {
                 match expr_result {
                     ExprResult::Yielding(e) => {
                         results.push((
@@ -107,114 +447,74 @@ pub fn run_expr(
                             global,
                         ));
                     }
-                    ExprResult::Returning(n) => {
+                    ExprResult::Returning(Value::Number(n)) => {
                         // Assign to local or global
                         if is_local(var) {
                             results.push((
-                                ExprResult::Returning(n),
+                                ExprResult::Returning(Value::Number(n)),
                                 local.insert(var.clone(), n),
                                 global,
                             ));
                         } else {
                             results.push((
-                                ExprResult::Returning(n),
-                                local,
+                                ExprResult::Returning(Value::Number(n)),
+                                log_write(local, &global, var),
                                 global.insert(var.clone(), n),
                             ));
                         }
                     }
+                    // Errors propagate unchanged
+                    halted => results.push((halted, local, global)),
                 }
             }
         }
         Expr::Equal(e1, e2) => {
-            for (expr_result1, local1, global1) in run_expr(exprhc, e1, local, global) {
-                match expr_result1 {
-                    ExprResult::Yielding(e) => {
-                        results.push((
-                            ExprResult::Yielding(exprhc.equal(e, e2.clone())),
-                            local1,
-                            global1,
-                        ));
-                    }
-                    ExprResult::Returning(n1) => {
-                        for (expr_result2, local2, global2) in run_expr(exprhc, e2, local1, global1)
-                        {
-                            match expr_result2 {
-                                ExprResult::Yielding(e) => {
-                                    let e1 = exprhc.number(n1);
-                                    let e = exprhc.equal(e1, e);
-                                    results.push((ExprResult::Yielding(e), local2, global2));
-                                }
-                                ExprResult::Returning(n2) => {
-                                    let result = if n1 == n2 { 1 } else { 0 };
-                                    results.push((ExprResult::Returning(result), local2, global2));
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+            results = run_binary(exprhc, budget, e1, e2, local, global, ExprHc::equal, |n1, n2| {
+                if n1 == n2 { 1 } else { 0 }
+            });
+        }
+        Expr::NotEqual(e1, e2) => {
+            results = run_binary(exprhc, budget, e1, e2, local, global, ExprHc::not_equal, |n1, n2| {
+                if n1 != n2 { 1 } else { 0 }
+            });
+        }
+        Expr::Less(e1, e2) => {
+            results = run_binary(exprhc, budget, e1, e2, local, global, ExprHc::less, |n1, n2| {
+                if n1 < n2 { 1 } else { 0 }
+            });
+        }
+        Expr::LessEqual(e1, e2) => {
+            results = run_binary(exprhc, budget, e1, e2, local, global, ExprHc::less_equal, |n1, n2| {
+                if n1 <= n2 { 1 } else { 0 }
+            });
+        }
+        Expr::Greater(e1, e2) => {
+            results = run_binary(exprhc, budget, e1, e2, local, global, ExprHc::greater, |n1, n2| {
+                if n1 > n2 { 1 } else { 0 }
+            });
+        }
+        Expr::GreaterEqual(e1, e2) => {
+            results = run_binary(exprhc, budget, e1, e2, local, global, ExprHc::greater_equal, |n1, n2| {
+                if n1 >= n2 { 1 } else { 0 }
+            });
         }
         Expr::Add(e1, e2) => {
-            for (expr_result1, local1, global1) in run_expr(exprhc, e1, local, global) {
-                match expr_result1 {
-                    ExprResult::Yielding(e) => {
-                        results.push((
-                            ExprResult::Yielding(exprhc.add(e, e2.clone())),
-                            local1,
-                            global1,
-                        ));
-                    }
-                    ExprResult::Returning(n1) => {
-                        for (expr_result2, local2, global2) in run_expr(exprhc, e2, local1, global1)
-                        {
-                            match expr_result2 {
-                                ExprResult::Yielding(e) => {
-                                    let e1 = exprhc.number(n1);
-                                    let e = exprhc.add(e1, e);
-                                    results.push((ExprResult::Yielding(e), local2, global2));
-                                }
-                                ExprResult::Returning(n2) => {
-                                    let result = n1 + n2;
-                                    results.push((ExprResult::Returning(result), local2, global2));
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+            results = run_binary(exprhc, budget, e1, e2, local, global, ExprHc::add, add_values);
         }
         Expr::Subtract(e1, e2) => {
-            for (expr_result1, local1, global1) in run_expr(exprhc, e1, local, global) {
-                match expr_result1 {
-                    ExprResult::Yielding(e) => {
-                        results.push((
-                            ExprResult::Yielding(exprhc.subtract(e, e2.clone())),
-                            local1,
-                            global1,
-                        ));
-                    }
-                    ExprResult::Returning(n1) => {
-                        for (expr_result2, local2, global2) in run_expr(exprhc, e2, local1, global1)
-                        {
-                            match expr_result2 {
-                                ExprResult::Yielding(e) => {
-                                    let e1 = exprhc.number(n1);
-                                    let e = exprhc.subtract(e1, e);
-                                    results.push((ExprResult::Yielding(e), local2, global2));
-                                }
-                                ExprResult::Returning(n2) => {
-                                    let result = n1 - n2;
-                                    results.push((ExprResult::Returning(result), local2, global2));
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+            results = run_binary(exprhc, budget, e1, e2, local, global, ExprHc::subtract, subtract_values);
+        }
+        Expr::Multiply(e1, e2) => {
+            results = run_binary(exprhc, budget, e1, e2, local, global, ExprHc::multiply, multiply_values);
+        }
+        Expr::Divide(e1, e2) => {
+            results = run_binary(exprhc, budget, e1, e2, local, global, ExprHc::divide, divide_values);
+        }
+        Expr::Modulo(e1, e2) => {
+            results = run_binary(exprhc, budget, e1, e2, local, global, ExprHc::modulo, modulo_values);
         }
         Expr::Sequence(e1, e2) => {
-            for (expr_result1, local1, global1) in run_expr(exprhc, e1, local, global) {
+            for (expr_result1, local1, global1) in run_expr(exprhc, budget, e1, local, global) {
                 match expr_result1 {
                     ExprResult::Yielding(e) => {
                         results.push((
@@ -225,16 +525,18 @@ pub fn run_expr(
                     }
                     ExprResult::Returning(_) => {
                         // Ignore the result of e1 and continue with e2
-                        for (expr_result2, local2, global2) in run_expr(exprhc, e2, local1, global1)
+                        for (expr_result2, local2, global2) in run_expr(exprhc, budget, e2, local1, global1)
                         {
                             results.push((expr_result2, local2, global2));
                         }
                     }
+                    // Errors propagate unchanged
+                    halted => results.push((halted, local1, global1)),
                 }
             }
         }
         Expr::If(cond, then_branch, else_branch) => {
-            for (expr_result, local1, global1) in run_expr(exprhc, cond, local, global) {
+            for (expr_result, local1, global1) in run_expr(exprhc, budget, cond, local, global) {
                 match expr_result {
                     ExprResult::Yielding(e) => {
                         results.push((
@@ -247,23 +549,25 @@ pub fn run_expr(
                             global1,
                         ));
                     }
-                    ExprResult::Returning(n) => {
+                    ExprResult::Returning(Value::Number(n)) => {
                         if n != 0 {
                             // Condition is true, execute then branch
                             for (expr_result2, local2, global2) in
-                                run_expr(exprhc, then_branch, local1, global1)
+                                run_expr(exprhc, budget, then_branch, local1, global1)
                             {
                                 results.push((expr_result2, local2, global2));
                             }
                         } else {
                             // Condition is false, execute else branch
                             for (expr_result2, local2, global2) in
-                                run_expr(exprhc, else_branch, local1, global1)
+                                run_expr(exprhc, budget, else_branch, local1, global1)
                             {
                                 results.push((expr_result2, local2, global2));
                             }
                         }
                     }
+                    // Errors propagate unchanged
+                    halted => results.push((halted, local1, global1)),
                 }
             }
         }
@@ -280,9 +584,15 @@ pub fn run_expr(
                 if !visited.insert((local.clone(), global.clone())) {
                     continue;
                 }
+                // A loop that keeps reaching new states, e.g. with an unbounded counter, would not stop
+                if visited.len() > budget {
+                    let loop_expr = exprhc.while_expr(cond.clone(), body.clone());
+                    results.push((ExprResult::Diverging(loop_expr), local, global));
+                    break;
+                }
 
                 // First, evaluate the condition
-                for (expr_result, local1, global1) in run_expr(exprhc, cond, local, global) {
+                for (expr_result, local1, global1) in run_expr(exprhc, budget, cond, local, global) {
                     match expr_result {
                         ExprResult::Yielding(e) => {
                             // If condition yields, we yield the entire while expression
@@ -292,81 +602,530 @@ pub fn run_expr(
                                 global1,
                             ));
                         }
-                        ExprResult::Returning(n) => {
+                        ExprResult::Returning(Value::Number(n)) => {
                             if n != 0 {
                                 // Condition is true, execute body
                                 for (expr_result2, local2, global2) in
-                                    run_expr(exprhc, body, local1, global1)
+                                    run_expr(exprhc, budget, body, local1, global1)
                                 {
                                     match expr_result2 {
                                         ExprResult::Yielding(e) => {
                                             // If body yields, we yield followed by the while loop
-                                            let while_expr =
-                                                exprhc.while_expr(cond.clone(), body.clone());
                                             results.push((
                                                 ExprResult::Yielding(
-                                                    exprhc.sequence(e, while_expr),
+                                                    iteration_rest(exprhc, e, cond, body),
                                                 ),
                                                 local2,
                                                 global2,
                                             ));
                                         }
-                                        ExprResult::Returning(_) => {
+                                        ExprResult::Returning(_)
+                                        | ExprResult::Jumping(Jump::Continue) => {
                                             // Body completed without yielding, continue loop
                                             todo.push((local2, global2));
                                         }
+                                        ExprResult::Jumping(Jump::Break) => {
+                                            // Leave the loop with result 0
+                                            results.push((ExprResult::Returning(Value::Number(0)), local2, global2));
+                                        }
+                                        // Errors propagate unchanged
+                                        halted => results.push((halted, local2, global2)),
                                     }
                                 }
                             } else {
                                 // Condition is false, exit the loop with result 0
-                                results.push((ExprResult::Returning(0), local1, global1));
+                                results.push((ExprResult::Returning(Value::Number(0)), local1, global1));
+                            }
+                        }
+                        // Errors propagate unchanged
+                        halted => results.push((halted, local1, global1)),
+                    }
+                }
+            }
+        }
+        Expr::Iterate(rest, cond, body) => {
+            for (expr_result, local1, global1) in run_expr(exprhc, budget, rest, local, global) {
+                match expr_result {
+                    ExprResult::Yielding(e) => {
+                        results.push((
+                            ExprResult::Yielding(iteration_rest(exprhc, e, cond, body)),
+                            local1,
+                            global1,
+                        ));
+                    }
+                    ExprResult::Returning(_) | ExprResult::Jumping(Jump::Continue) => {
+                        // The iteration is done; the loop goes on in the same step
+                        let while_expr = exprhc.while_expr(cond.clone(), body.clone());
+                        results.extend(run_expr(exprhc, budget, &while_expr, local1, global1));
+                    }
+                    ExprResult::Jumping(Jump::Break) => {
+                        results.push((ExprResult::Returning(Value::Number(0)), local1, global1));
+                    }
+                    // Errors propagate unchanged
+                    halted => results.push((halted, local1, global1)),
+                }
+            }
+        }
+        Expr::Break => results.push((ExprResult::Jumping(Jump::Break), local, global)),
+        Expr::Continue => results.push((ExprResult::Jumping(Jump::Continue), local, global)),
+        Expr::Return(value) => {
+            for (expr_result, local1, global1) in run_expr(exprhc, budget, value, local, global) {
+                match expr_result {
+                    ExprResult::Yielding(e) => {
+                        results.push((ExprResult::Yielding(exprhc.return_expr(e)), local1, global1));
+                    }
+                    ExprResult::Returning(value) => {
+                        results.push((ExprResult::Jumping(Jump::Return(value)), local1, global1));
+                    }
+                    // Errors propagate unchanged
+                    halted => results.push((halted, local1, global1)),
+                }
+            }
+        }
+        Expr::Atomic(body) => {
+            // The parser rejects yields inside atomic blocks, so the body runs to completion
+            // and the whole region becomes a single transition
+            for (expr_result, local1, global1) in run_expr(exprhc, budget, body, local.clone(), global.clone()) {
+                match expr_result {
+                    // Only a blocked lock can yield here; the region then waits as a whole
+                    ExprResult::Yielding(_) => {
+                        results.push((
+                            ExprResult::Yielding(exprhc.atomic(body.clone())),
+                            local.clone(),
+                            global.clone(),
+                        ));
+                    }
+                    other => results.push((other, local1, global1)),
+                }
+            }
+        }
+        Expr::Transaction(body) => {
+            // The write log lives in the local state until the transaction ends
+            let local = local.insert(IN_TRANSACTION.to_string(), 1);
+            for (expr_result, local1, global1) in run_expr(exprhc, budget, body, local, global) {
+                match expr_result {
+                    // Nothing is left that could abort, so the transaction is over
+                    ExprResult::Yielding(e) if matches!(e.get(), Expr::Number(_)) => {
+                        results.push((ExprResult::Yielding(e), forget_writes(local1), global1));
+                    }
+                    ExprResult::Yielding(e) => {
+                        results.push((ExprResult::Yielding(exprhc.transaction(e)), local1, global1));
+                    }
+                    // An abort already dropped the log; a jump or error leaves the transaction
+                    other => results.push((other, forget_writes(local1), global1)),
+                }
+            }
+        }
+        Expr::Lock(lock) => {
+            let held = held_marker(lock);
+            if local.get(&held) != 0 {
+                // Taking the lock again would deadlock the request
+                let message = format!("lock({}) while already holding {}", lock, lock);
+                results.push((ExprResult::Failing(message), local, global));
+            } else if global.get(lock) != 0 {
+                // Blocked: the request stays here until the lock is released
+                results.push((ExprResult::Yielding(exprhc.lock(lock.clone())), local, global));
+            } else {
+                results.push((
+                    ExprResult::Returning(Value::Number(0)),
+                    local.insert(held, 1),
+                    global.insert(lock.clone(), 1),
+                ));
+            }
+        }
+        Expr::Unlock(lock) => {
+            let held = held_marker(lock);
+            if local.get(&held) == 0 {
+                let message = format!("unlock({}) without holding {}", lock, lock);
+                results.push((ExprResult::Failing(message), local, global));
+            } else {
+                results.push((
+                    ExprResult::Returning(Value::Number(0)),
+                    local.insert(held, 0),
+                    global.insert(lock.clone(), 0),
+                ));
+            }
+        }
+        Expr::Acquire(sem, capacity) => {
+            let in_use = global.get(sem);
+            if in_use >= *capacity {
+                // Blocked until another request releases a permit
+                results.push((
+                    ExprResult::Yielding(exprhc.acquire(sem.clone(), *capacity)),
+                    local,
+                    global,
+                ));
+            } else {
+                let held = held_marker(sem);
+                // The state space pairs every local state with every global state, so the
+                // counts are kept within the capacity even in combinations no run reaches
+                let count = (local.get(&held) + 1).min(*capacity);
+                results.push((
+                    ExprResult::Returning(Value::Number(0)),
+                    local.insert(held, count),
+                    global.insert(sem.clone(), in_use + 1),
+                ));
+            }
+        }
+        Expr::Release(sem) => {
+            let held = held_marker(sem);
+            let count = local.get(&held);
+            if count == 0 {
+                let message = format!("release({}) without holding a permit of {}", sem, sem);
+                results.push((ExprResult::Failing(message), local, global));
+            } else {
+                let in_use = (global.get(sem) - 1).max(0);
+                results.push((
+                    ExprResult::Returning(Value::Number(0)),
+                    local.insert(held, count - 1),
+                    global.insert(sem.clone(), in_use),
+                ));
+            }
+        }
+        Expr::Await(cond) => {
+            for (expr_result, local1, global1) in run_expr(exprhc, budget, cond, local.clone(), global.clone()) {
+                match expr_result {
+                    // Blocked until another request makes the condition true; the condition
+                    // is checked again from the same state, so nothing it did is kept.
+                    // A blocked lock in the condition waits the same way.
+                    ExprResult::Returning(Value::Number(0)) | ExprResult::Yielding(_) => {
+                        results.push((
+                            ExprResult::Yielding(exprhc.await_expr(cond.clone())),
+                            local.clone(),
+                            global.clone(),
+                        ));
+                    }
+                    ExprResult::Returning(_) => {
+                        results.push((ExprResult::Returning(Value::Number(0)), local1, global1));
+                    }
+                    // Errors propagate unchanged
+                    halted => results.push((halted, local1, global1)),
+                }
+            }
+        }
+        Expr::Send(name, channel, value) => {
+            for (expr_result, local1, global1) in run_expr(exprhc, budget, value, local, global) {
+                match expr_result {
+                    ExprResult::Yielding(e) => {
+                        results.push((
+                            ExprResult::Yielding(exprhc.send(name.clone(), *channel, e)),
+                            local1,
+                            global1,
+                        ));
+                    }
+                    ExprResult::Returning(Value::Number(n)) if n < channel.lo || n > channel.hi => {
+                        let message = format!("send({}, {}) outside {}..{}", name, n, channel.lo, channel.hi);
+                        results.push((ExprResult::Failing(message), local1, global1));
+                    }
+                    ExprResult::Returning(Value::Number(n)) => {
+                        let mut queue = messages(&global1, name);
+                        if queue.len() as i64 >= channel.capacity {
+                            // Blocked: the request stays here until a message is received
+                            let value = exprhc.number(n);
+                            results.push((
+                                ExprResult::Yielding(exprhc.send(name.clone(), *channel, value)),
+                                local1,
+                                global1,
+                            ));
+                            continue;
+                        }
+                        let len = queue.len();
+                        queue.push(n);
+                        if channel.bag {
+                            queue.sort();
+                        }
+                        results.push((
+                            ExprResult::Returning(Value::Number(0)),
+                            local1,
+                            set_messages(global1, name, len, &queue),
+                        ));
+                    }
+                    // Errors propagate unchanged
+                    halted => results.push((halted, local1, global1)),
+                }
+            }
+        }
+        Expr::Recv(name, channel) => {
+            let queue = messages(&global, name);
+            if queue.is_empty() {
+                // Blocked: the request stays here until a message is sent
+                results.push((ExprResult::Yielding(exprhc.recv(name.clone(), *channel)), local, global));
+            } else if channel.bag {
+                // Any of the messages may come first
+                let mut values = queue.clone();
+                values.dedup();
+                for value in values {
+                    let mut rest = queue.clone();
+                    let at = rest.iter().position(|m| *m == value).unwrap();
+                    rest.remove(at);
+                    let global = set_messages(global.clone(), name, queue.len(), &rest);
+                    results.push((ExprResult::Returning(Value::Number(value)), local.clone(), global));
+                }
+            } else {
+                let global = set_messages(global, name, queue.len(), &queue[1..]);
+                results.push((ExprResult::Returning(Value::Number(queue[0])), local, global));
+            }
+        }
+        Expr::Load(array, indices, index) => {
+            for (expr_result, local1, global1) in run_expr(exprhc, budget, index, local, global) {
+                match expr_result {
+                    ExprResult::Yielding(e) => {
+                        results.push((
+                            ExprResult::Yielding(exprhc.load(array.clone(), *indices, e)),
+                            local1,
+                            global1,
+                        ));
+                    }
+                    ExprResult::Returning(Value::Number(i)) if !indices.contains(i) => {
+                        let message = format!("{}[{}] out of range", array, i);
+                        results.push((ExprResult::Failing(message), local1, global1));
+                    }
+                    ExprResult::Returning(Value::Number(i)) => {
+                        let value = global1.get(&element(array, i));
+                        results.push((ExprResult::Returning(Value::Number(value)), local1, global1));
+                    }
+                    // Errors propagate unchanged
+                    halted => results.push((halted, local1, global1)),
+                }
+            }
+        }
+        Expr::Store(array, indices, index, value) => {
+            for (expr_result1, local1, global1) in run_expr(exprhc, budget, index, local, global) {
+                match expr_result1 {
+                    ExprResult::Yielding(e) => {
+                        results.push((
+                            ExprResult::Yielding(exprhc.store(
+                                array.clone(),
+                                *indices,
+                                e,
+                                value.clone(),
+                            )),
+                            local1,
+                            global1,
+                        ));
+                    }
+                    ExprResult::Returning(Value::Number(i)) if !indices.contains(i) => {
+                        let message = format!("{}[{}] out of range", array, i);
+                        results.push((ExprResult::Failing(message), local1, global1));
+                    }
+                    ExprResult::Returning(Value::Number(i)) => {
+                        for (expr_result2, local2, global2) in run_expr(exprhc, budget, value, local1, global1) {
+                            match expr_result2 {
+                                ExprResult::Yielding(e) => {
+                                    let index = exprhc.number(i);
+                                    results.push((
+                                        ExprResult::Yielding(exprhc.store(
+                                            array.clone(),
+                                            *indices,
+                                            index,
+                                            e,
+                                        )),
+                                        local2,
+                                        global2,
+                                    ));
+                                }
+                                ExprResult::Returning(Value::Number(n)) => {
+                                    let var = element(array, i);
+                                    results.push((
+                                        ExprResult::Returning(Value::Number(n)),
+                                        log_write(local2, &global2, &var),
+                                        global2.insert(var, n),
+                                    ));
+                                }
+                                // Errors propagate unchanged
+                                halted => results.push((halted, local2, global2)),
                             }
                         }
                     }
+                    // Errors propagate unchanged
+                    halted => results.push((halted, local1, global1)),
+                }
+            }
+        }
+        Expr::Bounded(var, domain, e) => {
+            for (expr_result, local1, global1) in run_expr(exprhc, budget, e, local, global) {
+                match expr_result {
+                    ExprResult::Yielding(e) => {
+                        results.push((
+                            ExprResult::Yielding(exprhc.bounded(var.clone(), *domain, e)),
+                            local1,
+                            global1,
+                        ));
+                    }
+                    ExprResult::Returning(Value::Number(n)) => match domain.apply(n) {
+                        Some(n) => results.push((ExprResult::Returning(Value::Number(n)), local1, global1)),
+                        None => results.push((
+                            ExprResult::Failing(format!("{} out of range", var)),
+                            local1,
+                            global1,
+                        )),
+                    },
+                    // Errors propagate unchanged
+                    halted => results.push((halted, local1, global1)),
+                }
+            }
+        }
+        Expr::Error(message) => {
+            // Only reached as the terminal state of a failed request
+            results.push((ExprResult::Failing(message.clone()), local, global));
+        }
+        Expr::Assume(cond) => {
+            for (expr_result, local1, global1) in run_expr(exprhc, budget, cond, local, global) {
+                match expr_result {
+                    ExprResult::Yielding(e) => {
+                        results.push((ExprResult::Yielding(exprhc.assume(e)), local1, global1));
+                    }
+                    // A false assumption leaves no execution to continue
+                    ExprResult::Returning(Value::Number(0)) => {}
+                    ExprResult::Returning(_) => {
+                        results.push((ExprResult::Returning(Value::Number(0)), local1, global1));
+                    }
+                    // Errors propagate unchanged
+                    halted => results.push((halted, local1, global1)),
+                }
+            }
+        }
+        Expr::Assert(message, cond) => {
+            for (expr_result, local1, global1) in run_expr(exprhc, budget, cond, local, global) {
+                match expr_result {
+                    ExprResult::Yielding(e) => {
+                        results.push((
+                            ExprResult::Yielding(exprhc.assert(message.clone(), e)),
+                            local1,
+                            global1,
+                        ));
+                    }
+                    ExprResult::Returning(Value::Number(0)) => {
+                        results.push((ExprResult::Violating(message.clone()), local1, global1));
+                    }
+                    ExprResult::Returning(_) => {
+                        results.push((ExprResult::Returning(Value::Number(0)), local1, global1));
+                    }
+                    // Errors propagate unchanged
+                    halted => results.push((halted, local1, global1)),
+                }
+            }
+        }
+        Expr::Violation(message) => {
+            // Only reached as the terminal state of a failed assertion
+            results.push((ExprResult::Violating(message.clone()), local, global));
+        }
+        Expr::Yield => {
+            // Yield the current state
+            results.push((ExprResult::Yielding(exprhc.number(0)), local, global));
+        }
+        Expr::Exit => {
+            // Abort the request; its writes so far stay visible to the others
+            results.push((ExprResult::Exiting, local, global));
+        }
+        Expr::Crashed => {
+            // A crashed request takes no more steps
+        }
+        Expr::Abort => {
+            // Undo the writes of the transaction; the request then stops
+            let (local, global) = roll_back(local, global);
+            results.push((ExprResult::Aborting, local, global));
+        }
+        Expr::Unknown => {
+            // Returns both 0 and 1
+            results.push((ExprResult::Returning(Value::Number(0)), local.clone(), global.clone()));
+            results.push((ExprResult::Returning(Value::Number(1)), local, global));
+        }
+        Expr::Choose(lo, hi) => {
+            // Every value in the range is a separate result
+            for n in *lo..=*hi {
+                results.push((ExprResult::Returning(Value::Number(n)), local.clone(), global.clone()));
+            }
+        }
+        Expr::Choice(first, second) => {
+            // The results of both branches, each from the current state
+            results.extend(run_expr(exprhc, budget, first, local.clone(), global.clone()));
+            results.extend(run_expr(exprhc, budget, second, local, global));
+        }
+        Expr::Typed(enum_name, constants, body) => {
+            // The response must be one of the constants
+            let in_range = |n: i64| 0 <= n && n < constants.len() as i64;
+            for (expr_result, local1, global1) in run_expr(exprhc, budget, body, local, global) {
+                match expr_result {
+                    ExprResult::Yielding(e) => {
+                        let e = exprhc.typed(enum_name.clone(), constants.clone(), e);
+                        results.push((ExprResult::Yielding(e), local1, global1));
+                    }
+                    ExprResult::Returning(Value::Number(n))
+                    | ExprResult::Jumping(Jump::Return(Value::Number(n)))
+                        if !in_range(n) =>
+                    {
+                        let message = format!("{} is not a {}", n, enum_name);
+                        results.push((ExprResult::Failing(message), local1, global1));
+                    }
+                    other => results.push((other, local1, global1)),
                 }
             }
         }
-        Expr::Yield => {
-            // Yield the current state
-            results.push((ExprResult::Yielding(exprhc.number(0)), local, global));
-        }
-        Expr::Exit => {
-            // Exit the whole program (kill all threads / packets)
-            // Unimplemented (do we actually need this?)
-            panic!("Exit not implemented");
+        Expr::Symbol(_, n) => {
+            results.push((ExprResult::Returning(Value::Number(*n)), local, global));
         }
-        Expr::Unknown => {
-            // Returns both 0 and 1
-            results.push((ExprResult::Returning(0), local.clone(), global.clone()));
-            results.push((ExprResult::Returning(1), local, global));
+        Expr::Tuple(items) => {
+            // Evaluate the items left to right; after a yield the items
+            // evaluated so far stay in the tuple as numbers
+            let mut partial = vec![(Vec::new(), local, global)];
+            for (i, item) in items.iter().enumerate() {
+                let mut next = Vec::new();
+                for (values, local, global) in partial {
+                    for (expr_result, local1, global1) in run_expr(exprhc, budget, item, local, global) {
+                        match expr_result {
+                            ExprResult::Yielding(e) => {
+                                let mut rest: Vec<Hc<Expr>> =
+                                    values.iter().map(|&v| exprhc.number(v)).collect();
+                                rest.push(e);
+                                rest.extend(items[i + 1..].iter().cloned());
+                                results.push((ExprResult::Yielding(exprhc.tuple(rest)), local1, global1));
+                            }
+                            ExprResult::Returning(Value::Number(n)) => {
+                                let mut values = values.clone();
+                                values.push(n);
+                                next.push((values, local1, global1));
+                            }
+                            // Errors propagate unchanged
+                            halted => results.push((halted, local1, global1)),
+                        }
+                    }
+                }
+                partial = next;
+            }
+            for (values, local, global) in partial {
+                results.push((ExprResult::Returning(Value::Tuple(values)), local, global));
+            }
         }
         Expr::Number(n) => {
             // Return the number directly
-            results.push((ExprResult::Returning(*n), local, global));
+            results.push((ExprResult::Returning(Value::Number(*n)), local, global));
         }
         Expr::Variable(x) => {
             // Look up the variable in local or global environment
             if is_local(x) {
-                results.push((ExprResult::Returning(local.get(x)), local, global));
+                results.push((ExprResult::Returning(Value::Number(local.get(x))), local, global));
             } else {
-                results.push((ExprResult::Returning(global.get(x)), local, global));
+                results.push((ExprResult::Returning(Value::Number(global.get(x))), local, global));
             }
         }
         Expr::Not(e) => {
-            for (expr_result, local1, global1) in run_expr(exprhc, e, local, global) {
+            for (expr_result, local1, global1) in run_expr(exprhc, budget, e, local, global) {
                 match expr_result {
                     ExprResult::Yielding(e) => {
                         results.push((ExprResult::Yielding(exprhc.not(e)), local1, global1));
                     }
-                    ExprResult::Returning(n) => {
+                    ExprResult::Returning(Value::Number(n)) => {
                         let result = if n == 0 { 1 } else { 0 };
-                        results.push((ExprResult::Returning(result), local1, global1));
+                        results.push((ExprResult::Returning(Value::Number(result)), local1, global1));
                     }
+                    // Errors propagate unchanged
+                    halted => results.push((halted, local1, global1)),
                 }
             }
         }
         Expr::And(e1, e2) => {
-            for (expr_result1, local1, global1) in run_expr(exprhc, e1, local, global) {
+            for (expr_result1, local1, global1) in run_expr(exprhc, budget, e1, local, global) {
                 match expr_result1 {
                     ExprResult::Yielding(e) => {
                         results.push((
@@ -375,38 +1134,42 @@ pub fn run_expr(
                             global1,
                         ));
                     }
-                    ExprResult::Returning(n1) => {
+                    ExprResult::Returning(Value::Number(n1)) => {
                         if n1 == 0 {
                             // Short-circuit: If first operand is false, result is false
-                            results.push((ExprResult::Returning(0), local1, global1));
+                            results.push((ExprResult::Returning(Value::Number(0)), local1, global1));
                         } else {
                             // First operand is true, evaluate second operand
                             for (expr_result2, local2, global2) in
-                                run_expr(exprhc, e2, local1, global1)
+                                run_expr(exprhc, budget, e2, local1, global1)
                             {
                                 match expr_result2 {
                                     ExprResult::Yielding(e) => {
                                         // Second operand yielded
                                         results.push((ExprResult::Yielding(e), local2, global2));
                                     }
-                                    ExprResult::Returning(n2) => {
+                                    ExprResult::Returning(Value::Number(n2)) => {
                                         // Second operand returned, result is n2 != 0
                                         let result = if n2 == 0 { 0 } else { 1 };
                                         results.push((
-                                            ExprResult::Returning(result),
+                                            ExprResult::Returning(Value::Number(result)),
                                             local2,
                                             global2,
                                         ));
                                     }
+                                    // Errors propagate unchanged
+                                    halted => results.push((halted, local2, global2)),
                                 }
                             }
                         }
                     }
+                    // Errors propagate unchanged
+                    halted => results.push((halted, local1, global1)),
                 }
             }
         }
         Expr::Or(e1, e2) => {
-            for (expr_result1, local1, global1) in run_expr(exprhc, e1, local, global) {
+            for (expr_result1, local1, global1) in run_expr(exprhc, budget, e1, local, global) {
                 match expr_result1 {
                     ExprResult::Yielding(e) => {
                         results.push((
@@ -415,33 +1178,37 @@ pub fn run_expr(
                             global1,
                         ));
                     }
-                    ExprResult::Returning(n1) => {
+                    ExprResult::Returning(Value::Number(n1)) => {
                         if n1 != 0 {
                             // Short-circuit: If first operand is true, result is true
-                            results.push((ExprResult::Returning(1), local1, global1));
+                            results.push((ExprResult::Returning(Value::Number(1)), local1, global1));
                         } else {
                             // First operand is false, evaluate second operand
                             for (expr_result2, local2, global2) in
-                                run_expr(exprhc, e2, local1, global1)
+                                run_expr(exprhc, budget, e2, local1, global1)
                             {
                                 match expr_result2 {
                                     ExprResult::Yielding(e) => {
                                         // Second operand yielded
                                         results.push((ExprResult::Yielding(e), local2, global2));
                                     }
-                                    ExprResult::Returning(n2) => {
+                                    ExprResult::Returning(Value::Number(n2)) => {
                                         // Second operand returned, result is n2 != 0
                                         let result = if n2 == 0 { 0 } else { 1 };
                                         results.push((
-                                            ExprResult::Returning(result),
+                                            ExprResult::Returning(Value::Number(result)),
                                             local2,
                                             global2,
                                         ));
                                     }
+                                    // Errors propagate unchanged
+                                    halted => results.push((halted, local2, global2)),
                                 }
                             }
                         }
                     }
+                    // Errors propagate unchanged
+                    halted => results.push((halted, local1, global1)),
                 }
             }
         }
@@ -449,15 +1216,27 @@ pub fn run_expr(
     results
 }
 
-// Request type that holds the request name
+// Request type that holds the request name and the arguments it was instantiated with
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, serde::Serialize, serde::Deserialize)]
 pub struct ExprRequest {
     pub name: String,
+    #[serde(default)]
+    pub args: Vec<i64>,
+    /// Session that issues the request and its position in that session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<(usize, usize)>,
 }
 
 impl std::fmt::Display for ExprRequest {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.name)
+        if let Some((session, position)) = self.session {
+            write!(f, "s{}.{}:", session + 1, position + 1)?;
+        }
+        if self.args.is_empty() {
+            return write!(f, "{}", self.name);
+        }
+        let args: Vec<String> = self.args.iter().map(|arg| arg.to_string()).collect();
+        write!(f, "{}({})", self.name, args.join(", "))
     }
 }
 
@@ -471,44 +1250,145 @@ impl std::fmt::Display for LocalExpr {
     }
 }
 
-// Function to convert a program with multiple requests to a network system
-pub fn program_to_ns(
+/// Every combination of the initial values given with `init`; globals without one start at 0
+fn initial_globals(program: &Program, names: &Arc<EnumNames>) -> Vec<Global> {
+    let mut globals = vec![Global::with_names(names)];
+    for init in &program.inits {
+        let var = match init.index {
+            Some(index) => element(&init.name, index),
+            None => init.name.clone(),
+        };
+        globals = globals
+            .iter()
+            .flat_map(|g| init.values.iter().map(|v| g.clone().insert(var.clone(), *v)))
+            .collect();
+    }
+    globals
+}
+
+/// Whether the next step of `expr` is an `await`, `lock`, `acquire`, `send` or `recv`.
+/// A step from there that changes nothing is a blocked request, not a spin through `yield`.
+fn waits_first(expr: &Expr) -> bool {
+    match expr {
+        // An atomic region only yields when a lock inside it is taken
+        Expr::Await(_) | Expr::Lock(_) | Expr::Acquire(_, _) | Expr::Recv(_, _) | Expr::Atomic(_) => true,
+        Expr::Send(_, _, value) => matches!(value.get(), Expr::Number(_)) || waits_first(value),
+        Expr::Sequence(first, _)
+        | Expr::Assign(_, first)
+        | Expr::If(first, _, _)
+        | Expr::While(first, _)
+        | Expr::Iterate(first, _, _)
+        | Expr::Return(first)
+        | Expr::Transaction(first)
+        | Expr::Not(first)
+        | Expr::And(first, _)
+        | Expr::Or(first, _)
+        | Expr::Load(_, _, first)
+        | Expr::Bounded(_, _, first)
+        | Expr::Assume(first)
+        | Expr::Assert(_, first)
+        | Expr::Typed(_, _, first) => waits_first(first),
+        Expr::Equal(e1, e2)
+        | Expr::NotEqual(e1, e2)
+        | Expr::Less(e1, e2)
+        | Expr::LessEqual(e1, e2)
+        | Expr::Greater(e1, e2)
+        | Expr::GreaterEqual(e1, e2)
+        | Expr::Add(e1, e2)
+        | Expr::Subtract(e1, e2)
+        | Expr::Multiply(e1, e2)
+        | Expr::Divide(e1, e2)
+        | Expr::Modulo(e1, e2)
+        | Expr::Store(_, _, e1, e2) => {
+            // The first operand is a number once it was evaluated
+            if matches!(e1.get(), Expr::Number(_)) { waits_first(e2) } else { waits_first(e1) }
+        }
+        Expr::Tuple(items) => items
+            .iter()
+            .find(|item| !matches!(item.get(), Expr::Number(_)))
+            .is_some_and(|item| waits_first(item)),
+        _ => false,
+    }
+}
+
+// Function to convert a program with multiple requests to a network system.
+// A loop that does not finish within the loop budget is reported with its source
+// location instead of making the conversion hang. Besides the network system, it
+// returns the number of self-loops of blocked requests that were left out.
+pub fn try_program_to_ns(
     exprhc: &mut ExprHc,
     program: &Program,
-) -> NS<Global, LocalExpr, ExprRequest, i64> {
-    let mut ns = NS::new(Global::new());
+    options: &ConvertOptions,
+) -> Result<(ProgramNS, usize), String> {
+    let constants = |enum_name: &str| -> Vec<String> {
+        let decl = program.enums.iter().find(|decl| decl.name == enum_name);
+        decl.map(|decl| decl.constants.clone()).unwrap_or_default()
+    };
+    // Every state of the program shares the names of its enum constants
+    let names: Arc<EnumNames> = Arc::new(
+        program
+            .declarations
+            .iter()
+            .filter_map(|decl| Some((decl.name.clone(), constants(decl.enum_type.as_ref()?))))
+            .collect(),
+    );
+
+    let initial_globals = initial_globals(program, &names);
+    let mut ns = NS::new(initial_globals[0].clone());
+    for initial_global in &initial_globals[1..] {
+        ns.add_initial_global(initial_global.clone());
+    }
 
     // Track seen states to avoid duplication and infinite loops
     let mut seen_packets: HashSet<LocalExpr> = HashSet::default();
-    let mut seen_globals: HashSet<Global> = HashSet::default();
+    let mut seen_globals: HashSet<Global> = initial_globals.iter().cloned().collect();
     let mut todo = vec![];
+    // Self-loops of blocked requests that were left out
+    let mut skipped_spins = 0;
 
-    // Process each request in the program
+    // Process each instance of each request in the program
     for request in &program.requests {
         let request_name = &request.name;
-        let expr = &request.body;
-
-        // Starting state - add a request that transitions to initial state
-        let initial_local = Local::new();
-        let initial_expr = expr.clone();
-        let initial_global = Global::new();
-        let initial_local_expr = LocalExpr(initial_local.clone(), initial_expr.clone());
-
-        todo.push((
-            initial_expr.clone(),
-            initial_local.clone(),
-            initial_global.clone(),
-        ));
-
-        // Add initial request with the specified name
-        ns.add_request(
-            ExprRequest {
-                name: request_name.to_string(),
-            },
-            initial_local_expr.clone(),
-        );
-        seen_globals.insert(initial_global.clone());
-        seen_packets.insert(initial_local_expr.clone());
+        // A request with an enum response type responds with the names of its constants
+        let expr = &match &request.returns {
+            Some(enum_name) => {
+                exprhc.typed(enum_name.clone(), constants(enum_name), request.body.clone())
+            }
+            None => request.body.clone(),
+        };
+
+        for args in request.instances() {
+            // Starting state - the parameters are preloaded as locals
+            let mut initial_local = Local::with_names(&names);
+            for (param, arg) in request.params.iter().zip(&args) {
+                initial_local = initial_local.insert(param.name.clone(), *arg);
+            }
+            if request.crashable {
+                initial_local = initial_local.insert(CRASHABLE.to_string(), 1);
+            }
+            let initial_expr = expr.clone();
+            let initial_local_expr = LocalExpr(initial_local.clone(), initial_expr.clone());
+
+            if seen_packets.insert(initial_local_expr.clone()) {
+                for initial_global in &initial_globals {
+                    todo.push((
+                        initial_expr.clone(),
+                        initial_local.clone(),
+                        initial_global.clone(),
+                    ));
+                }
+            }
+
+            // Add a request that transitions to the initial state of this instance
+            ns.add_request(
+                ExprRequest {
+                    name: request_name.to_string(),
+                    args,
+                    session: None,
+                },
+                initial_local_expr,
+            );
+        }
     }
 
     // Process states
@@ -518,18 +1398,62 @@ pub fn program_to_ns(
         match expr.get() {
             Expr::Number(n) => {
                 // Add a response for this local state
-                ns.add_response(local_expr.clone(), *n);
+                ns.add_response(local_expr.clone(), Response::Value(*n));
+            }
+            Expr::Symbol(name, _) => {
+                // The request finished with an enum constant
+                ns.add_response(local_expr.clone(), Response::Symbol(name.clone()));
+            }
+            Expr::Tuple(items) if items.iter().all(|item| matches!(item.get(), Expr::Number(_))) => {
+                // The request finished with a tuple of values
+                let values = items
+                    .iter()
+                    .filter_map(|item| match item.get() {
+                        Expr::Number(n) => Some(*n),
+                        _ => None,
+                    })
+                    .collect();
+                ns.add_response(local_expr.clone(), Response::Tuple(values));
+            }
+            Expr::Error(message) => {
+                // The request failed; it responds with the error
+                ns.add_response(local_expr.clone(), Response::Error(message.clone()));
+            }
+            Expr::Exit => {
+                // The request was aborted; it responds with `exit`
+                ns.add_response(local_expr.clone(), Response::Exit);
+            }
+            Expr::Abort => {
+                // A transaction was aborted and its writes undone; it responds with `abort`
+                ns.add_response(local_expr.clone(), Response::Aborted);
+            }
+            Expr::Violation(message) => {
+                // The assertion failed; the analysis looks for this response first
+                let response = Response::AssertionFailed(message.clone());
+                ns.add_violation(response.clone());
+                ns.add_response(local_expr.clone(), response);
             }
             _ => {
                 // Get all possible results of executing this expression
-                let results = run_expr(exprhc, &expr, local.clone(), global.clone());
+                let results = run_expr(exprhc, options.loop_budget, &expr, local.clone(), global.clone());
 
                 let mut new_globals = vec![];
                 let mut new_packets = vec![];
 
                 for (result, new_local, new_global) in results {
-                    match result {
+                    // A `return` finishes the request like running off its end
+                    let result = match result {
+                        ExprResult::Jumping(Jump::Return(value)) => ExprResult::Returning(value),
+                        other => other,
+                    };
+                    match check_released(result, &new_local) {
                         ExprResult::Yielding(e) => {
+                            if e == expr && new_local == local && new_global == global && waits_first(&expr) {
+                                // A blocked request stays where it is; the self-loop
+                                // would only add a spin transition
+                                skipped_spins += 1;
+                                continue;
+                            }
                             // Create a new expression to continue with
                             let new_local_expr = LocalExpr(new_local.clone(), e.clone());
 
@@ -544,10 +1468,20 @@ pub fn program_to_ns(
                             new_globals.push(new_global.clone());
                             new_packets.push(new_local_expr.clone());
                         }
-                        ExprResult::Returning(n) => {
+                        ExprResult::Returning(value) => {
                             // Add new global state to track if it's new
                             new_globals.push(new_global.clone());
-                            let new_local_expr = LocalExpr(new_local.clone(), exprhc.number(n));
+                            let value = match (value, expr.get()) {
+                                (Value::Number(n), Expr::Typed(_, constants, _)) => {
+                                    exprhc.symbol(constants[n as usize].clone(), n)
+                                }
+                                (Value::Number(n), _) => exprhc.number(n),
+                                (Value::Tuple(values), _) => {
+                                    let items = values.into_iter().map(|v| exprhc.number(v)).collect();
+                                    exprhc.tuple(items)
+                                }
+                            };
+                            let new_local_expr = LocalExpr(new_local.clone(), value);
                             // Add a transition from (local_expr, global) to (new_local_expr, new_global)
                             ns.add_transition(
                                 local_expr.clone(),
@@ -557,6 +1491,78 @@ pub fn program_to_ns(
                             );
                             new_packets.push(new_local_expr.clone());
                         }
+                        ExprResult::Failing(message) => {
+                            // Move to a terminal error state that responds with the error
+                            new_globals.push(new_global.clone());
+                            let new_local_expr = LocalExpr(new_local.clone(), exprhc.error(message));
+                            ns.add_transition(
+                                local_expr.clone(),
+                                global.clone(),
+                                new_local_expr.clone(),
+                                new_global.clone(),
+                            );
+                            new_packets.push(new_local_expr.clone());
+                        }
+                        ExprResult::Exiting => {
+                            // Move to a terminal state that responds with `exit`
+                            new_globals.push(new_global.clone());
+                            let new_local_expr = LocalExpr(new_local.clone(), exprhc.exit());
+                            ns.add_transition(
+                                local_expr.clone(),
+                                global.clone(),
+                                new_local_expr.clone(),
+                                new_global.clone(),
+                            );
+                            new_packets.push(new_local_expr.clone());
+                        }
+                        ExprResult::Aborting => {
+                            // Move to a terminal state that responds with `abort`
+                            new_globals.push(new_global.clone());
+                            let new_local_expr = LocalExpr(new_local.clone(), exprhc.abort());
+                            ns.add_transition(
+                                local_expr.clone(),
+                                global.clone(),
+                                new_local_expr.clone(),
+                                new_global.clone(),
+                            );
+                            new_packets.push(new_local_expr.clone());
+                        }
+                        ExprResult::Violating(message) => {
+                            // Move to a terminal state that reports the failed assertion
+                            new_globals.push(new_global.clone());
+                            let new_local_expr =
+                                LocalExpr(new_local.clone(), exprhc.violation(message));
+                            ns.add_transition(
+                                local_expr.clone(),
+                                global.clone(),
+                                new_local_expr.clone(),
+                                new_global.clone(),
+                            );
+                            new_packets.push(new_local_expr.clone());
+                        }
+                        ExprResult::Jumping(jump) => {
+                            unreachable!("the parser only allows {:?} inside a loop", jump)
+                        }
+                        ExprResult::Diverging(loop_expr) => {
+                            let location = program
+                                .loops
+                                .iter()
+                                .filter(|(other, _)| *other == loop_expr)
+                                .map(|(_, location)| location.as_str())
+                                .collect::<Vec<_>>()
+                                .join(" or ");
+                            let cond = match loop_expr.get() {
+                                Expr::While(cond, _) => cond.to_string(),
+                                other => other.to_string(),
+                            };
+                            return Err(format!(
+                                "The loop while({}){{...}} at {} went through {} states without yielding, \
+                                 so it probably never ends (raise the limit with --loop-budget if it does)",
+                                cond,
+                                if location.is_empty() { "an unknown location" } else { &location },
+                                options.loop_budget
+                            ));
+                        }
                     }
                 }
                 for new_global in new_globals {
@@ -580,12 +1586,92 @@ pub fn program_to_ns(
         }
     }
 
-    ns
+    add_crashes(exprhc, &mut ns, &seen_packets, &seen_globals);
+    add_sessions(&mut ns, &options.sessions)?;
+    Ok((ns, skipped_spins))
+}
+
+/// Replace the requests by those of the given sessions, named like `inc` or `put(1, 2)`.
+/// Each request of a session becomes a request of its own, which the session issues
+/// after the previous one responded.
+fn add_sessions(ns: &mut ProgramNS, sessions: &[Vec<String>]) -> Result<(), String> {
+    if sessions.is_empty() {
+        return Ok(());
+    }
+    let without_spaces = |name: &str| name.split_whitespace().collect::<String>();
+    let requests = std::mem::take(&mut ns.requests);
+    for (session, names) in sessions.iter().enumerate() {
+        let mut issued = Vec::new();
+        for (position, name) in names.iter().enumerate() {
+            let instances: Vec<&(ExprRequest, LocalExpr)> = requests
+                .iter()
+                .filter(|(req, _)| without_spaces(&req.to_string()) == without_spaces(name))
+                .collect();
+            let Some((req, _)) = instances.first() else {
+                let mut known: Vec<String> = requests.iter().map(|(req, _)| req.to_string()).collect();
+                known.sort();
+                known.dedup();
+                return Err(format!(
+                    "Session {} issues unknown request '{}'; the requests are {}",
+                    session + 1,
+                    name,
+                    known.join(", ")
+                ));
+            };
+            let tagged = ExprRequest {
+                session: Some((session, position)),
+                ..req.clone()
+            };
+            for (_, local) in instances {
+                ns.add_request(tagged.clone(), local.clone());
+            }
+            issued.push(tagged);
+        }
+        ns.add_session(issued);
+    }
+    Ok(())
+}
+
+/// Let every crashable request crash at each point between two of its atomic steps:
+/// it moves to the crashed state without changing the globals, and that state
+/// responds with `crashed`.
+// Local states are keyed by their hash-consed expression, which is never mutated
+#[allow(clippy::mutable_key_type)]
+fn add_crashes(
+    exprhc: &mut ExprHc,
+    ns: &mut ProgramNS,
+    packets: &HashSet<LocalExpr>,
+    globals: &HashSet<Global>,
+) {
+    // The local states of the crashable requests that have not finished yet
+    let finished: HashSet<&LocalExpr> = ns.responses.iter().map(|(local, _)| local).collect();
+    let mut crash_points: Vec<LocalExpr> = packets
+        .iter()
+        .filter(|packet| packet.0.get(CRASHABLE) != 0 && !finished.contains(packet))
+        .cloned()
+        .collect();
+    if crash_points.is_empty() {
+        return;
+    }
+    crash_points.sort();
+
+    let crashed = LocalExpr(Local::new(), exprhc.crashed());
+    for local in crash_points {
+        for global in globals {
+            ns.add_transition(local.clone(), global.clone(), crashed.clone(), global.clone());
+        }
+    }
+    ns.add_response(crashed, Response::Crashed);
+    ns.add_crash(Response::Crashed);
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    fn program_to_ns(exprhc: &mut ExprHc, program: &Program) -> ProgramNS {
+        try_program_to_ns(exprhc, program, &ConvertOptions::default()).unwrap().0
+    }
     
     #[test]
     fn test_env_serialization() {
@@ -612,6 +1698,587 @@ mod tests {
         assert_eq!(env2.get("nonexistent"), 0); // Default value
     }
     
+    #[test]
+    fn test_run_expr_arithmetic_and_comparisons() {
+        let mut table = ExprHc::new();
+        let expr = parse("x := 0 - 7; X := x % 3; y := x / 0; x * 2 <= X", &mut table).unwrap();
+        let budget = ConvertOptions::default().loop_budget;
+        let results = run_expr(&mut table, budget, &expr, Env::new(), Env::new());
+        assert_eq!(results.len(), 1);
+        let (result, local, global) = &results[0];
+        assert_eq!(*result, ExprResult::Returning(Value::Number(1)));
+        assert_eq!(global.get("X"), 2);
+        assert_eq!(local.get("y"), 0);
+    }
+
+    #[test]
+    fn test_run_expr_arithmetic_wraps_on_overflow() {
+        let mut table = ExprHc::new();
+        let expr = parse("x := 4294967296; x := x * x; X := 4611686018427387904 * 2; x", &mut table).unwrap();
+        let budget = ConvertOptions::default().loop_budget;
+        let results = run_expr(&mut table, budget, &expr, Env::new(), Env::new());
+        assert_eq!(results.len(), 1);
+        let (result, _, global) = &results[0];
+        assert_eq!(*result, ExprResult::Returning(Value::Number(0)));
+        assert_eq!(global.get("X"), i64::MIN);
+
+        // Sums and differences wrap too, also when the parser folds constants
+        let expr = parse("x := 4611686018427387904 * 2; y := x - 1; 9223372036854775807 + 1", &mut table).unwrap();
+        let results = run_expr(&mut table, budget, &expr, Env::new(), Env::new());
+        let (result, local, _) = &results[0];
+        assert_eq!(*result, ExprResult::Returning(Value::Number(i64::MIN)));
+        assert_eq!(local.get("y"), i64::MAX);
+    }
+
+    #[test]
+    fn test_wrapping_counter_has_finite_state_space() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "global X : 0..2 wrap; request incr { X := X + 1; yield; X }",
+            &mut table,
+        )
+        .unwrap();
+        let ns = program_to_ns(&mut table, &program);
+        let mut values: Vec<i64> = ns.get_global_states().iter().map(|g| g.get("X")).collect();
+        values.sort();
+        assert_eq!(values, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_out_of_range_write_responds_with_error() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "local y : 0..1 error; request foo { y := 1; y := y + 1; y }",
+            &mut table,
+        )
+        .unwrap();
+        let ns = program_to_ns(&mut table, &program);
+        let responses: Vec<_> = ns.responses.iter().map(|(_, resp)| resp.clone()).collect();
+        assert_eq!(responses, vec![Response::Error("y out of range".to_string())]);
+    }
+
+    #[test]
+    fn test_atomic_region_is_one_transition() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "request foo { atomic { X := 1; X := X + 1 }; yield; X := 0; 0 }",
+            &mut table,
+        )
+        .unwrap();
+        let ns = program_to_ns(&mut table, &program);
+        // The intermediate value X = 1 is never visible to other requests
+        let mut values: Vec<i64> = ns.get_global_states().iter().map(|g| g.get("X")).collect();
+        values.sort();
+        assert_eq!(values, vec![0, 2]);
+        assert_eq!(ns.transitions.len(), 2 * 2);
+    }
+
+    #[test]
+    fn test_blocked_lock_has_no_self_loop() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "request foo { lock(L); X := 1; yield; X := 0; unlock(L); 0 }",
+            &mut table,
+        )
+        .unwrap();
+        let ns = program_to_ns(&mut table, &program);
+        for (from_local, from_global, to_local, to_global) in &ns.transitions {
+            assert!(from_local != to_local || from_global != to_global);
+        }
+        // The lock protects X, so X = 1 is only seen while L is held
+        for global in ns.get_global_states() {
+            assert!(global.get("X") <= global.get("L"));
+        }
+        assert_eq!(ns.responses.len(), 1);
+    }
+
+    #[test]
+    fn test_lock_misuse_is_reported() {
+        let cases = [
+            ("request foo { unlock(L); 0 }", "unlock(L) without holding L"),
+            ("request foo { lock(L); lock(L); 0 }", "lock(L) while already holding L"),
+            ("request foo { lock(L); 0 }", "finished while holding L"),
+            ("global S : 0..1; request foo { release(S); 0 }", "release(S) without holding a permit of S"),
+        ];
+        for (source, message) in cases {
+            let mut table = ExprHc::new();
+            let program = parse_program(source, &mut table).unwrap();
+            let ns = program_to_ns(&mut table, &program);
+            let responses: Vec<_> = ns.responses.iter().map(|(_, resp)| resp.clone()).collect();
+            assert!(responses.contains(&Response::Error(message.to_string())), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_semaphore_capacity() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "global S : 0..2; request foo { acquire(S); yield; release(S); 0 }",
+            &mut table,
+        )
+        .unwrap();
+        let ns = program_to_ns(&mut table, &program);
+        let mut in_use: Vec<i64> = ns.get_global_states().iter().map(|g| g.get("S")).collect();
+        in_use.sort();
+        assert_eq!(in_use, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_sparse_array_store() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "global A[0..3] : 0..1; request put(k in 0..3) { A[k] := 1; yield; A[k] := 0; k }",
+            &mut table,
+        )
+        .unwrap();
+        let ns = program_to_ns(&mut table, &program);
+        let globals = ns.get_global_states();
+        // Every subset of the 4 elements can be set at the same time
+        assert_eq!(globals.len(), 16);
+        // Elements that are 0 take no space
+        assert!(globals.contains(&&Global::new()));
+        let one_set = Global::new().insert("A[3]".to_string(), 1);
+        assert_eq!(one_set.to_string(), "{A[3]=1}");
+        assert!(globals.contains(&&one_set));
+    }
+
+    #[test]
+    fn test_every_initial_global_is_explored() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "global A[0..1] : 0..3; init { STOP := 1; A[1] := 3 } init X in {0, 2} request get { X + A[1] }",
+            &mut table,
+        )
+        .unwrap();
+        let ns = program_to_ns(&mut table, &program);
+        let start = |x| {
+            Global::new()
+                .insert("STOP".to_string(), 1)
+                .insert("A[1]".to_string(), 3)
+                .insert("X".to_string(), x)
+        };
+        assert_eq!(ns.initial_globals, vec![start(0), start(2)]);
+        let responses: Vec<_> = ns.responses.iter().map(|(_, resp)| resp.clone()).collect();
+        assert!(responses.contains(&Response::Value(3)));
+        assert!(responses.contains(&Response::Value(5)));
+    }
+
+    #[test]
+    fn test_exit_aborts_request() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "request r(k in 0..1) { X := 1; if (k == 1) { exit } else { 0 }; X := 2; k }",
+            &mut table,
+        )
+        .unwrap();
+        let ns = program_to_ns(&mut table, &program);
+        let responses: Vec<_> = ns.responses.iter().map(|(_, resp)| resp.clone()).collect();
+        assert!(responses.contains(&Response::Exit));
+        assert!(responses.contains(&Response::Value(0)));
+        assert!(!responses.contains(&Response::Value(1)));
+        assert_eq!(Response::Exit.to_string(), "exit");
+
+        // The writes before the exit stay
+        let exited = Global::new().insert("X".to_string(), 1);
+        assert!(ns.transitions.iter().any(|(_, _, LocalExpr(_, e), g)| {
+            matches!(e.get(), Expr::Exit) && *g == exited
+        }));
+    }
+
+    #[test]
+    fn test_channels() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "channel Q[2] : 0..3; channel B[2] : 0..3 bag;
+             request put(v in 1..2) { send(Q, v); send(B, v) }
+             request take { (recv(Q), recv(B)) }",
+            &mut table,
+        )
+        .unwrap();
+        let ns = program_to_ns(&mut table, &program);
+
+        // Messages are stored in globals: the count under the channel's name, then the messages
+        let queue = |global: &Global| messages(global, "Q");
+        assert!(ns.get_global_states().iter().all(|g| queue(g).len() <= 2));
+        let both = ns.get_global_states().into_iter().find(|g| queue(g) == vec![2, 1]).unwrap();
+        assert_eq!(both.to_string(), "{B=2,B[0]=1,B[1]=2,Q=2,Q[0]=2,Q[1]=1}");
+
+        // A FIFO channel gives the oldest message, a bag any of them
+        let responses: Vec<_> = ns.responses.iter().map(|(_, resp)| resp.clone()).collect();
+        assert!(responses.contains(&Response::Tuple(vec![2, 1])));
+        assert!(responses.contains(&Response::Tuple(vec![2, 2])));
+        assert!(!responses.contains(&Response::Tuple(vec![1, 0])));
+
+        // A request waits at a full or empty channel
+        let blocked = |expr: &str| ns.get_local_states().iter().any(|LocalExpr(_, e)| e.to_string() == expr);
+        assert!(blocked("send(Q, 1); send(B, v)"));
+        assert!(blocked("(recv(Q), recv(B))"));
+    }
+
+    #[test]
+    fn test_transaction_abort_rolls_back() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "global A[0..1] : 0..1;
+             init { X := 1 }
+             request t { W := 1; transaction { X := 0; A[1] := 1; X := 0; yield; if (Y == 1) { abort }; 7 } }
+             request set { Y := 1 }",
+            &mut table,
+        )
+        .unwrap();
+        let ns = program_to_ns(&mut table, &program);
+        let responses: Vec<_> = ns.responses.iter().map(|(_, resp)| resp.clone()).collect();
+        assert!(responses.contains(&Response::Aborted));
+        assert!(responses.contains(&Response::Value(7)));
+        assert_eq!(Response::Aborted.to_string(), "abort");
+
+        // Inside the transaction the log holds the values from before its first writes
+        let logged = Local::new()
+            .insert("@transaction".to_string(), 1)
+            .insert("X@logged".to_string(), 1)
+            .insert("X@undo".to_string(), 1)
+            .insert("A[1]@logged".to_string(), 1);
+        assert_eq!(logged.to_string(), "{@transaction=1,A[1]@logged=1,X@logged=1,X@undo=1}");
+        assert!(ns.get_local_states().iter().any(|LocalExpr(local, _)| *local == logged));
+
+        // The abort restores the globals written in the transaction and nothing else
+        let aborts: Vec<_> = ns
+            .transitions
+            .iter()
+            .filter(|(LocalExpr(local, _), _, _, _)| *local == logged)
+            .filter(|(_, _, LocalExpr(_, e), _)| matches!(e.get(), Expr::Abort))
+            .collect();
+        assert!(!aborts.is_empty());
+        for (_, g, LocalExpr(local, _), g2) in aborts {
+            let restored = g.clone().insert("X".to_string(), 1).insert("A[1]".to_string(), 0);
+            assert_eq!(*g2, restored);
+            assert_eq!(*local, Local::new());
+        }
+        // A finished transaction leaves no log behind
+        assert!(ns
+            .responses
+            .iter()
+            .all(|(LocalExpr(local, _), _)| *local == Local::new()));
+    }
+
+    #[test]
+    fn test_assume_and_assert() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "request r { X := 1; yield; assert(X == 1); X := 0; 0 }
+             request s { assume(X == 1); 1 }",
+            &mut table,
+        )
+        .unwrap();
+        let ns = program_to_ns(&mut table, &program);
+
+        // The assertion fails only when another r resets X in between
+        let failed = Response::AssertionFailed("X == 1".to_string());
+        assert_eq!(ns.violations, vec![failed.clone()]);
+        assert!(ns.responses.iter().any(|(_, resp)| *resp == failed));
+        assert_eq!(failed.to_string(), "assertion failed(X == 1)");
+
+        // s only moves on in states where its assumption holds
+        let assumed = Global::new().insert("X".to_string(), 1);
+        let s_start = ns.requests.iter().find(|(req, _)| req.name == "s").unwrap().1.clone();
+        let s_steps: Vec<_> = ns.transitions.iter().filter(|(l, _, _, _)| *l == s_start).collect();
+        assert!(!s_steps.is_empty());
+        assert!(s_steps.iter().all(|(_, g, _, _)| *g == assumed));
+    }
+
+    #[test]
+    fn test_await_adds_only_enabling_transitions() {
+        let stopper = "request stop { STOP := 1; yield; STOP := 0; 0 }";
+        let mut table = ExprHc::new();
+        let waiting = format!("request w {{ X := 1; await(STOP == 0); X := 2; 0 }} {}", stopper);
+        let program = parse_program(&waiting, &mut table).unwrap();
+        let options = ConvertOptions::default();
+        let (ns, skipped) = try_program_to_ns(&mut table, &program, &options).unwrap();
+
+        // No spin self-loops, and the waiting request only moves on once STOP is 0
+        let self_loops =
+            |ns: &ProgramNS| ns.transitions.iter().filter(|(l1, g1, l2, g2)| l1 == l2 && g1 == g2).count();
+        assert_eq!(self_loops(&ns), 0);
+        let waits: Vec<_> = ns
+            .transitions
+            .iter()
+            .filter(|(LocalExpr(_, e), _, _, _)| {
+                matches!(e.get(), Expr::Sequence(first, _) if matches!(first.get(), Expr::Await(_)))
+            })
+            .collect();
+        assert!(!waits.is_empty());
+        assert!(waits.iter().all(|(_, g, _, _)| g.get("STOP") == 0));
+
+        // The spin loop it replaces keeps a self-loop for every state in which it waits,
+        // and those are the transitions that `await` saves
+        let spinning = format!("request w {{ X := 1; while(STOP == 1){{ yield }}; X := 2; 0 }} {}", stopper);
+        let program = parse_program(&spinning, &mut table).unwrap();
+        let (spin_ns, spin_skipped) = try_program_to_ns(&mut table, &program, &options).unwrap();
+        assert_eq!(spin_skipped, 0);
+        assert!(skipped > 0);
+        assert_eq!(self_loops(&spin_ns), skipped);
+    }
+
+    #[test]
+    fn test_enum_names_in_states_and_responses() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "enum Color { RED, GREEN }
+             global C : Color;
+             request paint : Color { C := GREEN; yield; C }
+             request bad : Color { 2 }",
+            &mut table,
+        )
+        .unwrap();
+        let ns = program_to_ns(&mut table, &program);
+
+        let responses: Vec<_> = ns.responses.iter().map(|(_, resp)| resp.clone()).collect();
+        assert!(responses.contains(&Response::Symbol("GREEN".to_string())));
+        assert!(responses.contains(&Response::Error("2 is not a Color".to_string())));
+        assert!(!responses.contains(&Response::Value(1)));
+
+        // States name the constants instead of their values
+        let green = Global::new().insert("C".to_string(), 1);
+        assert_eq!(green.to_string(), "{C=1}");
+        let states = ns.get_global_states();
+        let state = states.iter().find(|&&global| *global == green).unwrap();
+        assert_eq!(state.to_string(), "{C=GREEN}");
+        assert!(ns.to_graphviz().contains("C=GREEN"));
+
+        // The names belong to the program, so converting another one keeps them
+        let other = parse_program("global C : 0..1; request set { C := 1; 0 }", &mut table).unwrap();
+        let other_ns = program_to_ns(&mut table, &other);
+        assert!(other_ns.to_graphviz().contains("C=1"));
+        assert!(ns.to_graphviz().contains("C=GREEN"));
+    }
+
+    #[test]
+    fn test_tuple_responses() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "request pair { X := 1; yield; (X, X * 5) }
+             request early { if (X == 0) { return (0, 1) }; yield; (1, 0) }",
+            &mut table,
+        )
+        .unwrap();
+        let ns = program_to_ns(&mut table, &program);
+
+        let responses: Vec<_> = ns.responses.iter().map(|(_, resp)| resp.clone()).collect();
+        assert!(responses.contains(&Response::Tuple(vec![1, 5])));
+        assert!(responses.contains(&Response::Tuple(vec![0, 1])));
+        assert!(responses.contains(&Response::Tuple(vec![1, 0])));
+        assert_eq!(Response::Tuple(vec![1, 5]).to_string(), "(1, 5)");
+
+        // A yield inside a tuple keeps the items evaluated before it
+        let tuple = parse("(X, yield; X)", &mut table).unwrap();
+        let budget = ConvertOptions::default().loop_budget;
+        let results = run_expr(&mut table, budget, &tuple, Local::new(), Global::new().insert("X".to_string(), 3));
+        assert_eq!(results.len(), 1);
+        match &results[0].0 {
+            ExprResult::Yielding(rest) => assert_eq!(rest.to_string(), "(3, X)"),
+            other => panic!("expected a yield, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_jumps_after_yield() {
+        let mut table = ExprHc::new();
+        let cases = [
+            // break leaves the loop even when it comes after a yield
+            ("request r { while(1 == 1){ i := i + 1; yield; if(i == 2){ break } }; i }", vec![2]),
+            // continue skips the rest of the iteration
+            ("request r { while(i < 3){ i := i + 1; yield; if(i == 2){ continue }; s := s + i }; s }", vec![4]),
+            // return finishes the request from the middle
+            ("request r { if(?){ yield; return 7 }; 8 }", vec![7, 8]),
+        ];
+        for (source, expected) in cases {
+            let program = parse_program(source, &mut table).unwrap();
+            let ns = program_to_ns(&mut table, &program);
+            let mut responses: Vec<_> = ns.responses.iter().map(|(_, resp)| resp.clone()).collect();
+            responses.sort();
+            let expected: Vec<_> = expected.into_iter().map(Response::Value).collect();
+            assert_eq!(responses, expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_break_adds_no_states() {
+        let mut table = ExprHc::new();
+        let sugar = parse_program(
+            "request r { while(i < 5){ i := i + 1; yield; if(i == 2){ break } }; i }",
+            &mut table,
+        )
+        .unwrap();
+        let flag = parse_program(
+            "request r { while(i < 5 && done == 0){ i := i + 1; yield; if(i == 2){ done := 1 }else{0} }; i }",
+            &mut table,
+        )
+        .unwrap();
+        let sugar = program_to_ns(&mut table, &sugar);
+        let flag = program_to_ns(&mut table, &flag);
+        assert!(sugar.get_local_states().len() <= flag.get_local_states().len());
+        assert_eq!(sugar.responses.len(), 1);
+    }
+
+    #[test]
+    fn test_choose_expands_into_successors() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "request r { x := choose(1..3); yield; choose { x } or { x * 10 } }",
+            &mut table,
+        )
+        .unwrap();
+        let ns = program_to_ns(&mut table, &program);
+        let mut responses: Vec<_> = ns.responses.iter().map(|(_, resp)| resp.clone()).collect();
+        responses.sort();
+        let expected: Vec<_> = [1, 2, 3, 10, 20, 30].into_iter().map(Response::Value).collect();
+        assert_eq!(responses, expected);
+
+        // One state per chosen value after the yield, and nothing in between
+        let waiting = ns
+            .get_local_states()
+            .into_iter()
+            .filter(|LocalExpr(_, e)| !matches!(e.get(), Expr::Number(_)))
+            .count();
+        assert_eq!(waiting, 4);
+    }
+
+    #[test]
+    fn test_array_index_out_of_range() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "global A[0..1] : 0..1; request get(k in 0..2) { A[k] }",
+            &mut table,
+        )
+        .unwrap();
+        let ns = program_to_ns(&mut table, &program);
+        let responses: Vec<_> = ns.responses.iter().map(|(_, resp)| resp.clone()).collect();
+        assert!(responses.contains(&Response::Value(0)));
+        assert!(responses.contains(&Response::Error("A[2] out of range".to_string())));
+    }
+
+    #[test]
+    fn test_sessions() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "global X : 0..2; request put(v in 1..2) { X := v; 0 } request get { X }",
+            &mut table,
+        )
+        .unwrap();
+        let mut ns = program_to_ns(&mut table, &program);
+        let sessions = vec![
+            vec!["put(1)".to_string(), "get".to_string()],
+            vec!["put (2)".to_string()],
+        ];
+        add_sessions(&mut ns, &sessions).unwrap();
+
+        // Only the requests of the sessions are left, one for each position
+        let mut requests: Vec<String> = ns.get_requests().iter().map(|req| req.to_string()).collect();
+        requests.sort();
+        assert_eq!(requests, vec!["s1.1:put(1)", "s1.2:get", "s2.1:put(2)"]);
+        assert_eq!(ns.sessions.len(), 2);
+
+        // The get of the first session comes after its put, so it never reads 0
+        let serial = ns.serialized_automaton_regex().to_string();
+        assert!(serial.contains("s1.2:get/1") && serial.contains("s1.2:get/2"), "{}", serial);
+        assert!(!serial.contains("s1.2:get/0"), "{}", serial);
+
+        // A request with several initial states is listed once
+        let mut ns = program_to_ns(&mut table, &program);
+        let (put, local) = ns.requests[0].clone();
+        ns.add_request(put, LocalExpr(local.0.insert("z".to_string(), 1), local.1));
+        let err = add_sessions(&mut ns, &[vec!["put".to_string()]]).unwrap_err();
+        assert!(err.contains("unknown request 'put'"), "{}", err);
+        assert!(err.ends_with("the requests are get, put(1), put(2)"), "{}", err);
+    }
+
+    #[test]
+    fn test_crashable_request() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "crashable request move { X := 1; yield; Y := 1; 0 } request get { X + Y }",
+            &mut table,
+        )
+        .unwrap();
+        assert!(program.requests[0].crashable && !program.requests[1].crashable);
+        let ns = program_to_ns(&mut table, &program);
+        assert_eq!(ns.crashes, vec![Response::Crashed]);
+
+        // Only `move` crashes, at its start and at its yield, without changing the globals
+        let crashed: Vec<_> = ns
+            .transitions
+            .iter()
+            .filter(|(_, _, to, _)| ns.responses.contains(&(to.clone(), Response::Crashed)))
+            .collect();
+        assert!(crashed.iter().all(|(_, g, _, g2)| g == g2));
+        let crash_points: HashSet<_> = crashed.iter().map(|(from, _, _, _)| from.1.to_string()).collect();
+        let expected: HashSet<_> = ["X := 1; yield; Y := 1; 0", "Y := 1; 0"]
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(crash_points, expected);
+
+        // Serially, a crashed `move` either did nothing or moved both
+        let x = Global::new().insert("X".to_string(), 1);
+        let both = x.clone().insert("Y".to_string(), 1);
+        let crashes: Vec<_> = ns
+            .serialized_automaton()
+            .into_iter()
+            .filter(|(g, _, resp, _)| *resp == Response::Crashed && *g == Global::new())
+            .map(|(_, _, _, g2)| g2)
+            .collect();
+        assert!(crashes.contains(&Global::new()) && crashes.contains(&both));
+        assert!(!crashes.contains(&x));
+    }
+
+    #[test]
+    fn test_crash_does_not_spread_to_a_shared_state() {
+        let mut table = ExprHc::new();
+        // After its yield, `a` is where `b` starts, but only `a` may crash there
+        let program = parse_program(
+            "crashable request a { X := 1; yield; Y := 1 } request b { Y := 1 }",
+            &mut table,
+        )
+        .unwrap();
+        let ns = program_to_ns(&mut table, &program);
+        let crash_points: Vec<_> = ns
+            .transitions
+            .iter()
+            .filter(|(_, _, LocalExpr(_, to), _)| matches!(to.get(), Expr::Crashed))
+            .map(|(from, _, _, _)| from)
+            .collect();
+        let start = |name: &str| {
+            let (_, local) = ns.requests.iter().find(|(req, _)| req.name == name).unwrap();
+            local.clone()
+        };
+        assert!(crash_points.contains(&&start("a")));
+        assert!(!crash_points.contains(&&start("b")));
+        assert!(crash_points.iter().any(|from| from.1 == start("b").1));
+        assert!(crash_points.iter().all(|from| from.0.get(CRASHABLE) == 1));
+    }
+
+    #[test]
+    fn test_diverging_loop_is_reported() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "request ok { i := 0; while (i < 3) { i := i + 1 }; i }
+request spin {
+  i := 0;
+  while (1 == 1) { i := i + 1 }
+}",
+            &mut table,
+        )
+        .unwrap();
+        let options = ConvertOptions {
+            loop_budget: 50,
+            ..ConvertOptions::default()
+        };
+        let err = try_program_to_ns(&mut table, &program, &options).unwrap_err();
+        assert!(err.contains("while(1){...} at line 4, column 3"), "{}", err);
+        assert!(err.contains("went through 50 states"), "{}", err);
+        assert!(err.contains("--loop-budget"), "{}", err);
+    }
+
     #[test]
     fn test_empty_env_serialization() {
         let env = Env::new();
@@ -625,6 +2292,8 @@ mod tests {
         // Create a request
         let req = ExprRequest {
             name: "foo".to_string(),
+            args: vec![],
+            session: None,
         };
         
         // Serialize to JSON
@@ -639,11 +2308,55 @@ mod tests {
         assert_eq!(req2.name, "foo");
     }
     
+    #[test]
+    fn test_expr_request_with_args() {
+        let req = ExprRequest {
+            name: "transfer".to_string(),
+            args: vec![2, -1],
+            session: None,
+        };
+        assert_eq!(req.to_string(), "transfer(2, -1)");
+
+        let json = serde_json::to_string(&req).unwrap();
+        let req2: ExprRequest = serde_json::from_str(&json).unwrap();
+        assert_eq!(req, req2);
+
+        // Certificates written before requests had arguments still load
+        let old: ExprRequest = serde_json::from_str(r#"{"name":"foo"}"#).unwrap();
+        assert!(old.args.is_empty());
+        assert_eq!(old.to_string(), "foo");
+    }
+
+    #[test]
+    fn test_request_parameters_are_preloaded() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "request transfer(amount in 0..2) { X := amount; amount }",
+            &mut table,
+        )
+        .unwrap();
+        let ns = program_to_ns(&mut table, &program);
+        let mut requests: Vec<String> = ns.requests.iter().map(|(req, _)| req.to_string()).collect();
+        requests.sort();
+        assert_eq!(requests, vec!["transfer(0)", "transfer(1)", "transfer(2)"]);
+        for (req, LocalExpr(local, _)) in &ns.requests {
+            assert_eq!(local.get("amount"), req.args[0]);
+        }
+        let mut responses: Vec<_> = ns.responses.iter().map(|(_, resp)| resp.clone()).collect();
+        responses.sort_by_key(|resp| resp.to_string());
+        assert_eq!(
+            responses,
+            vec![Response::Value(0), Response::Value(1), Response::Value(2)]
+        );
+    }
+
     #[test]
     fn test_expr_request_special_chars() {
         // Test with special characters in name
         let req = ExprRequest {
             name: "request/with\\special\"chars".to_string(),
+            args: vec![],
+            session: None,
         };
         
         let json = serde_json::to_string(&req).unwrap();
@@ -0,0 +1,314 @@
+// Pretty-printer for .ser files (`ser fmt`).
+// It rearranges the tokens of the file instead of printing the parsed program, because
+// parsing inlines procedures, folds constants and drops comments. Only the whitespace
+// between tokens changes, so the output parses to the identical program.
+// Blocks are indented by two spaces, every statement of a block gets its own line,
+// comments stay where they are and a blank line between lines is kept (several become one).
+
+use crate::parser::{ParseError, Token, tokenize_with_comments, tokenize_with_spans};
+
+const INDENT: &str = "  ";
+
+/// Tokens that start a top-level item and therefore a new line
+fn starts_item(token: &Token) -> bool {
+    match token {
+        Token::Request => true,
+        Token::Identifier(name) => {
+            matches!(
+                name.as_str(),
+                "global" | "local" | "channel" | "fn" | "init" | "enum" | "import" | "crashable"
+            )
+        }
+        _ => false,
+    }
+}
+
+/// Whether a `-` after `prev` subtracts rather than negates
+fn is_binary_minus(prev: Option<&Token>) -> bool {
+    match prev {
+        Some(Token::Identifier(name)) => name != "in",
+        Some(Token::Number(_) | Token::RParen | Token::RBracket | Token::RBrace | Token::Question) => true,
+        _ => false,
+    }
+}
+
+/// Whether a space separates `prev` and `next` on a line
+fn space_between(prev: &Token, prev_is_unary: bool, next: &Token) -> bool {
+    if prev_is_unary {
+        return false;
+    }
+    match (prev, next) {
+        (_, Token::RParen | Token::RBracket | Token::Comma | Token::Semicolon | Token::DotDot) => false,
+        (Token::LParen | Token::LBracket | Token::Not | Token::DotDot, _) => false,
+        (Token::LBrace, Token::RBrace) => false,
+        // Calls, arrays and the primitives that take their operand in parentheses
+        (Token::Identifier(_), Token::LParen | Token::LBracket) => false,
+        (
+            Token::Assume
+            | Token::Assert
+            | Token::Lock
+            | Token::Unlock
+            | Token::Acquire
+            | Token::Release
+            | Token::Await
+            | Token::Send
+            | Token::Recv
+            | Token::Choose,
+            Token::LParen,
+        ) => false,
+        _ => true,
+    }
+}
+
+/// Brace that is open while printing
+struct Brace {
+    /// A block of statements, as opposed to a list like `{ 0, 1 }`
+    block: bool,
+    /// Parentheses and brackets that were open outside the brace
+    outer_parens: usize,
+}
+
+#[derive(Default)]
+struct Printer {
+    out: String,
+    indent: usize,
+    braces: Vec<Brace>,
+    /// Parentheses and brackets open inside the innermost brace
+    parens: usize,
+}
+
+impl Printer {
+    fn at_line_start(&self) -> bool {
+        self.out.is_empty() || self.out.ends_with('\n')
+    }
+
+    fn newline(&mut self) {
+        let trimmed = self.out.trim_end_matches(' ').len();
+        self.out.truncate(trimmed);
+        if !self.at_line_start() {
+            self.out.push('\n');
+        }
+    }
+
+    fn blank_line(&mut self) {
+        self.newline();
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+
+    fn write_indent(&mut self) {
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+    }
+}
+
+/// Format the source of a .ser file. The tokens of the output are checked to be
+/// those of the input, so parsing the output gives the same program.
+pub fn format_source(source: &str) -> Result<String, ParseError> {
+    let tokens = tokenize_with_comments(source)?;
+    let mut printer = Printer::default();
+    // Source line of the last token printed, comments included
+    let mut last_line: Option<usize> = None;
+    let mut last_was_open_brace = false;
+    // The last two tokens printed that are not comments, the latest first
+    let mut last_code: Option<&Token> = None;
+    let mut before_last_code: Option<&Token> = None;
+    let mut last_is_unary = false;
+
+    for (index, (token, span)) in tokens.iter().enumerate() {
+        let same_line = last_line == Some(span.line);
+        let gap = last_line.is_some_and(|line| span.line > line + 1);
+        match token {
+            Token::Eof => break,
+            Token::Comment(text) => {
+                if same_line {
+                    // A comment after code stays at the end of its line
+                    printer.newline();
+                    printer.out.pop();
+                    printer.out.push(' ');
+                } else {
+                    printer.newline();
+                    if gap && !last_was_open_brace {
+                        printer.blank_line();
+                    }
+                    printer.write_indent();
+                }
+                printer.out.push_str(text);
+                printer.newline();
+            }
+            _ => {
+                let closes_block = *token == Token::RBrace && printer.braces.last().is_some_and(|b| b.block);
+                if closes_block {
+                    printer.indent = printer.indent.saturating_sub(1);
+                    if last_code != Some(&Token::LBrace) {
+                        printer.newline();
+                    }
+                } else if printer.braces.is_empty() && printer.parens == 0 && starts_item(token) {
+                    // `crashable request` stays on one line
+                    let after_crashable = matches!(last_code, Some(Token::Identifier(name)) if name == "crashable");
+                    if !after_crashable {
+                        printer.newline();
+                    }
+                }
+                if printer.at_line_start() && gap && !last_was_open_brace && !closes_block {
+                    printer.blank_line();
+                }
+
+                if printer.at_line_start() {
+                    printer.write_indent();
+                } else if last_code.is_some_and(|prev| space_between(prev, last_is_unary, token)) {
+                    printer.out.push(' ');
+                }
+                printer.out.push_str(&token.text());
+
+                // The next token that is not a comment decides some line breaks
+                let next = tokens[index + 1..]
+                    .iter()
+                    .map(|(token, _)| token)
+                    .find(|token| !matches!(token, Token::Comment(_)));
+                match token {
+                    Token::LBrace => {
+                        let list = matches!(last_code, Some(Token::Identifier(name)) if name == "in")
+                            || matches!(before_last_code, Some(Token::Identifier(name)) if name == "enum");
+                        printer.braces.push(Brace {
+                            block: !list,
+                            outer_parens: printer.parens,
+                        });
+                        printer.parens = 0;
+                        if !list {
+                            printer.indent += 1;
+                            if next != Some(&Token::RBrace) {
+                                printer.newline();
+                            }
+                        }
+                    }
+                    Token::RBrace => {
+                        if let Some(brace) = printer.braces.pop() {
+                            printer.parens = brace.outer_parens;
+                        }
+                        let continues = match next {
+                            Some(Token::Identifier(name)) => name == "or",
+                            Some(next) => matches!(
+                                next,
+                                Token::Else | Token::Semicolon | Token::RParen | Token::RBracket | Token::Comma
+                            ),
+                            None => false,
+                        };
+                        if closes_block && !continues {
+                            printer.newline();
+                        }
+                    }
+                    Token::Semicolon if printer.parens == 0 => printer.newline(),
+                    Token::LParen | Token::LBracket => printer.parens += 1,
+                    Token::RParen | Token::RBracket => printer.parens = printer.parens.saturating_sub(1),
+                    _ => {}
+                }
+
+                last_is_unary = *token == Token::Minus && !is_binary_minus(last_code);
+                before_last_code = last_code;
+                last_code = Some(token);
+            }
+        }
+        last_line = Some(span.line);
+        last_was_open_brace = *token == Token::LBrace;
+    }
+    printer.newline();
+
+    // Only whitespace may change
+    let before: Vec<Token> = tokenize_with_spans(source)?.into_iter().map(|(token, _)| token).collect();
+    let after: Vec<Token> = tokenize_with_spans(&printer.out)?.into_iter().map(|(token, _)| token).collect();
+    if before != after {
+        return Err(ParseError {
+            message: "Formatting would change the program, so the file is left as it is".to_string(),
+            span: None,
+        });
+    }
+    Ok(printer.out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{ExprHc, parse_program};
+
+    #[test]
+    fn test_format_source() {
+        let source = "// Counter with a lock
+enum Mode{OFF,ON}
+global X:0..3 wrap;   init X in {0,1} init {}
+channel JOBS[2]:0..1 bag;
+
+
+fn inc(){X:=X+1}
+request r (n in -1..1) : Mode { lock(L) ; if(X==0){inc()}else if (X>=2) {X:=0;return 1-n}; // reset
+  unlock(L);
+  while(!(X<3)){yield}; send (JOBS,recv(JOBS)); choose{ON}or{OFF}
+}
+crashable request last{0}";
+        let expected = "// Counter with a lock
+enum Mode { OFF, ON }
+global X : 0..3 wrap;
+init X in { 0, 1 }
+init {}
+channel JOBS[2] : 0..1 bag;
+
+fn inc() {
+  X := X + 1
+}
+request r(n in -1..1) : Mode {
+  lock(L);
+  if (X == 0) {
+    inc()
+  } else if (X >= 2) {
+    X := 0;
+    return 1 - n
+  }; // reset
+  unlock(L);
+  while (!(X < 3)) {
+    yield
+  };
+  send(JOBS, recv(JOBS));
+  choose {
+    ON
+  } or {
+    OFF
+  }
+}
+crashable request last {
+  0
+}
+";
+        let formatted = format_source(source).unwrap();
+        assert_eq!(formatted, expected);
+        // Formatting is idempotent
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+
+        // The output parses to the identical hash-consed program
+        let mut table = ExprHc::new();
+        let original = parse_program(source, &mut table).unwrap();
+        let reparsed = parse_program(&formatted, &mut table).unwrap();
+        assert_eq!(original.requests, reparsed.requests);
+        assert_eq!(original.declarations, reparsed.declarations);
+        for (a, b) in original.requests.iter().zip(&reparsed.requests) {
+            assert!(std::ptr::eq(a.body.get(), b.body.get()));
+        }
+    }
+
+    #[test]
+    fn test_format_keeps_comments() {
+        let source = "import \"common.ser\"; // shared
+request r {
+  // first
+  X := 1; // one
+
+  // after a blank line
+  yield
+  // last
+}
+";
+        let formatted = format_source(source).unwrap();
+        assert_eq!(formatted, source);
+    }
+}
@@ -0,0 +1,160 @@
+// Loading a .ser file together with the files it imports with `import "file.ser";`.
+// Import paths are relative to the importing file. The files are ordered so that every
+// file comes after the files it imports; a file imported twice is loaded once.
+
+use crate::parser::{ExprHc, ParseError, Program, SourceFile, imports, parse_files, parse_source};
+use crate::deterministic_map::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Read `root` and, transitively, the files it imports
+pub fn load(root: &str) -> Result<Vec<SourceFile>, String> {
+    let mut files = Vec::new();
+    let mut loaded = HashSet::default();
+    let mut stack = Vec::new();
+    let source = fs::read_to_string(root).map_err(|err| format!("{}: {}", root, err))?;
+    visit(Path::new(root), source, &mut stack, &mut loaded, &mut files)?;
+    Ok(files)
+}
+
+/// Load `path` after the files it imports. `stack` holds the files being loaded,
+/// outermost first, to detect import cycles.
+fn visit(
+    path: &Path,
+    source: String,
+    stack: &mut Vec<(PathBuf, String)>,
+    loaded: &mut HashSet<PathBuf>,
+    files: &mut Vec<SourceFile>,
+) -> Result<(), String> {
+    let shown = path.display().to_string();
+    let identity = fs::canonicalize(path).map_err(|err| format!("{}: {}", shown, err))?;
+    if let Some(start) = stack.iter().position(|(other, _)| *other == identity) {
+        let mut cycle: Vec<&str> = stack[start..].iter().map(|(_, name)| name.as_str()).collect();
+        cycle.push(&shown);
+        return Err(format!("Import cycle: {}", cycle.join(" -> ")));
+    }
+    if !loaded.insert(identity.clone()) {
+        return Ok(());
+    }
+
+    let imported = imports(&source).map_err(|err| format!("{}: {}", shown, err.render(&source)))?;
+    stack.push((identity, shown.clone()));
+    let dir = path.parent().unwrap_or(Path::new(""));
+    for (import, span) in imported {
+        let import_path = dir.join(&import);
+        let import_source = fs::read_to_string(&import_path).map_err(|err| {
+            let err = ParseError {
+                message: format!("Cannot import \"{}\": {}", import, err),
+                span: Some(span),
+            };
+            format!("{}: {}", shown, err.render(&source))
+        })?;
+        visit(&import_path, import_source, stack, loaded, files)?;
+    }
+    stack.pop();
+
+    files.push(SourceFile {
+        path: shown,
+        source,
+    });
+    Ok(())
+}
+
+/// Read and parse `root` with its imports. A file without imports may also be a
+/// single expression, as with `parse_source`.
+pub fn parse_project(root: &str, table: &mut ExprHc) -> Result<Program, String> {
+    let files = load(root)?;
+    if let [file] = files.as_slice() {
+        return parse_source(&file.source, table)
+            .map_err(|err| format!("{}: {}", file.path, err.render(&file.source)));
+    }
+    parse_files(&files, table).map_err(|(index, err)| {
+        let file = &files[index];
+        format!("{}: {}", file.path, err.render(&file.source))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write(dir: &TempDir, name: &str, source: &str) -> String {
+        let path = dir.path().join(name);
+        fs::write(&path, source).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn test_parse_project() {
+        let dir = TempDir::new().unwrap();
+        write(
+            &dir,
+            "common.ser",
+            "enum Mode { OFF, ON }
+             global M : Mode;
+             fn toggle() { M := 1 - M; M }",
+        );
+        fs::create_dir(dir.path().join("lib")).unwrap();
+        write(&dir, "lib/extra.ser", "import \"../common.ser\"; fn twice() { toggle(); toggle() }");
+        let root = write(
+            &dir,
+            "main.ser",
+            "import \"common.ser\";
+             import \"lib/extra.ser\";
+             request flip : Mode { toggle() }
+             request both { twice() }",
+        );
+
+        let files = load(&root).unwrap();
+        // Dependencies come first and common.ser is loaded once
+        assert_eq!(files.len(), 3);
+        assert!(files[0].path.ends_with("common.ser"));
+        assert!(files[1].path.ends_with("extra.ser"));
+        assert_eq!(files[2].path, root);
+
+        let mut table = ExprHc::new();
+        let program = parse_project(&root, &mut table).unwrap();
+        assert_eq!(program.enums.len(), 1);
+        assert_eq!(program.declarations.len(), 1);
+        let names: Vec<_> = program.requests.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["flip", "both"]);
+        assert_eq!(program.requests[0].returns, Some("Mode".to_string()));
+    }
+
+    #[test]
+    fn test_import_errors() {
+        let dir = TempDir::new().unwrap();
+        let mut table = ExprHc::new();
+
+        write(&dir, "a.ser", "import \"b.ser\"; request r { 0 }");
+        write(&dir, "b.ser", "import \"a.ser\"; fn f() { 0 }");
+        let err = load(&dir.path().join("a.ser").to_string_lossy()).unwrap_err();
+        assert!(err.contains("Import cycle"), "{}", err);
+        assert!(err.contains("a.ser -> ") && err.contains("b.ser -> "), "{}", err);
+
+        write(&dir, "lib.ser", "fn f() { 1 } global X : 0..3;");
+        let root = write(&dir, "clash.ser", "import \"lib.ser\"; fn f() { 2 } request r { f() }");
+        let err = parse_project(&root, &mut table).unwrap_err();
+        assert!(err.contains("'f' is defined in both"), "{}", err);
+        assert!(err.starts_with(&root), "{}", err);
+
+        let root = write(&dir, "var.ser", "import \"lib.ser\"; global X : 0..1; request r { X }");
+        let err = parse_project(&root, &mut table).unwrap_err();
+        assert!(err.contains("'X' is defined in both"), "{}", err);
+
+        let root = write(&dir, "missing.ser", "import \"nowhere.ser\"; request r { 0 }");
+        let err = parse_project(&root, &mut table).unwrap_err();
+        assert!(err.contains("Cannot import \"nowhere.ser\""), "{}", err);
+
+        let root = write(&dir, "late.ser", "request r { 0 } import \"lib.ser\";");
+        let err = parse_project(&root, &mut table).unwrap_err();
+        assert!(err.contains("Imports must come before"), "{}", err);
+
+        // An error in an imported file is reported in that file
+        write(&dir, "broken.ser", "fn g() { 1 + }");
+        let root = write(&dir, "uses_broken.ser", "import \"broken.ser\"; request r { g() }");
+        let err = parse_project(&root, &mut table).unwrap_err();
+        assert!(err.contains("broken.ser: line 1"), "{}", err);
+    }
+}
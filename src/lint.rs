@@ -0,0 +1,356 @@
+// Static checks over a parsed program (`ser lint`). They flag code that is legal but
+// most likely not what was meant. Procedures are already inlined, so their locals show
+// up with the `@name` suffix of the call.
+
+use crate::deterministic_map::{HashMap, HashSet};
+use crate::expr_to_ns::is_local;
+use crate::parser::{Expr, Program, Request};
+use hash_cons::Hc;
+use std::fmt;
+
+/// A finding of the linter, in a request or in the program as a whole
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lint {
+    pub request: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.request {
+            Some(request) => write!(f, "request '{}': {}", request, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// The subexpressions of `expr`, in evaluation order
+fn children(expr: &Expr) -> Vec<&Hc<Expr>> {
+    match expr {
+        Expr::Assign(_, e)
+        | Expr::Return(e)
+        | Expr::Atomic(e)
+        | Expr::Transaction(e)
+        | Expr::Await(e)
+        | Expr::Send(_, _, e)
+        | Expr::Not(e)
+        | Expr::Load(_, _, e)
+        | Expr::Bounded(_, _, e)
+        | Expr::Assume(e)
+        | Expr::Assert(_, e)
+        | Expr::Typed(_, _, e) => vec![e],
+        Expr::Equal(e1, e2)
+        | Expr::Add(e1, e2)
+        | Expr::Subtract(e1, e2)
+        | Expr::Multiply(e1, e2)
+        | Expr::Divide(e1, e2)
+        | Expr::Modulo(e1, e2)
+        | Expr::NotEqual(e1, e2)
+        | Expr::Less(e1, e2)
+        | Expr::LessEqual(e1, e2)
+        | Expr::Greater(e1, e2)
+        | Expr::GreaterEqual(e1, e2)
+        | Expr::Sequence(e1, e2)
+        | Expr::While(e1, e2)
+        | Expr::And(e1, e2)
+        | Expr::Or(e1, e2)
+        | Expr::Store(_, _, e1, e2)
+        | Expr::Choice(e1, e2) => vec![e1, e2],
+        Expr::If(e1, e2, e3) | Expr::Iterate(e1, e2, e3) => vec![e1, e2, e3],
+        Expr::Tuple(items) => items.iter().collect(),
+        Expr::Break
+        | Expr::Continue
+        | Expr::Lock(_)
+        | Expr::Unlock(_)
+        | Expr::Acquire(_, _)
+        | Expr::Release(_)
+        | Expr::Recv(_, _)
+        | Expr::Error(_)
+        | Expr::Violation(_)
+        | Expr::Yield
+        | Expr::Exit
+        | Expr::Crashed
+        | Expr::Abort
+        | Expr::Unknown
+        | Expr::Choose(_, _)
+        | Expr::Symbol(_, _)
+        | Expr::Number(_)
+        | Expr::Variable(_) => vec![],
+    }
+}
+
+fn any(expr: &Expr, pred: &impl Fn(&Expr) -> bool) -> bool {
+    pred(expr) || children(expr).into_iter().any(|child| any(child, pred))
+}
+
+/// Whether other requests can run in the middle of `expr`: at a `yield`, or while
+/// it is blocked on a lock, a semaphore, an `await` or a channel
+fn may_yield(expr: &Expr) -> bool {
+    any(expr, &|e| {
+        matches!(
+            e,
+            Expr::Yield | Expr::Lock(_) | Expr::Acquire(_, _) | Expr::Await(_) | Expr::Send(_, _, _) | Expr::Recv(_, _)
+        )
+    })
+}
+
+/// Collect the variables (and arrays) that `expr` reads and writes
+fn variables(expr: &Expr, reads: &mut HashSet<String>, writes: &mut HashSet<String>) {
+    match expr {
+        Expr::Variable(var) | Expr::Load(var, _, _) => {
+            reads.insert(var.clone());
+        }
+        Expr::Assign(var, _) | Expr::Store(var, _, _, _) => {
+            writes.insert(var.clone());
+        }
+        Expr::Lock(var)
+        | Expr::Unlock(var)
+        | Expr::Acquire(var, _)
+        | Expr::Release(var)
+        | Expr::Send(var, _, _)
+        | Expr::Recv(var, _) => {
+            reads.insert(var.clone());
+            writes.insert(var.clone());
+        }
+        _ => {}
+    }
+    for child in children(expr) {
+        variables(child, reads, writes);
+    }
+}
+
+/// Locals that are definitely written when control reaches a point;
+/// `None` where control cannot get (after a `return`, `break`, ...)
+type Written = Option<HashSet<String>>;
+
+fn meet(a: Written, b: Written) -> Written {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.intersection(&b).cloned().collect()),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+/// Walk `expr` in evaluation order and record the locals read while they may still
+/// hold their initial 0. Returns the locals written afterwards.
+fn read_before_write(expr: &Expr, written: Written, unwritten_reads: &mut HashSet<String>) -> Written {
+    let mut set = written?;
+    match expr {
+        Expr::Variable(var) => {
+            if is_local(var) && !set.contains(var) {
+                unwritten_reads.insert(var.clone());
+            }
+            Some(set)
+        }
+        Expr::Assign(var, e) => {
+            let mut after = read_before_write(e, Some(set), unwritten_reads)?;
+            if is_local(var) {
+                after.insert(var.clone());
+            }
+            Some(after)
+        }
+        Expr::If(cond, then_branch, else_branch) => {
+            let after_cond = read_before_write(cond, Some(set), unwritten_reads);
+            let after_then = read_before_write(then_branch, after_cond.clone(), unwritten_reads);
+            let after_else = read_before_write(else_branch, after_cond, unwritten_reads);
+            meet(after_then, after_else)
+        }
+        Expr::Choice(first, second) => {
+            let after_first = read_before_write(first, Some(set.clone()), unwritten_reads);
+            let after_second = read_before_write(second, Some(set), unwritten_reads);
+            meet(after_first, after_second)
+        }
+        Expr::While(cond, body) => {
+            // The body may not run at all; a loop that only ends through `break` still
+            // leaves after the condition was evaluated
+            let after_cond = read_before_write(cond, Some(set), unwritten_reads);
+            read_before_write(body, after_cond.clone(), unwritten_reads);
+            after_cond
+        }
+        Expr::And(left, right) | Expr::Or(left, right) => {
+            // The right operand is not always evaluated
+            let after_left = read_before_write(left, Some(set), unwritten_reads);
+            read_before_write(right, after_left.clone(), unwritten_reads);
+            after_left
+        }
+        Expr::Return(e) => {
+            read_before_write(e, Some(set), unwritten_reads);
+            None
+        }
+        Expr::Break | Expr::Continue | Expr::Exit | Expr::Abort | Expr::Error(_) => None,
+        _ => {
+            for child in children(expr) {
+                set = read_before_write(child, Some(set), unwritten_reads)?;
+            }
+            Some(set)
+        }
+    }
+}
+
+/// Loops that can never end: no other request can run inside them, nothing in them
+/// changes what the condition reads and nothing jumps out. `run_expr` would not return.
+fn endless_loops(expr: &Expr, found: &mut Vec<String>) {
+    if let Expr::While(cond, body) = expr {
+        let mut cond_reads = HashSet::default();
+        let mut writes = HashSet::default();
+        variables(cond, &mut cond_reads, &mut writes);
+        variables(body, &mut HashSet::default(), &mut writes);
+        let leaves = any(body, &|e| {
+            matches!(e, Expr::Break | Expr::Return(_) | Expr::Exit | Expr::Abort | Expr::Error(_))
+        });
+        let nondeterministic = any(cond, &|e| matches!(e, Expr::Unknown | Expr::Choose(_, _)));
+        if !may_yield(expr)
+            && !leaves
+            && !nondeterministic
+            && **cond != Expr::Number(0)
+            && cond_reads.is_disjoint(&writes)
+        {
+            found.push(format!("while({}){{...}}", cond));
+        }
+    }
+    for child in children(expr) {
+        endless_loops(child, found);
+    }
+}
+
+fn lint_request(request: &Request, lints: &mut Vec<Lint>) {
+    let mut warn = |message: String| {
+        lints.push(Lint {
+            request: Some(request.name.clone()),
+            message,
+        })
+    };
+
+    let params = request.params.iter().map(|param| param.name.clone()).collect();
+    let mut unwritten_reads = HashSet::default();
+    read_before_write(&request.body, Some(params), &mut unwritten_reads);
+    let mut unwritten_reads: Vec<String> = unwritten_reads.into_iter().collect();
+    unwritten_reads.sort();
+    for var in unwritten_reads {
+        warn(format!("local '{}' is read before it is written, so it is always 0 there", var));
+    }
+
+    if !may_yield(&request.body) {
+        warn("never yields, so it is trivially atomic".to_string());
+    }
+
+    let mut loops = Vec::new();
+    endless_loops(&request.body, &mut loops);
+    for found in loops {
+        warn(format!(
+            "{} never ends: its body does not yield and does not change the condition",
+            found
+        ));
+    }
+}
+
+/// Run all checks on a program
+pub fn lint(program: &Program) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    for request in &program.requests {
+        lint_request(request, &mut lints);
+    }
+
+    let mut reads = HashSet::default();
+    let mut writes = HashSet::default();
+    for request in &program.requests {
+        variables(&request.body, &mut reads, &mut writes);
+        reads.extend(request.params.iter().map(|param| param.name.clone()));
+    }
+    let mut unread: Vec<&String> =
+        writes.iter().filter(|var| !is_local(var) && !reads.contains(*var)).collect();
+    unread.sort();
+    for var in unread {
+        lints.push(Lint {
+            request: None,
+            message: format!("global '{}' is written but never read", var),
+        });
+    }
+
+    // Names that differ only in case; the case alone decides whether a name is local.
+    // Procedure locals are compared without their `@name` suffix.
+    let mut by_lowercase: HashMap<String, HashSet<String>> = HashMap::default();
+    let declared = program.declarations.iter().map(|decl| decl.name.clone());
+    for var in reads.into_iter().chain(writes).chain(declared) {
+        let base = var.split('@').next().unwrap_or(&var).to_string();
+        by_lowercase.entry(base.to_lowercase()).or_default().insert(base);
+    }
+    let mut clashes: Vec<Vec<String>> = by_lowercase
+        .into_values()
+        .filter(|names| names.len() > 1)
+        .map(|names| {
+            let mut names: Vec<String> = names.into_iter().collect();
+            names.sort();
+            names
+        })
+        .collect();
+    clashes.sort();
+    for names in clashes {
+        let names: Vec<String> = names.iter().map(|name| format!("'{}'", name)).collect();
+        lints.push(Lint {
+            request: None,
+            message: format!(
+                "{} differ only in case; lowercase names are locals and uppercase names globals",
+                names.join(" and ")
+            ),
+        });
+    }
+    lints
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{ExprHc, parse_program};
+
+    fn messages(source: &str) -> Vec<String> {
+        let mut table = ExprHc::new();
+        let program = parse_program(source, &mut table).unwrap();
+        lint(&program).iter().map(|lint| lint.to_string()).collect()
+    }
+
+    #[test]
+    fn test_lint() {
+        let found = messages(
+            "request r(n in 0..1) {
+               if (n == 0) { a := 1 } else { return 0 };
+               y := a + z + n;
+               yield;
+               LOG := y;
+               while (X == 0) { y := y + 1 };
+               count := Count
+             }
+             request s { X := 1; yield; X }",
+        );
+        assert_eq!(
+            found,
+            vec![
+                "request 'r': local 'z' is read before it is written, so it is always 0 there",
+                "request 'r': while(X == 0){...} never ends: its body does not yield and does not change the condition",
+                "global 'LOG' is written but never read",
+                "'Count' and 'count' differ only in case; lowercase names are locals and uppercase names globals",
+            ]
+        );
+
+        let found = messages("request atomic_inc { X := X + 1 } request get { X }");
+        assert!(found.contains(&"request 'atomic_inc': never yields, so it is trivially atomic".to_string()));
+        assert!(found.contains(&"request 'get': never yields, so it is trivially atomic".to_string()));
+    }
+
+    #[test]
+    fn test_lint_accepts_usual_loops() {
+        let found = messages(
+            "fn wait() { while (STOP == 1) { yield } }
+             request r {
+               wait();
+               i := 0;
+               while (i < 3) { i := i + 1 };
+               while (1 == 1) { if (i == 3) { break } };
+               lock(L); unlock(L);
+               i
+             }
+             request stop { STOP := 1; yield; STOP := 0 }",
+        );
+        assert!(found.is_empty(), "{:?}", found);
+    }
+}
@@ -4,10 +4,13 @@
 mod debug_report;
 mod deterministic_map;
 mod expr_to_ns;
+mod formatter;
 mod graphviz;
+mod imports;
 mod isl;
 
 mod kleene;
+mod lint;
 mod ns;
 mod ns_decision;
 mod ns_to_petri;
@@ -28,8 +31,6 @@ mod stats;
 mod utils;
 
 use colored::*;
-use parser::Program;
-use parser::Request;
 use std::env;
 use std::fmt::Display;
 use std::fs;
@@ -37,11 +38,15 @@ use std::hash::Hash;
 use std::path::Path;
 use std::process;
 
+use expr_to_ns::ConvertOptions;
 use ns::NS;
-use parser::{ExprHc, parse, parse_program};
+use parser::ExprHc;
 
 fn print_usage() {
     println!("{}", "Usage: ser [options] <filename or directory>".bold());
+    println!("{}", "       ser fmt [--check] <file.ser>...".bold());
+    println!("{}", "       ser lint <file.ser>...".bold());
+    println!("{}", "       ser refine [--loop-budget <n>] [--session <requests>] <impl.ser> <spec.ser>".bold());
     println!("{}", "Options:".bold());
     println!(
         "  {}                  Open generated visualization files",
@@ -63,6 +68,14 @@ fn print_usage() {
         "  {}      Set SMPT timeout in seconds (default: 300)",
         "--timeout <seconds>".green()
     );
+    println!(
+        "  {}        States a loop may go through without yielding (default: 100000)",
+        "--loop-budget <n>".green()
+    );
+    println!(
+        "  {}     Add a client that issues the requests in order, e.g. \"inc; get\"",
+        "--session <requests>".green()
+    );
     println!(
         "  {}             Enable SMPT result caching",
         "--use-cache".green()
@@ -106,12 +119,26 @@ fn print_usage() {
 fn main() {
     let args: Vec<String> = env::args().collect();
 
+    if args.get(1).map(String::as_str) == Some("fmt") {
+        format_files(&args[2..]);
+        return;
+    }
+    if args.get(1).map(String::as_str) == Some("lint") {
+        lint_files(&args[2..]);
+        return;
+    }
+    if args.get(1).map(String::as_str) == Some("refine") {
+        refine_files(&args[2..]);
+        return;
+    }
+
     // Parse command line flags
     let mut open_files = false;
     let mut optimize_enabled = true;
     let mut path_str = "";
     let mut create_certificate_mode = false;
     let mut check_certificate_mode = false;
+    let mut options = ConvertOptions::default();
 
     // Skip the program name (args[0])
     let mut i = 1;
@@ -165,6 +192,7 @@ fn main() {
                     }
                 }
             }
+            "--loop-budget" | "--session" => parse_convert_option(&args, &mut i, &mut options),
             "--without-remove-redundant" => {
                 semilinear::set_remove_redundant(false);
                 i += 1;
@@ -247,9 +275,9 @@ fn main() {
             }
             Some("ser") => {
                 if create_certificate_mode {
-                    create_certificate_for_ser_file(path_str);
+                    create_certificate_for_ser_file(path_str, &options);
                 } else {
-                    check_certificate_for_ser_file(path_str);
+                    check_certificate_for_ser_file(path_str, &options);
                 }
             }
             _ => {
@@ -269,7 +297,7 @@ fn main() {
 
     if path.is_dir() {
         // Process directory recursively
-        match process_directory(path, open_files) {
+        match process_directory(path, open_files, &options) {
             Ok(count) => {
                 println!(
                     "{} {} files",
@@ -286,7 +314,7 @@ fn main() {
         // Process single file
         match path.extension().and_then(|ext| ext.to_str()) {
             Some("json") => process_json_file(path_str, open_files),
-            Some("ser") => process_ser_file(path_str, open_files),
+            Some("ser") => process_ser_file(path_str, open_files, &options),
             _ => {
                 eprintln!(
                     "{}: Unsupported file extension for '{}'. Please use {} or {}",
@@ -302,6 +330,191 @@ fn main() {
     }
 }
 
+// `ser fmt`: rewrite .ser files in the canonical layout. With --check the files are
+// left alone and the exit code says whether any of them needs formatting.
+fn format_files(args: &[String]) {
+    let check = args.iter().any(|arg| arg == "--check");
+    let files: Vec<&String> = args.iter().filter(|arg| *arg != "--check").collect();
+    if files.is_empty() {
+        print_usage();
+        process::exit(1);
+    }
+
+    let mut unformatted = 0;
+    for file in files {
+        let content = match fs::read_to_string(file) {
+            Ok(content) => content,
+            Err(err) => {
+                eprintln!("{} {}: {}", "Error reading".red().bold(), file, err);
+                process::exit(1);
+            }
+        };
+        let formatted = match formatter::format_source(&content) {
+            Ok(formatted) => formatted,
+            Err(err) => {
+                eprintln!("{} {}: {}", "Error formatting".red().bold(), file, err.render(&content));
+                process::exit(1);
+            }
+        };
+        if formatted == content {
+            continue;
+        }
+        if check {
+            println!("{} {}", "Not formatted:".yellow().bold(), file);
+            unformatted += 1;
+        } else if let Err(err) = fs::write(file, &formatted) {
+            eprintln!("{} {}: {}", "Error writing".red().bold(), file, err);
+            process::exit(1);
+        } else {
+            println!("{} {}", "Formatted".green().bold(), file);
+        }
+    }
+    if unformatted > 0 {
+        process::exit(1);
+    }
+}
+
+// `ser lint`: report suspicious code in .ser files; the exit code is 1 if there is any
+fn lint_files(files: &[String]) {
+    if files.is_empty() {
+        print_usage();
+        process::exit(1);
+    }
+
+    let mut count = 0;
+    for file in files {
+        let mut table = ExprHc::new();
+        let program = match imports::parse_project(file, &mut table) {
+            Ok(program) => program,
+            Err(err) => {
+                eprintln!("{} {}", "Error parsing".red().bold(), err);
+                process::exit(1);
+            }
+        };
+        for lint in lint::lint(&program) {
+            println!("{}: {} {}", file, "warning:".yellow().bold(), lint);
+            count += 1;
+        }
+    }
+    if count > 0 {
+        println!("{} {} warnings", "Found".yellow().bold(), count);
+        process::exit(1);
+    }
+}
+
+// Parse `--loop-budget <n>` or `--session <requests>` at `args[*i]` into `options`
+fn parse_convert_option(args: &[String], i: &mut usize, options: &mut ConvertOptions) {
+    let flag = args[*i].as_str();
+    if *i + 1 >= args.len() {
+        let what = if flag == "--session" { "a list of requests" } else { "a value" };
+        eprintln!("{}: {} requires {}", "Error".red().bold(), flag, what);
+        print_usage();
+        process::exit(1);
+    }
+    *i += 1;
+    if flag == "--session" {
+        let requests: Vec<String> = args[*i]
+            .split(';')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+            .collect();
+        options.sessions.push(requests);
+    } else {
+        match args[*i].parse::<usize>() {
+            Ok(budget) => options.loop_budget = budget,
+            Err(_) => {
+                eprintln!(
+                    "{}: Invalid loop budget '{}'",
+                    "Error".red().bold(),
+                    args[*i]
+                );
+                print_usage();
+                process::exit(1);
+            }
+        }
+    }
+    *i += 1;
+}
+
+// `ser refine`: check that the implementation only produces completed requests that
+// serial executions of the spec produce; the exit code is 1 if it does not.
+// `--loop-budget` and `--session` apply to both programs.
+fn refine_files(args: &[String]) {
+    let mut options = ConvertOptions::default();
+    let mut files = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--loop-budget" | "--session" => parse_convert_option(args, &mut i, &mut options),
+            _ => {
+                files.push(args[i].as_str());
+                i += 1;
+            }
+        }
+    }
+    let [impl_path, spec_path] = files[..] else {
+        print_usage();
+        process::exit(1);
+    };
+    stats::start_analysis(impl_path.to_string());
+
+    // Both programs share the table, so equal local states are the same expression
+    let mut table = ExprHc::new();
+    let mut load = |file_path: &str| {
+        let program = imports::parse_project(file_path, &mut table).unwrap_or_else(|err| {
+            eprintln!("{} {}", "Error parsing".red().bold(), err);
+            process::exit(1);
+        });
+        expr_to_ns::try_program_to_ns(&mut table, &program, &options).unwrap_or_else(|err| {
+            eprintln!("{} {}: {}", "Error converting program".red().bold(), file_path, err);
+            process::exit(1);
+        })
+    };
+    let (ns, skipped_spins) = load(impl_path);
+    let (spec, _) = load(spec_path);
+    stats::set_skipped_spin_transitions(skipped_spins);
+
+    // A request the spec does not have could never be serialized
+    let spec_requests = spec.get_requests();
+    for req in ns.get_requests() {
+        if !spec_requests.contains(&req) {
+            eprintln!(
+                "{} request {} of {} is not in {}",
+                "Error:".red().bold(),
+                req,
+                impl_path,
+                spec_path
+            );
+            process::exit(1);
+        }
+    }
+
+    let file_stem = Path::new(impl_path).file_stem().and_then(|s| s.to_str()).unwrap_or("expr");
+    let out_dir = format!("out/{}", file_stem);
+    if let Err(err) = utils::file::ensure_dir_exists(&out_dir) {
+        eprintln!(
+            "{} output directory: {}",
+            "Failed to create".red().bold(),
+            err
+        );
+        process::exit(1);
+    }
+
+    println!(
+        "{} {} {} {}",
+        "🔍".blue(),
+        impl_path.cyan(),
+        "against spec".blue().bold(),
+        spec_path.cyan()
+    );
+    let refines = ns.refines(&spec, &out_dir);
+    stats::finalize_stats();
+    if !refines {
+        process::exit(1);
+    }
+}
+
 // Process a Network System: generate visualizations for NS, Petri net, and Petri net with requests
 fn process_ns<G, L, Req, Resp>(ns: &NS<G, L, Req, Resp>, out_dir: &str, open_files: bool)
 where
@@ -552,7 +765,7 @@ fn process_json_file(file_path: &str, open_files: bool) {
     stats::finalize_stats();
 }
 
-fn process_ser_file(file_path: &str, open_files: bool) {
+fn process_ser_file(file_path: &str, open_files: bool, options: &ConvertOptions) {
     // Initialize stats collection
     stats::start_analysis(file_path.to_string());
     
@@ -570,17 +783,8 @@ fn process_ser_file(file_path: &str, open_files: bool) {
         file_path.cyan()
     );
 
-    let content = match fs::read_to_string(file_path) {
-        Ok(content) => content,
-        Err(err) => {
-            eprintln!("{} file: {}", "Error reading".red().bold(), err);
-            process::exit(1);
-        }
-    };
-
-    // Try to parse as a program with multiple requests first
     let mut table = ExprHc::new();
-    let ns = match parse_program(&content, &mut table) {
+    let ns = match imports::parse_project(file_path, &mut table) {
         Ok(program) => {
             println!(
                 "{} {} requests",
@@ -592,33 +796,17 @@ fn process_ser_file(file_path: &str, open_files: bool) {
                 "{}",
                 "Converting program to Network System...".cyan().bold()
             );
-            expr_to_ns::program_to_ns(&mut table, &program)
-        }
-        Err(_) => {
-            // Fall back to parsing as a single expression
-            match parse(&content, &mut table) {
-                Ok(expr) => {
-                    println!("{} {}", "Parsed expression:".blue().bold(), expr);
-                    // Convert expression to Network System
-                    println!(
-                        "{}",
-                        "Converting expression to Network System...".cyan().bold()
-                    );
-                    expr_to_ns::program_to_ns(
-                        &mut table,
-                        &Program {
-                            requests: vec![Request {
-                                name: "request".to_string(),
-                                body: expr,
-                            }],
-                        },
-                    )
-                }
-                Err(err) => {
-                    eprintln!("{} SER file: {}", "Error parsing".red().bold(), err);
+            let (ns, skipped_spins) =
+                expr_to_ns::try_program_to_ns(&mut table, &program, options).unwrap_or_else(|err| {
+                    eprintln!("{} {}", "Error converting program:".red().bold(), err);
                     process::exit(1);
-                }
-            }
+                });
+            stats::set_skipped_spin_transitions(skipped_spins);
+            ns
+        }
+        Err(err) => {
+            eprintln!("{} {}", "Error parsing".red().bold(), err);
+            process::exit(1);
         }
     };
 
@@ -646,7 +834,7 @@ fn process_ser_file(file_path: &str, open_files: bool) {
 }
 
 // Recursively process all files in a directory and its subdirectories
-fn process_directory(dir: &Path, open_files: bool) -> Result<usize, String> {
+fn process_directory(dir: &Path, open_files: bool, options: &ConvertOptions) -> Result<usize, String> {
     let mut processed_count = 0;
 
     // Read directory contents
@@ -680,7 +868,7 @@ fn process_directory(dir: &Path, open_files: bool) -> Result<usize, String> {
 
         if path.is_dir() {
             // Recursively process subdirectory
-            match process_directory(&path, open_files) {
+            match process_directory(&path, open_files, options) {
                 Ok(count) => processed_count += count,
                 Err(err) => eprintln!("{}: {}", "Warning".yellow().bold(), err),
             }
@@ -695,7 +883,7 @@ fn process_directory(dir: &Path, open_files: bool) -> Result<usize, String> {
                         processed_count += 1;
                     }
                     "ser" => {
-                        process_ser_file(&path_str, open_files);
+                        process_ser_file(&path_str, open_files, options);
                         processed_count += 1;
                     }
                     _ => {} // Skip files with unsupported extensions
@@ -709,7 +897,7 @@ fn process_directory(dir: &Path, open_files: bool) -> Result<usize, String> {
 }
 
 // Certificate creation functions
-fn create_certificate_for_ser_file(file_path: &str) {
+fn create_certificate_for_ser_file(file_path: &str, options: &ConvertOptions) {
     println!();
     println!(
         "{}",
@@ -724,45 +912,24 @@ fn create_certificate_for_ser_file(file_path: &str) {
         file_path.cyan()
     );
 
-    let content = match fs::read_to_string(file_path) {
-        Ok(content) => content,
-        Err(err) => {
-            eprintln!("{} file: {}", "Error reading".red().bold(), err);
-            process::exit(1);
-        }
-    };
-
-    // Try to parse as a program with multiple requests first
     let mut table = ExprHc::new();
-    let ns = match parse_program(&content, &mut table) {
+    let ns = match imports::parse_project(file_path, &mut table) {
         Ok(program) => {
             println!(
                 "{} {} requests",
                 "Parsed program with".blue().bold(),
                 program.requests.len()
             );
-            expr_to_ns::program_to_ns(&mut table, &program)
-        }
-        Err(_) => {
-            // Fall back to parsing as a single expression
-            match parse(&content, &mut table) {
-                Ok(expr) => {
-                    println!("{} {}", "Parsed expression:".blue().bold(), expr);
-                    expr_to_ns::program_to_ns(
-                        &mut table,
-                        &Program {
-                            requests: vec![Request {
-                                name: "request".to_string(),
-                                body: expr,
-                            }],
-                        },
-                    )
-                }
-                Err(err) => {
-                    eprintln!("{} SER file: {}", "Error parsing".red().bold(), err);
+            let (ns, _) =
+                expr_to_ns::try_program_to_ns(&mut table, &program, options).unwrap_or_else(|err| {
+                    eprintln!("{} {}", "Error converting program:".red().bold(), err);
                     process::exit(1);
-                }
-            }
+                });
+            ns
+        }
+        Err(err) => {
+            eprintln!("{} {}", "Error parsing".red().bold(), err);
+            process::exit(1);
         }
     };
 
@@ -977,6 +1144,25 @@ where
                 }
             }
         }
+        ns_decision::NSDecision::AssertionViolation { trace } => {
+            println!("{} {}", "Certificate type:".cyan(), "ASSERTION VIOLATION".red().bold());
+            println!();
+
+            // The trace may end with other requests still in flight
+            match ns.check_violation_trace(trace) {
+                Ok((req, resp)) => {
+                    println!("{} {}", "✅".green(), "Certificate trace is VALID".green().bold());
+                    println!("  ✓ Trace is executable in the Network System");
+                    println!("  ✓ It ends with the failed assertion {}/{}", req, resp);
+                    true
+                }
+                Err(err) => {
+                    println!("{} {}", "❌".red(), "Certificate trace is INVALID".red().bold());
+                    println!("  ✗ {}", err);
+                    false
+                }
+            }
+        }
         ns_decision::NSDecision::Timeout { message } => {
             println!("{} {}", "Certificate type:".cyan(), "TIMEOUT".yellow().bold());
             println!();
@@ -984,11 +1170,28 @@ where
             println!("  {}", message);
             false
         }
+        ns_decision::NSDecision::PerInitialGlobal { decisions } => {
+            let mut all_valid = true;
+            for initial_global in &ns.initial_globals {
+                println!();
+                println!("{} {}", "Initial global state:".cyan(), initial_global);
+                match decisions.iter().find(|(g, _)| g == initial_global) {
+                    Some((g, decision)) => {
+                        all_valid &= verify_certificate(&ns.for_initial_global(g), decision);
+                    }
+                    None => {
+                        println!("{} {}", "❌".red(), "No certificate for this initial state".red().bold());
+                        all_valid = false;
+                    }
+                }
+            }
+            all_valid
+        }
     }
 }
 
 // Certificate checking functions
-fn check_certificate_for_ser_file(file_path: &str) {
+fn check_certificate_for_ser_file(file_path: &str, options: &ConvertOptions) {
     println!();
     println!(
         "{}",
@@ -1003,39 +1206,22 @@ fn check_certificate_for_ser_file(file_path: &str) {
         file_path.cyan()
     );
 
-    // Load and parse the .ser file to get NS
-    let content = match fs::read_to_string(file_path) {
-        Ok(content) => content,
+    // Load and parse the .ser file with its imports to get NS
+    let mut table = ExprHc::new();
+    let ns = match imports::parse_project(file_path, &mut table) {
+        Ok(program) => {
+            let (ns, _) = expr_to_ns::try_program_to_ns(&mut table, &program, options).unwrap_or_else(|err| {
+                eprintln!("{} {}", "Error converting program:".red().bold(), err);
+                process::exit(1);
+            });
+            ns
+        }
         Err(err) => {
-            eprintln!("{} file: {}", "Error reading".red().bold(), err);
+            eprintln!("{} {}", "Error parsing".red().bold(), err);
             process::exit(1);
         }
     };
 
-    let mut table = ExprHc::new();
-    let ns = match parse_program(&content, &mut table) {
-        Ok(program) => expr_to_ns::program_to_ns(&mut table, &program),
-        Err(_) => {
-            match parse(&content, &mut table) {
-                Ok(expr) => {
-                    expr_to_ns::program_to_ns(
-                        &mut table,
-                        &Program {
-                            requests: vec![Request {
-                                name: "request".to_string(),
-                                body: expr,
-                            }],
-                        },
-                    )
-                }
-                Err(err) => {
-                    eprintln!("{} SER file: {}", "Error parsing".red().bold(), err);
-                    process::exit(1);
-                }
-            }
-        }
-    };
-
     // Get the output directory path
     let path = Path::new(file_path);
     let file_stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("expr");
@@ -1057,9 +1243,9 @@ fn check_certificate_for_ser_file(file_path: &str) {
     println!("Loading certificate from: {}", cert_path.cyan());
     
     // Import the required types
-    use crate::expr_to_ns::{Env, ExprRequest, LocalExpr};
+    use crate::expr_to_ns::{Env, ExprRequest, LocalExpr, Response};
     
-    let decision = match ns_decision::NSDecision::<Env, LocalExpr, ExprRequest, i64>::load_from_file(&cert_path) {
+    let decision = match ns_decision::NSDecision::<Env, LocalExpr, ExprRequest, Response>::load_from_file(&cert_path) {
         Ok(decision) => decision,
         Err(err) => {
             eprintln!(
@@ -1188,11 +1374,26 @@ fn check_certificate_for_json_file(file_path: &str) {
             println!("{} {}", "Certificate type:".cyan(), "NOT SERIALIZABLE".red().bold());
             true
         }
+        ns_decision::NSDecision::AssertionViolation { .. } => {
+            println!();
+            println!("{} {}", "Certificate type:".cyan(), "ASSERTION VIOLATION".red().bold());
+            true
+        }
         ns_decision::NSDecision::Timeout { .. } => {
             println!();
             println!("{} {}", "Certificate type:".cyan(), "TIMEOUT".yellow().bold());
             true
         }
+        ns_decision::NSDecision::PerInitialGlobal { decisions } => {
+            println!();
+            println!(
+                "{} {} ({} initial states)",
+                "Certificate type:".cyan(),
+                "PER INITIAL STATE".cyan().bold(),
+                decisions.len()
+            );
+            true
+        }
     };
 
     println!();
@@ -28,6 +28,24 @@ type PetriTraceStep<L, G, Req, Resp> = (
     Vec<PetriPlace<L, G, Req, Resp>>,
 );
 
+// Accept either a single value or a list of values
+fn one_or_many<'de, D, T>(de: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        Many(Vec<T>),
+        One(T),
+    }
+    Ok(match OneOrMany::deserialize(de)? {
+        OneOrMany::Many(values) => values,
+        OneOrMany::One(value) => vec![value],
+    })
+}
+
 // Helper function to properly quote strings for GraphViz labels
 fn quote_for_graphviz(s: &str) -> String {
     format!("\"{}\"", s.replace('\"', "\\\""))
@@ -40,8 +58,10 @@ fn quote_for_graphviz(s: &str) -> String {
 /// - Resp: Response type
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct NS<G, L, Req, Resp> {
-    /// Initial global state
-    pub initial_global: G,
+    /// Initial global states; every one of them is checked separately.
+    /// Older JSON files with a single `initial_global` are still accepted.
+    #[serde(alias = "initial_global", deserialize_with = "one_or_many")]
+    pub initial_globals: Vec<G>,
 
     /// Requests from clients with their target local states
     pub requests: Vec<(Req, L)>,
@@ -51,6 +71,24 @@ pub struct NS<G, L, Req, Resp> {
 
     /// State transitions (from_local, from_global, to_local, to_global)
     pub transitions: Vec<(L, G, L, G)>,
+
+    /// Responses that report a failed assertion. Reaching one of them is
+    /// reported as a violation before serializability is checked.
+    #[serde(default = "Vec::new")]
+    pub violations: Vec<Resp>,
+
+    /// Responses that stand for a request that crashed. Such a request keeps its
+    /// writes but never really responds, so in the serial executions it counts as
+    /// either fully executed or not executed at all.
+    #[serde(default = "Vec::new")]
+    pub crashes: Vec<Resp>,
+
+    /// Sessions: clients that issue the given requests one after another. A request
+    /// of a session starts at most once, and only after the previous request of the
+    /// session responded. Requests in no session can start at any time; a request is
+    /// in at most one session.
+    #[serde(default = "Vec::new")]
+    pub sessions: Vec<Vec<Req>>,
 }
 
 impl<G, L, Req, Resp> NS<G, L, Req, Resp>
@@ -63,16 +101,45 @@ where
     /// Create a new Network System with the given initial global state
     pub fn new(initial_global: G) -> Self {
         NS {
-            initial_global,
+            initial_globals: vec![initial_global],
             requests: Vec::new(),
             responses: Vec::new(),
             transitions: Vec::new(),
+            violations: Vec::new(),
+            crashes: Vec::new(),
+            sessions: Vec::new(),
         }
     }
 
-    /// Set the initial global state
+    /// Set the initial global state, replacing any others
     pub fn set_initial_global(&mut self, initial_global: G) {
-        self.initial_global = initial_global;
+        self.initial_globals = vec![initial_global];
+    }
+
+    /// Add another initial global state
+    pub fn add_initial_global(&mut self, initial_global: G) {
+        if !self.initial_globals.contains(&initial_global) {
+            self.initial_globals.push(initial_global);
+        }
+    }
+
+    /// The first initial global state. Analyses that need a single start
+    /// state work on the restrictions returned by `for_initial_global`.
+    pub fn initial_global(&self) -> &G {
+        &self.initial_globals[0]
+    }
+
+    /// The same network system started only from the given global state
+    pub fn for_initial_global(&self, initial_global: &G) -> Self {
+        NS {
+            initial_globals: vec![initial_global.clone()],
+            requests: self.requests.clone(),
+            responses: self.responses.clone(),
+            transitions: self.transitions.clone(),
+            violations: self.violations.clone(),
+            crashes: self.crashes.clone(),
+            sessions: self.sessions.clone(),
+        }
     }
 
     /// Add a client request with its target local state
@@ -85,6 +152,41 @@ where
         }
     }
 
+    /// Mark a response as reporting a failed assertion
+    pub fn add_violation(&mut self, response: Resp) {
+        if !self.violations.contains(&response) {
+            self.violations.push(response);
+        }
+    }
+
+    /// Mark a response as standing for a crashed request
+    pub fn add_crash(&mut self, response: Resp) {
+        if !self.crashes.contains(&response) {
+            self.crashes.push(response);
+        }
+    }
+
+    /// Add a session that issues the given requests in order
+    pub fn add_session(&mut self, requests: Vec<Req>) {
+        self.sessions.push(requests);
+    }
+
+    /// The session of a request and its position in that session
+    pub fn session_position(&self, request: &Req) -> Option<(usize, usize)> {
+        self.sessions.iter().enumerate().find_map(|(session, requests)| {
+            requests
+                .iter()
+                .position(|req| req == request)
+                .map(|position| (session, position))
+        })
+    }
+
+    /// The request that the session of `request` issues after it
+    pub fn session_next(&self, request: &Req) -> Option<&Req> {
+        let (session, position) = self.session_position(request)?;
+        self.sessions[session].get(position + 1)
+    }
+
     /// Add a response from a local state
     pub fn add_response(&mut self, local_state: L, response: Resp) {
         if !self
@@ -134,7 +236,7 @@ where
     /// Get all unique global states in the network system
     pub fn get_global_states(&self) -> Vec<&G> {
         let mut globals = HashSet::default();
-        globals.insert(&self.initial_global);
+        globals.extend(&self.initial_globals);
 
         // Collect global states from transitions
         for (_, from_global, _, to_global) in &self.transitions {
@@ -168,8 +270,14 @@ where
     /// - request req in the network system that goes to some local state l
     /// - a sequence of transitions from l to l' that transitions from g to g'
     /// - a response from l' to resp
+    ///
+    /// A crashed request counts as either run completely or not at all: instead of
+    /// the runs that end in a crash response c, there are elements (g, req, c, g) and
+    /// (g, req, c, g') for every complete run (g, req, resp, g') of the request.
+    /// Only requests that can reach a crash response from some global state get these.
     pub fn serialized_automaton(&self) -> Vec<(G, Req, Resp, G)> {
         let mut serialized_automaton: Vec<(G, Req, Resp, G)> = Vec::new();
+        let mut crashable: HashSet<&Req> = HashSet::default();
         // iterate over all global states
         for g in self.get_global_states() {
             // iterate over all requests
@@ -189,7 +297,12 @@ where
                 let mut reached_responses: HashSet<(&Resp, &G)> = HashSet::default();
                 for (l, g) in reached {
                     for (l2, resp) in &self.responses {
-                        if l == l2 {
+                        if l != l2 {
+                            continue;
+                        }
+                        if self.crashes.contains(resp) {
+                            crashable.insert(req);
+                        } else {
                             reached_responses.insert((resp, g));
                         }
                     }
@@ -200,6 +313,28 @@ where
                 }
             }
         }
+
+        let mut crashed: Vec<(G, Req, Resp, G)> = Vec::new();
+        for crash in &self.crashes {
+            for g in self.get_global_states() {
+                for req in self.get_requests() {
+                    if crashable.contains(req) {
+                        crashed.push((g.clone(), req.clone(), crash.clone(), g.clone()));
+                    }
+                }
+            }
+            for (g, req, _, g2) in &serialized_automaton {
+                if crashable.contains(req) {
+                    crashed.push((g.clone(), req.clone(), crash.clone(), g2.clone()));
+                }
+            }
+        }
+        let mut seen: HashSet<_> = serialized_automaton.iter().cloned().collect();
+        for edge in crashed {
+            if seen.insert(edge.clone()) {
+                serialized_automaton.push(edge);
+            }
+        }
         serialized_automaton
     }
 
@@ -207,12 +342,59 @@ where
         &self,
         atom: impl Fn(Req, Resp) -> K,
     ) -> K {
-        let nfa: Vec<(G, K, G)> = self
-            .serialized_automaton()
+        let automaton = self.serialized_automaton();
+        if !self.sessions.is_empty() {
+            return self.serialized_sessions_kleene(&automaton, atom);
+        }
+        let nfa: Vec<(G, K, G)> = automaton
             .into_iter()
             .map(|(g, req, resp, g2)| (g, atom(req, resp), g2))
             .collect();
-        nfa_to_kleene(&nfa, self.initial_global.clone())
+        // Serial executions may start from any initial global state
+        let mut starts = self.initial_globals.iter();
+        let first = nfa_to_kleene(&nfa, starts.next().unwrap().clone());
+        starts.fold(first, |acc, g| acc.plus(nfa_to_kleene(&nfa, g.clone())))
+    }
+
+    /// Serial executions that keep the order of every session. The states of the
+    /// automaton also count how many requests each session has issued, and a request
+    /// of a session can only run when it is the next one of its session.
+    fn serialized_sessions_kleene<K: Kleene + Clone>(
+        &self,
+        automaton: &[(G, Req, Resp, G)],
+        atom: impl Fn(Req, Resp) -> K,
+    ) -> K {
+        let mut edges: HashMap<&G, Vec<(&Req, &Resp, &G)>> = HashMap::default();
+        for (g, req, resp, g2) in automaton {
+            edges.entry(g).or_default().push((req, resp, g2));
+        }
+
+        let start = |g: &G| (g.clone(), vec![0; self.sessions.len()]);
+        let mut nfa = Vec::new();
+        let mut seen: HashSet<(G, Vec<usize>)> = HashSet::default();
+        let mut todo: Vec<(G, Vec<usize>)> = self.initial_globals.iter().map(start).collect();
+        while let Some(state) = todo.pop() {
+            if !seen.insert(state.clone()) {
+                continue;
+            }
+            let (g, issued) = &state;
+            for (req, resp, g2) in edges.get(g).into_iter().flatten() {
+                let mut next_issued = issued.clone();
+                if let Some((session, position)) = self.session_position(req) {
+                    if issued[session] != position {
+                        continue;
+                    }
+                    next_issued[session] += 1;
+                }
+                let next = ((*g2).clone(), next_issued);
+                todo.push(next.clone());
+                nfa.push((state.clone(), atom((*req).clone(), (*resp).clone()), next));
+            }
+        }
+
+        let mut starts = self.initial_globals.iter();
+        let first = nfa_to_kleene(&nfa, start(starts.next().unwrap()));
+        starts.fold(first, |acc, g| acc.plus(nfa_to_kleene(&nfa, start(g))))
     }
 
     pub fn serialized_automaton_regex(&self) -> Regex<String> {
@@ -375,7 +557,7 @@ where
         let globals = self.get_global_states();
         for global in globals {
             // Check if this is the initial global state
-            let is_initial = &self.initial_global == global;
+            let is_initial = self.initial_globals.contains(global);
 
             // Create properly escaped IDs and labels
             let global_id = format!("G_{}", escape_for_graphviz_id(&format!("{}", global)));
@@ -453,21 +635,99 @@ where
         for (l, resp) in &other.responses {
             self.add_response(l.clone(), resp.clone());
         }
+
+        for resp in &other.violations {
+            self.add_violation(resp.clone());
+        }
+
+        for resp in &other.crashes {
+            self.add_crash(resp.clone());
+        }
+
+        for session in &other.sessions {
+            if !self.sessions.contains(session) {
+                self.add_session(session.clone());
+            }
+        }
     }
 
     /// Check if a trace can be executed by this NS
     /// Returns Ok(multiset of (request, response) pairs) if valid and no requests in flight
     /// Returns Err(message) if invalid or if requests remain in flight
+    /// With several initial global states the trace may start from any of them
     pub fn check_trace(
         &self,
         trace: &crate::ns_decision::NSTrace<G, L, Req, Resp>,
     ) -> Result<Vec<(Req, Resp)>, String> {
+        let mut result = Err("No initial global state".to_string());
+        for initial_global in &self.initial_globals {
+            result = self
+                .replay_trace_from(initial_global, trace)
+                .and_then(|(completed, in_flight)| {
+                    // Check that no requests remain in flight
+                    if in_flight.is_empty() {
+                        return Ok(completed);
+                    }
+                    let in_flight_str: Vec<String> = in_flight
+                        .iter()
+                        .map(|(req, local)| format!("({}, {})", req, local))
+                        .collect();
+                    Err(format!(
+                        "Requests still in flight at end of trace: [{}]",
+                        in_flight_str.join(", ")
+                    ))
+                });
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
+
+    /// Check that a trace can be executed by this NS and ends with a request
+    /// responding with a failed assertion. Other requests may still be in flight.
+    /// Returns Ok((request, response)) of that last step.
+    pub fn check_violation_trace(
+        &self,
+        trace: &crate::ns_decision::NSTrace<G, L, Req, Resp>,
+    ) -> Result<(Req, Resp), String> {
+        use crate::ns_decision::NSStep;
+
+        let (request, response) = match trace.steps.last() {
+            Some(NSStep::RequestComplete { request, response, .. })
+                if self.violations.contains(response) =>
+            {
+                (request.clone(), response.clone())
+            }
+            _ => return Err("Trace does not end with a failed assertion".to_string()),
+        };
+        let mut result = Err("No initial global state".to_string());
+        for initial_global in &self.initial_globals {
+            result = self
+                .replay_trace_from(initial_global, trace)
+                .map(|_| (request.clone(), response.clone()));
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
+
+    /// Replay a trace from the given initial global state.
+    /// Returns Ok((completed requests, requests still in flight)) if every step is possible.
+    fn replay_trace_from(
+        &self,
+        initial_global: &G,
+        trace: &crate::ns_decision::NSTrace<G, L, Req, Resp>,
+    ) -> Result<Replay<Req, L, Resp>, String> {
         use crate::ns_decision::NSStep;
 
         // Initialize simulation state
-        let mut global_state = self.initial_global.clone();
+        let mut global_state = initial_global.clone();
         let mut in_flight: Vec<(Req, L)> = Vec::new(); // Multiset of active requests
         let mut completed: Vec<(Req, Resp)> = Vec::new(); // Multiset of completed requests
+        // Requests of sessions that their session may issue now
+        let mut ready: HashSet<&Req> = self.sessions.iter().filter_map(|session| session.first()).collect();
 
         // Process each step in the trace
         for (step_idx, step) in trace.steps.iter().enumerate() {
@@ -487,6 +747,13 @@ where
                         ));
                     }
 
+                    if self.session_position(request).is_some() && !ready.remove(request) {
+                        return Err(format!(
+                            "Step {}: Request {} starts before the previous request of its session responded, or twice",
+                            step_idx, request
+                        ));
+                    }
+
                     // Add to in-flight multiset
                     in_flight.push((request.clone(), initial_local.clone()));
                 }
@@ -567,23 +834,14 @@ where
 
                     // Add to completed multiset
                     completed.push((request.clone(), response.clone()));
+                    if let Some(next) = self.session_next(request) {
+                        ready.insert(next);
+                    }
                 }
             }
         }
 
-        // Check that no requests remain in flight
-        if !in_flight.is_empty() {
-            let in_flight_str: Vec<String> = in_flight
-                .iter()
-                .map(|(req, local)| format!("({}, {})", req, local))
-                .collect();
-            return Err(format!(
-                "Requests still in flight at end of trace: [{}]",
-                in_flight_str.join(", ")
-            ));
-        }
-
-        Ok(completed)
+        Ok((completed, in_flight))
     }
 }
 
@@ -597,6 +855,31 @@ where
     /// Check if the network system is serializable using both methods and report results
     #[must_use]
     pub fn is_serializable(&self, out_dir: &str) -> bool 
+    where
+        G: Clone + Ord + Hash + Display + std::fmt::Debug + serde::Serialize + for<'de> serde::Deserialize<'de>,
+        L: Clone + Ord + Hash + Display + std::fmt::Debug + serde::Serialize + for<'de> serde::Deserialize<'de>,
+        Req: Clone + Ord + Hash + Display + std::fmt::Debug + serde::Serialize + for<'de> serde::Deserialize<'de>,
+        Resp: Clone + Ord + Hash + Display + std::fmt::Debug + serde::Serialize + for<'de> serde::Deserialize<'de>,
+    {
+        self.check_and_report(None, out_dir)
+    }
+
+    /// Check if every execution of the network system ends with completed requests that
+    /// some serial execution of `spec` could also produce, and report results
+    #[must_use]
+    pub fn refines(&self, spec: &Self, out_dir: &str) -> bool
+    where
+        G: Clone + Ord + Hash + Display + std::fmt::Debug + serde::Serialize + for<'de> serde::Deserialize<'de>,
+        L: Clone + Ord + Hash + Display + std::fmt::Debug + serde::Serialize + for<'de> serde::Deserialize<'de>,
+        Req: Clone + Ord + Hash + Display + std::fmt::Debug + serde::Serialize + for<'de> serde::Deserialize<'de>,
+        Resp: Clone + Ord + Hash + Display + std::fmt::Debug + serde::Serialize + for<'de> serde::Deserialize<'de>,
+    {
+        self.check_and_report(Some(spec), out_dir)
+    }
+
+    /// Create, save, reload and verify a certificate against the serial executions of
+    /// `spec`, or of this network system itself if there is none, and print the result
+    fn check_and_report(&self, spec: Option<&Self>, out_dir: &str) -> bool
     where
         G: Clone + Ord + Hash + Display + std::fmt::Debug + serde::Serialize + for<'de> serde::Deserialize<'de>,
         L: Clone + Ord + Hash + Display + std::fmt::Debug + serde::Serialize + for<'de> serde::Deserialize<'de>,
@@ -605,7 +888,7 @@ where
     {
         // Create certificate with timing
         let decision = crate::stats::record_certificate_creation_time(|| {
-            self.create_certificate(out_dir)
+            self.certificate_against(spec, out_dir)
         });
         
         // Save certificate to standard location
@@ -626,7 +909,7 @@ where
         
         // Verify and return result with timing
         let result = crate::stats::record_certificate_checking_time(|| {
-            self.verify_ns_decision(&loaded_decision)
+            self.verify_against(spec, &loaded_decision)
         });
         
         // Print result with consistent formatting
@@ -635,11 +918,8 @@ where
             "{}",
             "────────────────────────────────────────────────────────────".bright_black()
         );
-        println!(
-            "{} {}",
-            "🔍".yellow(),
-            "SERIALIZABILITY ANALYSIS".yellow().bold()
-        );
+        let title = if spec.is_some() { "REFINEMENT ANALYSIS" } else { "SERIALIZABILITY ANALYSIS" };
+        println!("{} {}", "🔍".yellow(), title.yellow().bold());
         println!(
             "{}",
             "────────────────────────────────────────────────────────────".bright_black()
@@ -648,35 +928,20 @@ where
         // Print the semilinear set for compatibility
         println!();
         println!("Serialized automaton semilinear set:");
-        println!("{}", self.serialized_automaton_semilinear());
+        println!("{}", spec.unwrap_or(self).serialized_automaton_semilinear());
         
         // Print decision details
-        match &loaded_decision {
-            crate::ns_decision::NSDecision::Serializable { invariant } => {
-                println!();
-                println!("✅ PROOF CERTIFICATE FOUND");
-                println!();
-                invariant.pretty_print_with_verification(self);
-            }
-            crate::ns_decision::NSDecision::NotSerializable { trace } => {
-                println!();
-                println!("❌ COUNTEREXAMPLE TRACE FOUND");
-                println!();
-                trace.pretty_print(self);
-            }
-            crate::ns_decision::NSDecision::Timeout { message } => {
-                println!();
-                println!("⏱️ ANALYSIS TIMED OUT");
-                println!();
-                println!("{}", message);
-            }
-        }
+        self.print_decision(&loaded_decision, spec);
         
         // Determine the result and stats string based on decision type
-        let (result_emoji, result_text, stats_result) = match &loaded_decision {
-            crate::ns_decision::NSDecision::Serializable { .. } => ("✅", "SERIALIZABLE".green().bold(), "serializable"),
-            crate::ns_decision::NSDecision::NotSerializable { .. } => ("❌", "NOT SERIALIZABLE".red().bold(), "not_serializable"),
-            crate::ns_decision::NSDecision::Timeout { .. } => ("⏱️", "TIMEOUT".yellow().bold(), "timeout"),
+        let stats_result = loaded_decision.outcome();
+        let (result_emoji, result_text) = match (stats_result, spec) {
+            ("serializable", None) => ("✅", "SERIALIZABLE".green().bold()),
+            ("not_serializable", None) => ("❌", "NOT SERIALIZABLE".red().bold()),
+            ("serializable", Some(_)) => ("✅", "REFINES SPEC".green().bold()),
+            ("not_serializable", Some(_)) => ("❌", "DOES NOT REFINE SPEC".red().bold()),
+            ("assertion_violation", _) => ("❌", "ASSERTION VIOLATED".red().bold()),
+            _ => ("⏱️", "TIMEOUT".yellow().bold()),
         };
         
         println!();
@@ -700,17 +965,84 @@ where
         result
     }
 
+    fn print_decision(&self, decision: &crate::ns_decision::NSDecision<G, L, Req, Resp>, spec: Option<&Self>) {
+        match decision {
+            crate::ns_decision::NSDecision::Serializable { invariant } => {
+                println!();
+                println!("✅ PROOF CERTIFICATE FOUND");
+                println!();
+                invariant.pretty_print_with_verification(self, spec.unwrap_or(self));
+            }
+            crate::ns_decision::NSDecision::NotSerializable { trace } => {
+                println!();
+                println!("❌ COUNTEREXAMPLE TRACE FOUND");
+                println!();
+                trace.pretty_print(self);
+            }
+            crate::ns_decision::NSDecision::AssertionViolation { trace } => {
+                println!();
+                println!("❌ ASSERTION VIOLATION FOUND");
+                println!();
+                trace.pretty_print_violation(self);
+            }
+            crate::ns_decision::NSDecision::Timeout { message } => {
+                println!();
+                println!("⏱️ ANALYSIS TIMED OUT");
+                println!();
+                println!("{}", message);
+            }
+            crate::ns_decision::NSDecision::PerInitialGlobal { decisions } => {
+                for (initial_global, decision) in decisions {
+                    println!();
+                    println!("▶ STARTING FROM INITIAL GLOBAL STATE {}", initial_global);
+                    self.for_initial_global(initial_global).print_decision(decision, spec);
+                }
+            }
+        }
+    }
+
     /// Create a serializability certificate (NSDecision) without full visualization
-    pub fn create_certificate(&self, out_dir: &str) -> crate::ns_decision::NSDecision<G, L, Req, Resp>
-    where
-        G: Clone + Ord + Hash + Display + std::fmt::Debug,
-        L: Clone + Ord + Hash + Display + std::fmt::Debug,
-        Req: Clone + Ord + Hash + Display + std::fmt::Debug,
-        Resp: Clone + Ord + Hash + Display + std::fmt::Debug,
-    {
+    pub fn create_certificate(&self, out_dir: &str) -> crate::ns_decision::NSDecision<G, L, Req, Resp> {
+        self.certificate_against(None, out_dir)
+    }
+
+    /// Create a certificate that every execution of the network system ends with
+    /// completed requests that some serial execution of `spec` could also produce.
+    /// A counterexample is an execution whose completed requests no serial run of `spec` gives.
+    pub fn create_refinement_certificate(
+        &self,
+        spec: &Self,
+        out_dir: &str,
+    ) -> crate::ns_decision::NSDecision<G, L, Req, Resp> {
+        self.certificate_against(Some(spec), out_dir)
+    }
+
+    /// Create a certificate against the serial executions of `spec`, or of this
+    /// network system itself if there is none
+    fn certificate_against(&self, spec: Option<&Self>, out_dir: &str) -> crate::ns_decision::NSDecision<G, L, Req, Resp> {
         use crate::ns_to_petri::*;
         use ReqPetriState::*;
 
+        // Each initial global state is checked on its own, since serial
+        // executions must start from the same state as the interleaved ones.
+        // A separate spec has its own global states, so its serial executions may
+        // start from any of its initial global states.
+        if self.initial_globals.len() > 1 {
+            let decisions = self
+                .initial_globals
+                .iter()
+                .enumerate()
+                .map(|(i, initial_global)| {
+                    let start_dir = format!("{}/initial_{}", out_dir, i);
+                    let decision = self
+                        .for_initial_global(initial_global)
+                        .certificate_against(spec, &start_dir);
+                    (initial_global.clone(), decision)
+                })
+                .collect();
+            return crate::ns_decision::NSDecision::PerInitialGlobal { decisions };
+        }
+
         // Initialize debug logger
         let program_name = std::path::Path::new(out_dir)
             .file_name()
@@ -723,12 +1055,18 @@ where
             format!("Network System: {:?}", self),
         );
 
+        // A reachable failed assertion is reported instead of a serializability result
+        if let Some(decision) = self.check_assertions(out_dir) {
+            return decision;
+        }
+
         // Convert to Petri net
         let mut places_that_must_be_zero = HashSet::default();
         let petri = ns_to_petri_with_requests(self).rename(|st| match st {
             Response(_, _) => Right(st),
-            Global(_) => Left(st),
-            Local(_, _) | Request(_) => {
+            // A session may stop before it issued all of its requests
+            Global(_) | Request(_) => Left(st),
+            Local(_, _) => {
                 places_that_must_be_zero.insert(st.clone());
                 Left(st)
             }
@@ -736,7 +1074,7 @@ where
         let places_that_must_be_zero: Vec<_> = places_that_must_be_zero.into_iter().collect();
 
         // Create serialized automaton semilinear set
-        let ser: SemilinearSet<_> = self.serialized_automaton_kleene(|req, resp| {
+        let ser: SemilinearSet<_> = spec.unwrap_or(self).serialized_automaton_kleene(|req, resp| {
             SemilinearSet::singleton(SparseVector::unit(Response(req, resp)))
         });
         
@@ -767,19 +1105,62 @@ where
         crate::ns_decision::petri_decision_to_ns(result_with_proofs, self)
     }
 
+    /// Look for an execution in which a request responds with a failed assertion.
+    /// Returns None if there are no assertions or none of them can fail.
+    fn check_assertions(&self, out_dir: &str) -> Option<crate::ns_decision::NSDecision<G, L, Req, Resp>> {
+        use crate::ns_to_petri::*;
+        use crate::spresburger::SPresburgerSet;
+        use ReqPetriState::*;
+
+        if self.violations.is_empty() {
+            return None;
+        }
+
+        // Same net as for serializability, but requests may stay in flight
+        let petri = ns_to_petri_with_requests(self).rename(|st| match st {
+            Response(_, _) => Right(st),
+            _ => Left(st),
+        });
+
+        // Some request responded with a violation; every other place is unconstrained
+        let universe = SPresburgerSet::universe(petri.get_places());
+        let mut target = SPresburgerSet::empty();
+        for req in self.get_requests() {
+            for violation in &self.violations {
+                let place = Right(Response(req.clone(), violation.clone()));
+                target = target.union(SPresburgerSet::atom(place).times(universe.clone()));
+            }
+        }
+
+        let decision = crate::reachability_with_proofs::can_reach_presburger(
+            petri,
+            target,
+            &format!("{}/assertions", out_dir),
+        );
+        crate::ns_decision::petri_violation_to_ns(decision, self)
+    }
+
     /// Verify an NSDecision against this Network System
     /// Returns true if the system is serializable based on the decision
-    pub fn verify_ns_decision(&self, decision: &crate::ns_decision::NSDecision<G, L, Req, Resp>) -> bool
-    where
-        G: Clone + Ord + Hash + Display + std::fmt::Debug,
-        L: Clone + Ord + Hash + Display + std::fmt::Debug,
-        Req: Clone + Ord + Hash + Display + std::fmt::Debug,
-        Resp: Clone + Ord + Hash + Display + std::fmt::Debug,
-    {
+    pub fn verify_ns_decision(&self, decision: &crate::ns_decision::NSDecision<G, L, Req, Resp>) -> bool {
+        self.verify_against(None, decision)
+    }
+
+    /// Verify a certificate from `create_refinement_certificate`
+    /// Returns true if the system refines `spec` based on the decision
+    pub fn verify_refinement_decision(
+        &self,
+        spec: &Self,
+        decision: &crate::ns_decision::NSDecision<G, L, Req, Resp>,
+    ) -> bool {
+        self.verify_against(Some(spec), decision)
+    }
+
+    fn verify_against(&self, spec: Option<&Self>, decision: &crate::ns_decision::NSDecision<G, L, Req, Resp>) -> bool {
         match decision {
             crate::ns_decision::NSDecision::Serializable { invariant } => {
                 // If we have a valid proof, the system is serializable
-                invariant.check_proof(self).is_ok()
+                invariant.check_refinement_proof(self, spec.unwrap_or(self)).is_ok()
             }
             crate::ns_decision::NSDecision::NotSerializable { trace } => {
                 // If we have a valid counterexample trace, the system is NOT serializable
@@ -792,15 +1173,34 @@ where
                     false
                 }
             }
+            crate::ns_decision::NSDecision::AssertionViolation { trace } => {
+                // A valid violation trace means the program is broken regardless of serializability
+                if let Err(err) = self.check_violation_trace(trace) {
+                    eprintln!("Warning: Invalid assertion violation trace found in certificate: {}", err);
+                }
+                false
+            }
             crate::ns_decision::NSDecision::Timeout { .. } => {
                 // Timeout means we cannot determine serializability
                 eprintln!("Warning: Analysis timed out - cannot determine serializability");
                 false
             }
+            crate::ns_decision::NSDecision::PerInitialGlobal { decisions } => {
+                // Every initial global state must be covered by a valid decision
+                self.initial_globals.iter().all(|initial_global| {
+                    decisions.iter().any(|(g, decision)| {
+                        g == initial_global
+                            && self.for_initial_global(g).verify_against(spec, decision)
+                    })
+                })
+            }
         }
     }
 }
 
+/// Requests completed by a replayed trace, and the requests still in flight
+type Replay<Req, L, Resp> = (Vec<(Req, Resp)>, Vec<(Req, L)>);
+
 fn display_vec<T: Display>(v: &[T]) -> String {
     v.iter()
         .map(|x| x.to_string())
@@ -1023,6 +1423,61 @@ mod tests {
         assert_eq!(ns.transitions.len(), 2);
     }
 
+    #[test]
+    fn test_multiple_initial_globals() {
+        use crate::ns_decision::{NSStep, NSTrace};
+
+        // Both the legacy single field and the list are accepted
+        let legacy = r#"{"initial_global": "G0", "requests": [], "responses": [], "transitions": []}"#;
+        let ns = NS::<String, String, String, String>::from_json(legacy).unwrap();
+        assert_eq!(ns.initial_globals, vec!["G0".to_string()]);
+        let many = r#"{"initial_globals": ["G0", "G1"], "requests": [], "responses": [], "transitions": []}"#;
+        let ns = NS::<String, String, String, String>::from_json(many).unwrap();
+        assert_eq!(ns.initial_globals.len(), 2);
+        assert_eq!(ns.get_global_states().len(), 2);
+
+        // The same request behaves differently depending on the start state
+        let mut ns = NS::<String, String, String, String>::new("G0".to_string());
+        ns.add_initial_global("G1".to_string());
+        ns.add_initial_global("G1".to_string());
+        ns.add_request("Req".to_string(), "L0".to_string());
+        ns.add_transition("L0".to_string(), "G0".to_string(), "L1".to_string(), "G0".to_string());
+        ns.add_transition("L0".to_string(), "G1".to_string(), "L2".to_string(), "G1".to_string());
+        ns.add_response("L1".to_string(), "A".to_string());
+        ns.add_response("L2".to_string(), "B".to_string());
+        assert_eq!(ns.initial_globals.len(), 2);
+
+        let trace = NSTrace {
+            steps: vec![
+                NSStep::RequestStart {
+                    request: "Req".to_string(),
+                    initial_local: "L0".to_string(),
+                },
+                NSStep::InternalStep {
+                    request: "Req".to_string(),
+                    from_local: "L0".to_string(),
+                    from_global: "G1".to_string(),
+                    to_local: "L2".to_string(),
+                    to_global: "G1".to_string(),
+                },
+                NSStep::RequestComplete {
+                    request: "Req".to_string(),
+                    final_local: "L2".to_string(),
+                    response: "B".to_string(),
+                },
+            ],
+        };
+        assert!(ns.check_trace(&trace).is_ok());
+        assert!(ns.for_initial_global(&"G0".to_string()).check_trace(&trace).is_err());
+        assert!(ns.for_initial_global(&"G1".to_string()).check_trace(&trace).is_ok());
+
+        // Serial executions start from either state
+        let serial = ns.serialized_automaton_regex().to_string();
+        assert!(serial.contains("Req/A") && serial.contains("Req/B"));
+        let serial = ns.for_initial_global(&"G0".to_string()).serialized_automaton_regex().to_string();
+        assert!(!serial.contains("Req/B"));
+    }
+
     #[test]
     fn test_ns_build_and_serialize() {
         let mut ns = NS::<String, String, String, String>::new("EmptySession".to_string());
@@ -1062,6 +1517,55 @@ mod tests {
         assert_eq!(ns.transitions.len(), ns2.transitions.len());
     }
 
+    #[test]
+    fn test_check_violation_trace() {
+        use crate::ns_decision::{NSStep, NSTrace};
+
+        let mut ns = NS::<String, String, String, String>::new("G0".to_string());
+        ns.add_request("Req".to_string(), "L0".to_string());
+        ns.add_transition("L0".to_string(), "G0".to_string(), "L1".to_string(), "G1".to_string());
+        ns.add_transition("L0".to_string(), "G1".to_string(), "Bad".to_string(), "G1".to_string());
+        ns.add_response("Bad".to_string(), "failed".to_string());
+        ns.add_violation("failed".to_string());
+
+        // The first request is still in flight when the second one fails
+        let trace = NSTrace {
+            steps: vec![
+                NSStep::RequestStart { request: "Req".to_string(), initial_local: "L0".to_string() },
+                NSStep::InternalStep {
+                    request: "Req".to_string(),
+                    from_local: "L0".to_string(),
+                    from_global: "G0".to_string(),
+                    to_local: "L1".to_string(),
+                    to_global: "G1".to_string(),
+                },
+                NSStep::RequestStart { request: "Req".to_string(), initial_local: "L0".to_string() },
+                NSStep::InternalStep {
+                    request: "Req".to_string(),
+                    from_local: "L0".to_string(),
+                    from_global: "G1".to_string(),
+                    to_local: "Bad".to_string(),
+                    to_global: "G1".to_string(),
+                },
+                NSStep::RequestComplete {
+                    request: "Req".to_string(),
+                    final_local: "Bad".to_string(),
+                    response: "failed".to_string(),
+                },
+            ],
+        };
+        assert_eq!(
+            ns.check_violation_trace(&trace),
+            Ok(("Req".to_string(), "failed".to_string()))
+        );
+        assert!(ns.check_trace(&trace).is_err());
+
+        // A trace must end with the violation
+        let mut shorter = trace.clone();
+        shorter.steps.pop();
+        assert!(ns.check_violation_trace(&shorter).is_err());
+    }
+
     #[test]
     fn test_check_trace() {
         use crate::ns_decision::{NSStep, NSTrace};
@@ -1419,6 +1923,110 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_serialized_automaton_crash() {
+        let mut ns = NS::<String, String, String, String>::new("G0".to_string());
+        ns.add_request("Req1".to_string(), "L0".to_string());
+        ns.add_response("L2".to_string(), "RespA".to_string());
+        ns.add_response("Crashed".to_string(), "crashed".to_string());
+        ns.add_crash("crashed".to_string());
+
+        // Req1 runs in two steps and may crash in between
+        ns.add_transition("L0".to_string(), "G0".to_string(), "L1".to_string(), "G1".to_string());
+        ns.add_transition("L1".to_string(), "G1".to_string(), "L2".to_string(), "G2".to_string());
+        ns.add_transition("L1".to_string(), "G1".to_string(), "Crashed".to_string(), "G1".to_string());
+
+        // Req2 cannot crash
+        ns.add_request("Req2".to_string(), "M0".to_string());
+        ns.add_transition("M0".to_string(), "G2".to_string(), "M1".to_string(), "G0".to_string());
+        ns.add_response("M1".to_string(), "RespB".to_string());
+
+        // The crash counts as no run or as the complete run, never as the half run to G1
+        let automaton: HashSet<_> = ns.serialized_automaton().into_iter().collect();
+        let edge = |g: &str, req: &str, resp: &str, g2: &str| {
+            (g.to_string(), req.to_string(), resp.to_string(), g2.to_string())
+        };
+        let expected: HashSet<_> = [
+            edge("G0", "Req1", "RespA", "G2"),
+            edge("G0", "Req1", "crashed", "G2"),
+            edge("G0", "Req1", "crashed", "G0"),
+            edge("G1", "Req1", "crashed", "G1"),
+            edge("G2", "Req1", "crashed", "G2"),
+            edge("G2", "Req2", "RespB", "G0"),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(automaton, expected);
+    }
+
+    #[test]
+    fn test_sessions() {
+        use crate::ns_decision::{NSStep, NSTrace};
+
+        // `set` writes 1 and `get` reads the global
+        let mut ns = NS::<String, String, String, String>::new("0".to_string());
+        ns.add_request("set".to_string(), "S".to_string());
+        ns.add_transition("S".to_string(), "0".to_string(), "S0".to_string(), "1".to_string());
+        ns.add_transition("S".to_string(), "1".to_string(), "S0".to_string(), "1".to_string());
+        ns.add_response("S0".to_string(), "0".to_string());
+        ns.add_request("get".to_string(), "G".to_string());
+        for g in ["0", "1"] {
+            ns.add_transition("G".to_string(), g.to_string(), format!("G{}", g), g.to_string());
+            ns.add_response(format!("G{}", g), g.to_string());
+        }
+        assert!(ns.serialized_automaton_regex().to_string().contains("get/0"));
+
+        // A client that sets and then gets can only read 1
+        ns.add_session(vec!["set".to_string(), "get".to_string()]);
+        assert_eq!(ns.session_position(&"get".to_string()), Some((0, 1)));
+        assert_eq!(ns.session_next(&"set".to_string()), Some(&"get".to_string()));
+        let serial = ns.serialized_automaton_regex().to_string();
+        assert!(serial.contains("get/1") && !serial.contains("get/0"), "{}", serial);
+
+        // Traces must keep the order of the session
+        let start = |req: &str, local: &str| NSStep::RequestStart {
+            request: req.to_string(),
+            initial_local: local.to_string(),
+        };
+        let step = |req: &str, from: &str, g: &str, to: &str, g2: &str| NSStep::InternalStep {
+            request: req.to_string(),
+            from_local: from.to_string(),
+            from_global: g.to_string(),
+            to_local: to.to_string(),
+            to_global: g2.to_string(),
+        };
+        let complete = |req: &str, local: &str, resp: &str| NSStep::RequestComplete {
+            request: req.to_string(),
+            final_local: local.to_string(),
+            response: resp.to_string(),
+        };
+        let in_order = NSTrace {
+            steps: vec![
+                start("set", "S"),
+                step("set", "S", "0", "S0", "1"),
+                complete("set", "S0", "0"),
+                start("get", "G"),
+                step("get", "G", "1", "G1", "1"),
+                complete("get", "G1", "1"),
+            ],
+        };
+        assert!(ns.check_trace(&in_order).is_ok());
+        let overlapping = NSTrace {
+            steps: vec![start("set", "S"), start("get", "G")],
+        };
+        let err = ns.check_trace(&overlapping).unwrap_err();
+        assert!(err.contains("before the previous request of its session"), "{}", err);
+        let twice = NSTrace {
+            steps: vec![
+                start("set", "S"),
+                step("set", "S", "0", "S0", "1"),
+                complete("set", "S0", "0"),
+                start("set", "S"),
+            ],
+        };
+        assert!(ns.check_trace(&twice).is_err());
+    }
+
     #[test]
     fn test_graphviz_output() {
         let mut ns = NS::<String, String, String, String>::new("NoSession".to_string());
@@ -48,6 +48,8 @@ pub enum RequestState<L, Resp> {
     InFlight(L),
     /// Request completed with response Resp
     Completed(Resp),
+    /// Request of a session that the session may issue next
+    Ready,
 }
 
 impl<L: Display, Resp: Display> Display for RequestState<L, Resp> {
@@ -55,6 +57,7 @@ impl<L: Display, Resp: Display> Display for RequestState<L, Resp> {
         match self {
             RequestState::InFlight(l) => write!(f, "InFlight({})", l),
             RequestState::Completed(resp) => write!(f, "Completed({})", resp),
+            RequestState::Ready => write!(f, "Ready"),
         }
     }
 }
@@ -68,10 +71,33 @@ impl<Req: Display, L: Display, Resp: Display> Display for RequestStatePair<Req,
         match &self.1 {
             RequestState::InFlight(l) => write!(f, "{}{}", self.0, l),
             RequestState::Completed(resp) => write!(f, "{}/{}", self.0, resp),
+            RequestState::Ready => write!(f, "{}?", self.0),
         }
     }
 }
 
+/// The invariant with the Ready requests of sessions existentially quantified, for
+/// questions about the requests in flight and completed only
+fn exists_ready<Req, L, Resp>(
+    invariant: &ProofInvariant<RequestStatePair<Req, L, Resp>>,
+) -> ProofInvariant<RequestStatePair<Req, L, Resp>>
+where
+    Req: Clone + Eq + Hash,
+    L: Clone + Eq + Hash,
+    Resp: Clone + Eq + Hash,
+{
+    let mut formula = invariant.formula.clone();
+    let mut variables = Vec::new();
+    for var in &invariant.variables {
+        if var.1 == RequestState::Ready {
+            formula = formula.mk_exists(var.clone());
+        } else {
+            variables.push(var.clone());
+        }
+    }
+    ProofInvariant { variables, formula }
+}
+
 /// Wrapper struct for (Req, Resp) pairs to implement Display
 #[derive(Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub struct CompletedRequestPair<Req, Resp>(pub Req, pub Resp);
@@ -119,12 +145,77 @@ where
 {
     /// Pretty print the NS trace
     pub fn pretty_print(&self, ns: &NS<G, L, Req, Resp>) {
+        if !self.print_steps() {
+            return;
+        }
+
+        // Run trace validation and display results
+        println!("\n==============================");
+        println!("Trace Validation:");
+        println!("==============================");
+
+        match ns.check_trace(self) {
+            Ok(completed_pairs) => {
+                println!("✅ Trace is valid!");
+
+                // Display completed request/response multiset
+                println!("\nCompleted Request/Response Pairs:");
+                if completed_pairs.is_empty() {
+                    println!("  (none)");
+                } else {
+                    // Count occurrences of each pair for multiset display
+                    let mut counts: HashMap<(Req, Resp), usize> = HashMap::default();
+                    for (req, resp) in completed_pairs {
+                        *counts.entry((req, resp)).or_insert(0) += 1;
+                    }
+
+                    // Display with multiplicity
+                    for ((req, resp), count) in counts {
+                        if count == 1 {
+                            println!("  {}/{}", req, resp);
+                        } else {
+                            println!("  ({}/{})^{}", req, resp, count);
+                        }
+                    }
+                }
+            }
+            Err(error) => {
+                println!("❌ Trace validation failed!");
+                println!("Error: {}", error);
+            }
+        }
+    }
+
+    /// Pretty print a trace that ends with a failed assertion
+    pub fn pretty_print_violation(&self, ns: &NS<G, L, Req, Resp>) {
+        if !self.print_steps() {
+            return;
+        }
+
+        println!("\n==============================");
+        println!("Trace Validation:");
+        println!("==============================");
+
+        match ns.check_violation_trace(self) {
+            Ok((req, resp)) => {
+                println!("✅ Trace is valid!");
+                println!("\nFailed assertion: {}/{}", req, resp);
+            }
+            Err(error) => {
+                println!("❌ Trace validation failed!");
+                println!("Error: {}", error);
+            }
+        }
+    }
+
+    /// Print the steps of the trace; returns false if there are none
+    fn print_steps(&self) -> bool {
         println!("NS-Level Counterexample Trace:");
         println!("==============================");
 
         if self.steps.is_empty() {
             println!("(Empty trace - violation at initial state)");
-            return;
+            return false;
         }
 
         for (i, step) in self.steps.iter().enumerate() {
@@ -163,42 +254,7 @@ where
                 }
             }
         }
-
-        // Run trace validation and display results
-        println!("\n==============================");
-        println!("Trace Validation:");
-        println!("==============================");
-
-        match ns.check_trace(self) {
-            Ok(completed_pairs) => {
-                println!("✅ Trace is valid!");
-
-                // Display completed request/response multiset
-                println!("\nCompleted Request/Response Pairs:");
-                if completed_pairs.is_empty() {
-                    println!("  (none)");
-                } else {
-                    // Count occurrences of each pair for multiset display
-                    let mut counts: HashMap<(Req, Resp), usize> = HashMap::default();
-                    for (req, resp) in completed_pairs {
-                        *counts.entry((req, resp)).or_insert(0) += 1;
-                    }
-
-                    // Display with multiplicity
-                    for ((req, resp), count) in counts {
-                        if count == 1 {
-                            println!("  {}/{}", req, resp);
-                        } else {
-                            println!("  ({}/{})^{}", req, resp, count);
-                        }
-                    }
-                }
-            }
-            Err(error) => {
-                println!("❌ Trace validation failed!");
-                println!("Error: {}", error);
-            }
-        }
+        true
     }
 }
 
@@ -217,8 +273,14 @@ where
     },
     /// Program is not serializable with counterexample trace
     NotSerializable { trace: NSTrace<G, L, Req, Resp> },
+    /// A request can respond with a failed assertion; the trace ends with that response
+    AssertionViolation { trace: NSTrace<G, L, Req, Resp> },
     /// Analysis timed out
     Timeout { message: String },
+    /// One decision per initial global state, for systems with several of them
+    PerInitialGlobal {
+        decisions: Vec<(G, NSDecision<G, L, Req, Resp>)>,
+    },
 }
 
 impl<G, L, Req, Resp> NSDecision<G, L, Req, Resp>
@@ -228,6 +290,29 @@ where
     Req: Eq + Hash,
     Resp: Eq + Hash,
 {
+    /// Overall outcome: "serializable", "not_serializable", "assertion_violation" or "timeout".
+    /// A per-initial-state decision is serializable only if every start is.
+    pub fn outcome(&self) -> &'static str {
+        match self {
+            NSDecision::Serializable { .. } => "serializable",
+            NSDecision::NotSerializable { .. } => "not_serializable",
+            NSDecision::AssertionViolation { .. } => "assertion_violation",
+            NSDecision::Timeout { .. } => "timeout",
+            NSDecision::PerInitialGlobal { decisions } => {
+                let outcomes: Vec<_> = decisions.iter().map(|(_, d)| d.outcome()).collect();
+                if outcomes.contains(&"assertion_violation") {
+                    "assertion_violation"
+                } else if outcomes.contains(&"not_serializable") {
+                    "not_serializable"
+                } else if outcomes.contains(&"timeout") {
+                    "timeout"
+                } else {
+                    "serializable"
+                }
+            }
+        }
+    }
+
     /// Save the NSDecision to a JSON file
     /// This method properly serializes the decision using serde
     pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), std::io::Error> 
@@ -305,7 +390,7 @@ where
             .get(global_state)
             .map(|full_invariant| {
                 // Create a projection that maps InFlight to 0 and Completed to the pair
-                full_invariant.substitute(|pair| {
+                exists_ready(full_invariant).substitute(|pair| {
                     match &pair.1 {
                         RequestState::InFlight(_) => {
                             // Map InFlight requests to 0
@@ -315,6 +400,7 @@ where
                             // Map Completed requests to CompletedRequestPair
                             Either::Left(CompletedRequestPair(pair.0.clone(), resp.clone()))
                         }
+                        RequestState::Ready => unreachable!("Ready requests are quantified away"),
                     }
                 })
             })
@@ -357,8 +443,9 @@ where
         }
     }
 
-    /// Pretty print the NS invariant with proof verification results
-    pub fn pretty_print_with_verification(&self, ns: &NS<G, L, Req, Resp>)
+    /// Pretty print the NS invariant with proof verification results.
+    /// The serial executions come from `spec`, which is `ns` itself unless checking refinement.
+    pub fn pretty_print_with_verification(&self, ns: &NS<G, L, Req, Resp>, spec: &NS<G, L, Req, Resp>)
     where
         G: Clone + Display + Eq + Hash + Ord + Debug + ToString,
         L: Clone + Display + Eq + Hash + Ord + Debug + ToString,
@@ -371,7 +458,7 @@ where
         println!("Proof Certificate Verification:");
         println!("=====================================");
 
-        match self.check_proof(ns) {
+        match self.check_refinement_proof(ns, spec) {
             Ok(()) => {
                 println!("✅ Proof certificate is VALID");
                 println!("  ✓ Initial state satisfies the invariant");
@@ -394,6 +481,26 @@ where
         Req: Clone + Display + Eq + Hash + Ord + Debug + ToString,
         Resp: Clone + Display + Eq + Hash + Ord + Debug + ToString,
     {
+        self.check_refinement_proof(ns, ns)
+    }
+
+    /// Check that the proof certificate shows that every execution of `ns` ends with
+    /// completed requests that some serial execution of `spec` could also produce
+    pub fn check_refinement_proof(&self, ns: &NS<G, L, Req, Resp>, spec: &NS<G, L, Req, Resp>) -> Result<(), String>
+    where
+        G: Clone + Display + Eq + Hash + Ord + Debug + ToString,
+        L: Clone + Display + Eq + Hash + Ord + Debug + ToString,
+        Req: Clone + Display + Eq + Hash + Ord + Debug + ToString,
+        Resp: Clone + Display + Eq + Hash + Ord + Debug + ToString,
+    {
+        // An invariant speaks about a single start state
+        if ns.initial_globals.len() != 1 {
+            return Err(format!(
+                "Invariant covers one initial global state, but the NS has {}",
+                ns.initial_globals.len()
+            ));
+        }
+
         // Check 1: Initial state satisfies the invariant
         self.check_initial_state(ns)?;
 
@@ -401,7 +508,7 @@ where
         self.check_inductive(ns)?;
 
         // Check 3: Invariant implies target (serializability)
-        self.check_implies_target(ns)?;
+        self.check_implies_target(spec)?;
 
         Ok(())
     }
@@ -417,19 +524,22 @@ where
         // Get the invariant for the initial global state
         let initial_invariant =
             self.global_invariants
-                .get(&ns.initial_global)
+                .get(ns.initial_global())
                 .ok_or_else(|| {
                     format!(
                         "No invariant found for initial global state: {}",
-                        ns.initial_global
+                        ns.initial_global()
                     )
                 })?;
 
         // Initial state has empty multiset (no requests in flight or completed)
-        // This means all variables in the formula should be substituted with 0
-        let mut mapping = |_var: &RequestStatePair<Req, L, Resp>| -> Either<String, i32> {
-            // All variables map to 0 in the empty multiset
-            Either::Right(0)
+        // This means all variables in the formula should be substituted with 0,
+        // except that every session is ready to issue its first request
+        let mut mapping = |var: &RequestStatePair<Req, L, Resp>| -> Either<String, i32> {
+            match ns.session_position(&var.0) {
+                Some((_, 0)) if var.1 == RequestState::Ready => Either::Right(1),
+                _ => Either::Right(0),
+            }
         };
         let substituted_invariant: ProofInvariant<String> =
             initial_invariant.substitute(&mut mapping);
@@ -494,11 +604,11 @@ where
         for (req, initial_local) in &ns.requests {
             let initial_inv = self
                 .global_invariants
-                .get(&ns.initial_global)
+                .get(ns.initial_global())
                 .ok_or_else(|| {
                     format!(
                         "No invariant for initial global state: {}",
-                        ns.initial_global
+                        ns.initial_global()
                     )
                 })?;
 
@@ -509,7 +619,15 @@ where
             let initial_inv_either: ProofInvariant<Either<usize, RequestStatePair<Req, L, Resp>>> =
                 initial_inv.clone().map(|v| Either::Right(v.clone()));
 
-            let inv_after_add = initial_inv_either.add_one(&new_var);
+            // A request of a session takes the turn of its session
+            let inv_after_start = match ns.session_position(req) {
+                Some(_) => initial_inv_either
+                    .filter_and_subtract_one(&RequestStatePair(req.clone(), RequestState::Ready))
+                    .project_right()
+                    .map(Either::Right),
+                None => initial_inv_either,
+            };
+            let inv_after_add = inv_after_start.add_one(&new_var);
             let inv_after_creation = inv_after_add.project_right();
 
             // Check if creating a new request preserves the initial state invariant
@@ -542,9 +660,14 @@ where
                         Either<usize, RequestStatePair<Req, L, Resp>>,
                     > = global_inv.clone().map(|v| Either::Right(v.clone()));
 
-                    // Apply completion: remove inflight, add completed
+                    // Apply completion: remove inflight, add completed, and let the
+                    // session issue its next request
                     let inv_after_remove = global_inv_either.filter_and_subtract_one(&inflight_var);
-                    let inv_after_add = inv_after_remove.add_one(&completed_var);
+                    let mut inv_after_add = inv_after_remove.add_one(&completed_var);
+                    if let Some(next) = ns.session_next(req) {
+                        let ready_var = RequestStatePair(next.clone(), RequestState::Ready);
+                        inv_after_add = inv_after_add.project_right().map(Either::Right).add_one(&ready_var);
+                    }
                     let inv_after_completion = inv_after_add.project_right();
 
                     // Check if completion preserves the same global state invariant
@@ -596,8 +719,8 @@ where
     }
 
     /// Check that the invariant implies the target property (serializability)
-    /// When there are no in-flight requests, completed requests must form a serializable execution
-    fn check_implies_target(&self, ns: &NS<G, L, Req, Resp>) -> Result<(), String>
+    /// When there are no in-flight requests, completed requests must form a serial execution of `spec`
+    fn check_implies_target(&self, spec: &NS<G, L, Req, Resp>) -> Result<(), String>
     where
         G: Clone + Display + Eq + Hash + Ord + Debug + ToString,
         L: Clone + Display + Eq + Hash + Ord + Debug + ToString,
@@ -608,7 +731,7 @@ where
         // This uses Response(Req, Resp) as the type
         use crate::ns_to_petri::ReqPetriState;
         let serializable_set: crate::semilinear::SemilinearSet<_> =
-            ns.serialized_automaton_kleene(|req, resp| {
+            spec.serialized_automaton_kleene(|req, resp| {
                 crate::semilinear::SemilinearSet::singleton(crate::semilinear::SparseVector::unit(
                     ReqPetriState::Response(req, resp),
                 ))
@@ -616,7 +739,8 @@ where
 
         // Check each global state
         for (global_state, invariant) in &self.global_invariants {
-            // Substitute: InFlight -> 0, Completed -> Response(Req, Resp)
+            // Substitute: InFlight -> 0, Completed -> Response(Req, Resp); sessions may
+            // still have any requests left to issue
             let mut mapping = |pair: &RequestStatePair<Req, L, Resp>| -> Either<ReqPetriState<L, G, Req, Resp>, i32> {
                 match &pair.1 {
                     RequestState::InFlight(_) => {
@@ -627,10 +751,11 @@ where
                         // Map completed requests to Response type used in semilinear set
                         Either::Left(ReqPetriState::Response(pair.0.clone(), resp.clone()))
                     }
+                    RequestState::Ready => unreachable!("Ready requests are quantified away"),
                 }
             };
 
-            let substituted_invariant = invariant.substitute(&mut mapping);
+            let substituted_invariant = exists_ready(invariant).substitute(&mut mapping);
 
             // Check if the invariant implies membership in the serializable set
             if !self.invariant_implies_semilinear(
@@ -733,11 +858,9 @@ where
                             RequestState::InFlight(l.clone()),
                         ))
                     }
-                    ReqPetriState::Request(_) => {
-                        // This is problematic, we don't yet support requests in the RequestStatePair
-                        // Need to think about how to fix this.
-                        // Maybe we should reachitect the data structures to make everything smoother.
-                        unreachable!("Request found in Left - not implemented yet!")
+                    ReqPetriState::Request(req) => {
+                        // The token of a session that may issue this request next
+                        Either::Left(RequestStatePair(req.clone(), RequestState::Ready))
                     }
                     ReqPetriState::Response(_, _) => {
                         panic!("Response found in Left - this should be unreachable!");
@@ -797,6 +920,13 @@ where
             }
         }
 
+        // And the requests that sessions may issue next
+        for session in &ns.sessions {
+            for req in session {
+                all_vars.insert(RequestStatePair(req.clone(), RequestState::Ready));
+            }
+        }
+
         // Convert to sorted vector for consistent ordering
         let mut additional_vars: Vec<_> = all_vars.into_iter().collect();
         additional_vars.sort_by(|a, b| format!("{}", a).cmp(&format!("{}", b)));
@@ -814,11 +944,13 @@ where
     NSInvariant { global_invariants }
 }
 
+/// Decision about the Petri net built by `ns_to_petri_with_requests`
+type ReqPetriDecision<G, L, Req, Resp> =
+    Decision<Either<ReqPetriState<L, G, Req, Resp>, ReqPetriState<L, G, Req, Resp>>>;
+
 /// Convert a Petri net Decision to an NS-level NSDecision
 pub fn petri_decision_to_ns<G, L, Req, Resp>(
-    petri_decision: Decision<
-        Either<ReqPetriState<L, G, Req, Resp>, ReqPetriState<L, G, Req, Resp>>,
-    >,
+    petri_decision: ReqPetriDecision<G, L, Req, Resp>,
     ns: &NS<G, L, Req, Resp>,
 ) -> NSDecision<G, L, Req, Resp>
 where
@@ -853,6 +985,27 @@ where
     }
 }
 
+/// Convert the Petri net Decision of the search for a failed assertion.
+/// Returns None if no assertion can fail, so serializability is checked next.
+pub fn petri_violation_to_ns<G, L, Req, Resp>(
+    petri_decision: ReqPetriDecision<G, L, Req, Resp>,
+    ns: &NS<G, L, Req, Resp>,
+) -> Option<NSDecision<G, L, Req, Resp>>
+where
+    G: Clone + Eq + Hash + Debug + Display,
+    L: Clone + Eq + Hash + Debug + Display,
+    Req: Clone + Eq + Hash + Debug + Display,
+    Resp: Clone + Eq + Hash + Debug + Display,
+{
+    match petri_decision {
+        Decision::Proof { .. } => None,
+        Decision::CounterExample { trace } => Some(NSDecision::AssertionViolation {
+            trace: convert_petri_trace_to_ns(trace, ns),
+        }),
+        Decision::Timeout { message } => Some(NSDecision::Timeout { message }),
+    }
+}
+
 /// Convert a Petri net trace to an NS-level trace
 fn convert_petri_trace_to_ns<G, L, Req, Resp>(
     petri_trace: Vec<(
@@ -870,7 +1023,14 @@ where
     let mut steps = Vec::new();
 
     // Analyze each transition in the Petri trace
-    for (inputs, outputs) in petri_trace {
+    for (mut inputs, mut outputs) in petri_trace {
+        // The turns of sessions follow from the requests that start and complete
+        let is_turn = |place: &Either<ReqPetriState<L, G, Req, Resp>, _>| {
+            matches!(place, Either::Left(ReqPetriState::Request(_)))
+        };
+        inputs.retain(|place| !is_turn(place));
+        outputs.retain(|place| !is_turn(place));
+
         // Case 1: Request creation (empty inputs, creates Local state)
         if inputs.is_empty() && outputs.len() == 1 {
             if let Some(Either::Left(ReqPetriState::Local(req, local))) = outputs.first() {
@@ -987,13 +1147,13 @@ mod tests {
         let local_expr = LocalExpr(env.clone(), expr);
         
         steps.push(NSStep::RequestStart {
-            request: ExprRequest { name: "foo".to_string() },
+            request: ExprRequest { name: "foo".to_string(), args: vec![], session: None },
             initial_local: local_expr.clone(),
         });
         
         // Step 2: Request complete
         steps.push(NSStep::RequestComplete {
-            request: ExprRequest { name: "foo".to_string() },
+            request: ExprRequest { name: "foo".to_string(), args: vec![], session: None },
             final_local: local_expr,
             response: 42,
         });
@@ -1035,7 +1195,7 @@ mod tests {
         let local_expr = LocalExpr(env, expr);
         
         steps.push(NSStep::RequestStart {
-            request: ExprRequest { name: "test_req".to_string() },
+            request: ExprRequest { name: "test_req".to_string(), args: vec![], session: None },
             initial_local: local_expr.clone(),
         });
         
@@ -1110,6 +1270,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_check_refinement_proof() {
+        // A request that always responds 1, checked against specs in which it
+        // responds 1 and 2 respectively
+        let mut ns = NS::<String, String, String, String>::new("G".to_string());
+        ns.add_request("r".to_string(), "L".to_string());
+        ns.add_response("L".to_string(), "1".to_string());
+        let mut other = NS::<String, String, String, String>::new("S".to_string());
+        other.add_request("r".to_string(), "M".to_string());
+        other.add_response("M".to_string(), "2".to_string());
+
+        // Any number of requests may be in flight or completed
+        let vars = vec![
+            RequestStatePair("r".to_string(), RequestState::InFlight("L".to_string())),
+            RequestStatePair("r".to_string(), RequestState::Completed("1".to_string())),
+        ];
+        let mut global_invariants = HashMap::default();
+        global_invariants.insert("G".to_string(), ProofInvariant::new(vars, Formula::And(vec![])));
+        let invariant = NSInvariant { global_invariants };
+
+        assert_eq!(invariant.check_proof(&ns), Ok(()));
+        assert_eq!(invariant.check_refinement_proof(&ns, &ns), Ok(()));
+        let err = invariant.check_refinement_proof(&ns, &other).unwrap_err();
+        assert!(err.contains("does not imply serializability"), "{}", err);
+    }
+
     #[test]
     fn test_invariant_implies_semilinear_empty_invariant() {
         use crate::kleene::Kleene;
@@ -54,8 +54,9 @@ where
     Resp: Clone + Eq + Hash + std::fmt::Display,
 {
     // Create a new Petri net with initial marking
-    // Start with one token for the initial global state
-    let initial_marking = vec![PetriState::Global(ns.initial_global.clone())];
+    // Start with one token for the initial global state; a system with several
+    // initial global states is split with NS::for_initial_global beforehand
+    let initial_marking = vec![PetriState::Global(ns.initial_global().clone())];
     let mut petri = Petri::new(initial_marking);
 
     // Create transitions for each request transition
@@ -139,28 +140,37 @@ where
     Resp: Clone + PartialEq + Eq + Hash + std::fmt::Display,
 {
     // Create a new Petri net with initial marking
-    // Start with one token for the initial global state
-    let initial_marking = vec![ReqPetriState::Global(ns.initial_global.clone())];
+    // Start with one token for the initial global state; a system with several
+    // initial global states is split with NS::for_initial_global beforehand
+    // A session has a token on the Request place of the request it may issue next;
+    // requests in no session can start at any time
+    let mut initial_marking = vec![ReqPetriState::Global(ns.initial_global().clone())];
+    for session in &ns.sessions {
+        if let Some(first) = session.first() {
+            initial_marking.push(ReqPetriState::Request(first.clone()));
+        }
+    }
 
     // Create a new Petri net with initial marking
     let mut petri = Petri::new(initial_marking);
 
     // Create transitions for each request transition
     for (req, local) in &ns.requests {
-        petri.add_transition(
-            // vec![ReqPetriState::Request(req.clone())],
-            vec![],
-            vec![ReqPetriState::Local(req.clone(), local.clone())],
-        );
+        let inputs = match ns.session_position(req) {
+            Some(_) => vec![ReqPetriState::Request(req.clone())],
+            None => vec![],
+        };
+        petri.add_transition(inputs, vec![ReqPetriState::Local(req.clone(), local.clone())]);
     }
 
-    // Create transitions for each response transition
+    // Create transitions for each response transition; a response lets the
+    // session of the request issue its next request
     for req in ns.get_requests() {
+        let next = ns.session_next(req);
         for (local, resp) in &ns.responses {
-            petri.add_transition(
-                vec![ReqPetriState::Local(req.clone(), local.clone())],
-                vec![ReqPetriState::Response(req.clone(), resp.clone())],
-            );
+            let mut outputs = vec![ReqPetriState::Response(req.clone(), resp.clone())];
+            outputs.extend(next.map(|next| ReqPetriState::Request(next.clone())));
+            petri.add_transition(vec![ReqPetriState::Local(req.clone(), local.clone())], outputs);
         }
     }
 
@@ -231,4 +241,31 @@ mod tests {
         // Verify transitions count (one for request, one for response, one for state transition)
         assert_eq!(petri.get_transitions().len(), 3);
     }
+
+    #[test]
+    fn test_ns_to_petri_with_sessions() {
+        let mut ns = NS::<String, String, String, String>::new("G".to_string());
+        ns.add_request("first".to_string(), "L1".to_string());
+        ns.add_request("second".to_string(), "L2".to_string());
+        ns.add_request("free".to_string(), "L3".to_string());
+        ns.add_response("L1".to_string(), "done".to_string());
+        ns.add_session(vec!["first".to_string(), "second".to_string()]);
+
+        let petri = ns_to_petri_with_requests(&ns);
+        let turn = |req: &str| ReqPetriState::Request(req.to_string());
+        let local = |req: &str, l: &str| ReqPetriState::Local(req.to_string(), l.to_string());
+        let response = |req: &str| ReqPetriState::Response(req.to_string(), "done".to_string());
+
+        // The session starts with the turn of its first request
+        let initial: HashSet<_> = petri.get_initial_marking().into_iter().collect();
+        assert_eq!(initial, HashSet::from([ReqPetriState::Global("G".to_string()), turn("first")]));
+
+        // Requests of the session take its turn, and responding passes it on
+        let transitions = petri.get_transitions();
+        assert!(transitions.contains(&(vec![turn("first")], vec![local("first", "L1")])));
+        assert!(transitions.contains(&(vec![turn("second")], vec![local("second", "L2")])));
+        assert!(transitions.contains(&(vec![], vec![local("free", "L3")])));
+        assert!(transitions.contains(&(vec![local("first", "L1")], vec![response("first"), turn("second")])));
+        assert!(transitions.contains(&(vec![local("second", "L1")], vec![response("second")])));
+    }
 }
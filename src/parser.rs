@@ -1,4 +1,6 @@
 use hash_cons::{Hc, HcTable};
+use std::cell::Cell;
+use crate::deterministic_map::{HashMap, HashSet};
 use std::fmt;
 use serde::{Serialize, Deserialize, Serializer, Deserializer};
 
@@ -8,31 +10,251 @@ pub enum Expr {
     Equal(#[serde(with = "hc_expr_serde")] Hc<Expr>, #[serde(with = "hc_expr_serde")] Hc<Expr>),
     Add(#[serde(with = "hc_expr_serde")] Hc<Expr>, #[serde(with = "hc_expr_serde")] Hc<Expr>),
     Subtract(#[serde(with = "hc_expr_serde")] Hc<Expr>, #[serde(with = "hc_expr_serde")] Hc<Expr>),
+    Multiply(#[serde(with = "hc_expr_serde")] Hc<Expr>, #[serde(with = "hc_expr_serde")] Hc<Expr>),
+    Divide(#[serde(with = "hc_expr_serde")] Hc<Expr>, #[serde(with = "hc_expr_serde")] Hc<Expr>),
+    Modulo(#[serde(with = "hc_expr_serde")] Hc<Expr>, #[serde(with = "hc_expr_serde")] Hc<Expr>),
+    NotEqual(#[serde(with = "hc_expr_serde")] Hc<Expr>, #[serde(with = "hc_expr_serde")] Hc<Expr>),
+    Less(#[serde(with = "hc_expr_serde")] Hc<Expr>, #[serde(with = "hc_expr_serde")] Hc<Expr>),
+    LessEqual(#[serde(with = "hc_expr_serde")] Hc<Expr>, #[serde(with = "hc_expr_serde")] Hc<Expr>),
+    Greater(#[serde(with = "hc_expr_serde")] Hc<Expr>, #[serde(with = "hc_expr_serde")] Hc<Expr>),
+    GreaterEqual(#[serde(with = "hc_expr_serde")] Hc<Expr>, #[serde(with = "hc_expr_serde")] Hc<Expr>),
     Sequence(#[serde(with = "hc_expr_serde")] Hc<Expr>, #[serde(with = "hc_expr_serde")] Hc<Expr>),
     If(#[serde(with = "hc_expr_serde")] Hc<Expr>, #[serde(with = "hc_expr_serde")] Hc<Expr>, #[serde(with = "hc_expr_serde")] Hc<Expr>),
     While(#[serde(with = "hc_expr_serde")] Hc<Expr>, #[serde(with = "hc_expr_serde")] Hc<Expr>),
+    /// The rest of an iteration that yielded, followed by the loop `while(cond){body}`.
+    /// It keeps the `break` and `continue` in the rest attached to their loop.
+    Iterate(#[serde(with = "hc_expr_serde")] Hc<Expr>, #[serde(with = "hc_expr_serde")] Hc<Expr>, #[serde(with = "hc_expr_serde")] Hc<Expr>),
+    /// Leave the innermost loop
+    Break,
+    /// Go on with the next iteration of the innermost loop
+    Continue,
+    /// Finish the request with the value of the expression
+    Return(#[serde(with = "hc_expr_serde")] Hc<Expr>),
+    /// Region that runs as a single step; the parser rejects yields inside it
+    Atomic(#[serde(with = "hc_expr_serde")] Hc<Expr>),
+    /// Region whose global writes are undone by an `abort` inside it
+    Transaction(#[serde(with = "hc_expr_serde")] Hc<Expr>),
+    /// Take a mutex, blocking while another request holds it
+    Lock(String),
+    /// Release a mutex held by this request
+    Unlock(String),
+    /// Take a permit of a semaphore with the given capacity, blocking while none is free
+    Acquire(String, i64),
+    /// Return a permit taken by this request
+    Release(String),
+    /// Wait until the condition holds; the condition is checked in a single step
+    Await(#[serde(with = "hc_expr_serde")] Hc<Expr>),
+    /// Put the value into a channel, blocking while it is full
+    Send(String, Channel, #[serde(with = "hc_expr_serde")] Hc<Expr>),
+    /// Take a message out of a channel, blocking while it is empty
+    Recv(String, Channel),
     Not(#[serde(with = "hc_expr_serde")] Hc<Expr>),
     And(#[serde(with = "hc_expr_serde")] Hc<Expr>, #[serde(with = "hc_expr_serde")] Hc<Expr>),
     Or(#[serde(with = "hc_expr_serde")] Hc<Expr>, #[serde(with = "hc_expr_serde")] Hc<Expr>),
+    /// Element `A[index]` of a declared global array
+    Load(String, Indices, #[serde(with = "hc_expr_serde")] Hc<Expr>),
+    /// Write `A[index] := value`; the index is evaluated before the value
+    Store(String, Indices, #[serde(with = "hc_expr_serde")] Hc<Expr>, #[serde(with = "hc_expr_serde")] Hc<Expr>),
+    /// Value written to a declared variable, checked against the variable's domain
+    Bounded(String, Domain, #[serde(with = "hc_expr_serde")] Hc<Expr>),
+    /// Terminal state of a request that stopped with a runtime error
+    Error(String),
+    /// Continue only in executions where the condition holds
+    Assume(#[serde(with = "hc_expr_serde")] Hc<Expr>),
+    /// Check the condition; the string is its source text, reported when it fails
+    Assert(String, #[serde(with = "hc_expr_serde")] Hc<Expr>),
+    /// Terminal state of a request whose assertion failed
+    Violation(String),
     Yield,
     Exit,
+    /// Terminal state of a crashed request
+    Crashed,
+    /// Undo the writes of the running transaction and respond with `abort`;
+    /// also the terminal state of an aborted request
+    Abort,
     Unknown,
+    /// Any value in the inclusive range `lo..hi`
+    Choose(i64, i64),
+    /// Run either branch
+    Choice(#[serde(with = "hc_expr_serde")] Hc<Expr>, #[serde(with = "hc_expr_serde")] Hc<Expr>),
+    /// Body of a request that responds with a constant of the named enum; the strings are its constants
+    Typed(String, Vec<String>, #[serde(with = "hc_expr_serde")] Hc<Expr>),
+    /// Constant of an enum with its value
+    Symbol(String, i64),
+    /// Tuple `(a, b, ...)` of at least two values; only the result of a request can be one
+    Tuple(#[serde(with = "hc_expr_vec_serde")] Vec<Hc<Expr>>),
     Number(i64),
     Variable(String),
 }
 
+/// What happens when a value outside a declared domain is written
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy, Ord, PartialOrd, serde::Serialize, serde::Deserialize)]
+pub enum Overflow {
+    /// Wrap around modulo the size of the domain
+    Wrap,
+    /// Clamp to the nearest bound
+    Saturate,
+    /// Stop the request with an error response
+    Error,
+}
+
+/// Inclusive range of values a declared variable may hold
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy, Ord, PartialOrd, serde::Serialize, serde::Deserialize)]
+pub struct Domain {
+    pub lo: i64,
+    pub hi: i64,
+    pub overflow: Overflow,
+}
+
+impl Domain {
+    /// Bring a written value into the domain, or `None` if the write is an error
+    pub fn apply(&self, n: i64) -> Option<i64> {
+        if self.lo <= n && n <= self.hi {
+            return Some(n);
+        }
+        match self.overflow {
+            Overflow::Wrap => {
+                // The size of a domain like `0..9223372036854775807` does not fit in an i64
+                let (lo, hi) = (self.lo as i128, self.hi as i128);
+                Some((lo + (n as i128 - lo).rem_euclid(hi - lo + 1)) as i64)
+            }
+            Overflow::Saturate => Some(n.clamp(self.lo, self.hi)),
+            Overflow::Error => None,
+        }
+    }
+}
+
+impl fmt::Display for Domain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let overflow = match self.overflow {
+            Overflow::Wrap => "wrap",
+            Overflow::Saturate => "saturate",
+            Overflow::Error => "error",
+        };
+        write!(f, "{}..{} {}", self.lo, self.hi, overflow)
+    }
+}
+
+/// Inclusive range of the indices of a global array
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy, Ord, PartialOrd, serde::Serialize, serde::Deserialize)]
+pub struct Indices {
+    pub lo: i64,
+    pub hi: i64,
+}
+
+impl Indices {
+    pub fn contains(&self, index: i64) -> bool {
+        self.lo <= index && index <= self.hi
+    }
+}
+
+/// A channel `channel C[capacity] : lo..hi;` that holds up to `capacity` messages with
+/// values in `lo..hi`. Messages are received in the order they were sent, or in any
+/// order for a channel declared `bag`.
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy, Ord, PartialOrd, serde::Serialize, serde::Deserialize)]
+pub struct Channel {
+    pub capacity: i64,
+    pub lo: i64,
+    pub hi: i64,
+    pub bag: bool,
+}
+
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Ord, PartialOrd, serde::Serialize, serde::Deserialize)]
+pub struct ChannelDecl {
+    pub name: String,
+    pub channel: Channel,
+}
+
+/// A `global X : lo..hi mode;` or `local x : lo..hi mode;` declaration.
+/// A global array `global A[lo..hi] : lo..hi mode;` also has indices; the domain applies to each element.
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Ord, PartialOrd, serde::Serialize, serde::Deserialize)]
+pub struct VarDecl {
+    pub name: String,
+    #[serde(default)]
+    pub indices: Option<Indices>,
+    pub domain: Domain,
+    /// Enum whose constants the variable holds, if declared as `X : Color`
+    #[serde(default)]
+    pub enum_type: Option<String>,
+}
+
+/// An `enum Color { RED, GREEN }` declaration; the constants stand for 0, 1, ...
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Ord, PartialOrd, serde::Serialize, serde::Deserialize)]
+pub struct EnumDecl {
+    pub name: String,
+    pub constants: Vec<String>,
+}
+
+/// The possible initial values of a global or of an array element (`index`),
+/// from `init { X := 2 }` or `init X in {0, 1}`
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Ord, PartialOrd, serde::Serialize, serde::Deserialize)]
+pub struct Init {
+    pub name: String,
+    #[serde(default)]
+    pub index: Option<i64>,
+    pub values: Vec<i64>,
+}
+
 #[derive(Hash, Eq, PartialEq, Debug, Clone, Ord, PartialOrd, serde::Serialize, serde::Deserialize)]
 pub struct Program {
+    #[serde(default)]
+    pub enums: Vec<EnumDecl>,
+    #[serde(default)]
+    pub declarations: Vec<VarDecl>,
+    #[serde(default)]
+    pub inits: Vec<Init>,
+    #[serde(default)]
+    pub channels: Vec<ChannelDecl>,
     pub requests: Vec<Request>,
+    /// Source location of each `while` loop, to report a loop that never ends
+    #[serde(skip)]
+    pub loops: Vec<(Hc<Expr>, String)>,
 }
 
 #[derive(Hash, Eq, PartialEq, Debug, Clone, Ord, PartialOrd, serde::Serialize, serde::Deserialize)]
 pub struct Request {
     pub name: String,
+    #[serde(default)]
+    pub params: Vec<Param>,
+    /// Enum of the response, if declared as `request name : Color { ... }`
+    #[serde(default)]
+    pub returns: Option<String>,
+    /// Whether the request may crash at a yield point, declared as `crashable request name { ... }`
+    #[serde(default)]
+    pub crashable: bool,
     #[serde(with = "hc_expr_serde")]
     pub body: Hc<Expr>,
 }
 
+/// A request parameter `x in lo..hi`; the bounds are inclusive.
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Ord, PartialOrd, serde::Serialize, serde::Deserialize)]
+pub struct Param {
+    pub name: String,
+    pub lo: i64,
+    pub hi: i64,
+}
+
+impl Request {
+    /// All argument tuples the request can be instantiated with, in lexicographic order.
+    /// A request without parameters has exactly one (empty) instance.
+    pub fn instances(&self) -> Vec<Vec<i64>> {
+        let mut instances = vec![vec![]];
+        for param in &self.params {
+            instances = instances
+                .into_iter()
+                .flat_map(|args| {
+                    (param.lo..=param.hi).map(move |value| {
+                        let mut args = args.clone();
+                        args.push(value);
+                        args
+                    })
+                })
+                .collect();
+        }
+        instances
+    }
+}
+
 impl fmt::Display for Expr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -40,17 +262,67 @@ impl fmt::Display for Expr {
             Expr::Equal(left, right) => write!(f, "{} == {}", left, right),
             Expr::Add(left, right) => write!(f, "{} + {}", left, right),
             Expr::Subtract(left, right) => write!(f, "{} - {}", left, right),
+            Expr::Multiply(left, right) => write!(f, "{} * {}", left, right),
+            Expr::Divide(left, right) => write!(f, "{} / {}", left, right),
+            Expr::Modulo(left, right) => write!(f, "{} % {}", left, right),
+            Expr::NotEqual(left, right) => write!(f, "{} != {}", left, right),
+            Expr::Less(left, right) => write!(f, "{} < {}", left, right),
+            Expr::LessEqual(left, right) => write!(f, "{} <= {}", left, right),
+            Expr::Greater(left, right) => write!(f, "{} > {}", left, right),
+            Expr::GreaterEqual(left, right) => write!(f, "{} >= {}", left, right),
             Expr::Sequence(first, second) => write!(f, "{}; {}", first, second),
             Expr::If(cond, then_branch, else_branch) => {
                 write!(f, "if({}){{{}}}else{{{}}}", cond, then_branch, else_branch)
             }
             Expr::While(cond, body) => write!(f, "while({}){{ {} }}", cond, body),
+            // Shown like the sequence it stands for
+            Expr::Iterate(rest, cond, body) => write!(f, "{}; while({}){{ {} }}", rest, cond, body),
+            Expr::Break => write!(f, "break"),
+            Expr::Continue => write!(f, "continue"),
+            Expr::Return(value) => write!(f, "return {}", value),
+            Expr::Atomic(body) => write!(f, "atomic{{ {} }}", body),
+            Expr::Transaction(body) => write!(f, "transaction{{ {} }}", body),
+            Expr::Load(array, _, index) => write!(f, "{}[{}]", array, index),
+            Expr::Store(array, _, index, value) => write!(f, "{}[{}] := {}", array, index, value),
+            Expr::Lock(lock) => write!(f, "lock({})", lock),
+            Expr::Unlock(lock) => write!(f, "unlock({})", lock),
+            Expr::Acquire(sem, _) => write!(f, "acquire({})", sem),
+            Expr::Release(sem) => write!(f, "release({})", sem),
+            Expr::Await(cond) => write!(f, "await({})", cond),
+            Expr::Send(channel, _, value) => write!(f, "send({}, {})", channel, value),
+            Expr::Recv(channel, _) => write!(f, "recv({})", channel),
             Expr::Not(expr) => write!(f, "!{}", expr),
             Expr::And(left, right) => write!(f, "{} && {}", left, right),
             Expr::Or(left, right) => write!(f, "{} || {}", left, right),
+            // The domain check is implied by the declaration, so only the written value is shown
+            Expr::Bounded(_, _, expr) => write!(f, "{}", expr),
+            Expr::Error(message) => write!(f, "error({})", message),
+            Expr::Assume(cond) => write!(f, "assume({})", cond),
+            Expr::Assert(_, cond) => write!(f, "assert({})", cond),
+            Expr::Violation(message) => write!(f, "assertion failed({})", message),
             Expr::Yield => write!(f, "yield"),
             Expr::Exit => write!(f, "exit"),
+            Expr::Crashed => write!(f, "crashed"),
+            Expr::Abort => write!(f, "abort"),
             Expr::Unknown => write!(f, "?"),
+            Expr::Choose(lo, hi) => write!(f, "choose({}..{})", lo, hi),
+            Expr::Choice(first, rest) => {
+                // A chain `choose{ A }or{ B }or{ C }` nests to the right
+                write!(f, "choose{{ {} }}", first)?;
+                let mut rest = rest;
+                while let Expr::Choice(next, more) = rest.as_ref() {
+                    write!(f, "or{{ {} }}", next)?;
+                    rest = more;
+                }
+                write!(f, "or{{ {} }}", rest)
+            }
+            // The response type is part of the request, not of its body
+            Expr::Typed(_, _, body) => write!(f, "{}", body),
+            Expr::Symbol(name, _) => write!(f, "{}", name),
+            Expr::Tuple(items) => {
+                let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+                write!(f, "({})", items.join(", "))
+            }
             Expr::Number(n) => write!(f, "{}", n),
             Expr::Variable(var) => write!(f, "{}", var),
         }
@@ -90,9 +362,142 @@ pub mod hc_expr_serde {
     }
 }
 
+// Custom serialization module for the Vec<Hc<Expr>> of a tuple
+pub mod hc_expr_vec_serde {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Item(#[serde(with = "hc_expr_serde")] Hc<Expr>);
+
+    pub fn serialize<S>(items: &[Hc<Expr>], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(items.iter().map(|item| &**item))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<Hc<Expr>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let items = Vec::<Item>::deserialize(deserializer)?;
+        Ok(items.into_iter().map(|Item(item)| item).collect())
+    }
+}
+
 // Now we need to tell serde to use our custom module for Hc<Expr> fields
 // We'll need to update the Expr enum to use this
 
+/// Check that tuples only occur where they become the result of the request:
+/// in tail position or as the value of a `return`. `tuples` is false for a
+/// request that responds with an enum.
+fn check_tuples(expr: &Expr, tail: bool, tuples: bool) -> Result<(), String> {
+    match expr {
+        Expr::Tuple(items) => {
+            if !tail {
+                return Err("A tuple can only be the result of a request".to_string());
+            }
+            if !tuples {
+                return Err("A request that responds with an enum cannot return a tuple".to_string());
+            }
+            items.iter().try_for_each(|item| check_tuples(item, false, tuples))
+        }
+        Expr::Return(e) => check_tuples(e, true, tuples),
+        Expr::Sequence(first, rest) => {
+            check_tuples(first, false, tuples)?;
+            check_tuples(rest, tail, tuples)
+        }
+        Expr::If(cond, then_branch, else_branch) => {
+            check_tuples(cond, false, tuples)?;
+            check_tuples(then_branch, tail, tuples)?;
+            check_tuples(else_branch, tail, tuples)
+        }
+        Expr::Choice(first, second) => {
+            check_tuples(first, tail, tuples)?;
+            check_tuples(second, tail, tuples)
+        }
+        Expr::Atomic(e) | Expr::Transaction(e) | Expr::Typed(_, _, e) => check_tuples(e, tail, tuples),
+        Expr::Assign(_, e)
+        | Expr::Assume(e)
+        | Expr::Assert(_, e)
+        | Expr::Await(e)
+        | Expr::Send(_, _, e)
+        | Expr::Not(e)
+        | Expr::Load(_, _, e)
+        | Expr::Bounded(_, _, e) => check_tuples(e, false, tuples),
+        Expr::Equal(e1, e2)
+        | Expr::Add(e1, e2)
+        | Expr::Subtract(e1, e2)
+        | Expr::Multiply(e1, e2)
+        | Expr::Divide(e1, e2)
+        | Expr::Modulo(e1, e2)
+        | Expr::NotEqual(e1, e2)
+        | Expr::Less(e1, e2)
+        | Expr::LessEqual(e1, e2)
+        | Expr::Greater(e1, e2)
+        | Expr::GreaterEqual(e1, e2)
+        | Expr::While(e1, e2)
+        | Expr::And(e1, e2)
+        | Expr::Or(e1, e2)
+        | Expr::Store(_, _, e1, e2) => {
+            check_tuples(e1, false, tuples)?;
+            check_tuples(e2, false, tuples)
+        }
+        Expr::Iterate(rest, cond, body) => {
+            check_tuples(rest, false, tuples)?;
+            check_tuples(cond, false, tuples)?;
+            check_tuples(body, false, tuples)
+        }
+        Expr::Break
+        | Expr::Continue
+        | Expr::Lock(_)
+        | Expr::Unlock(_)
+        | Expr::Acquire(_, _)
+        | Expr::Release(_)
+        | Expr::Recv(_, _)
+        | Expr::Error(_)
+        | Expr::Violation(_)
+        | Expr::Yield
+        | Expr::Exit
+        | Expr::Crashed
+        | Expr::Abort
+        | Expr::Unknown
+        | Expr::Choose(_, _)
+        | Expr::Symbol(_, _)
+        | Expr::Number(_)
+        | Expr::Variable(_) => Ok(()),
+    }
+}
+
+/// Integer division with total semantics, shared by constant folding and `run_expr`.
+///
+/// Division is Euclidean, so `a == b * (a / b) + a % b` with `0 <= a % b < |b|`.
+/// Division by zero yields 0.
+pub fn divide_values(n1: i64, n2: i64) -> i64 {
+    if n2 == 0 { 0 } else { n1.wrapping_div_euclid(n2) }
+}
+
+/// Euclidean remainder (never negative). The remainder of a division by zero is the dividend,
+/// which keeps `a == b * (a / b) + a % b` true for `b == 0` as well.
+pub fn modulo_values(n1: i64, n2: i64) -> i64 {
+    if n2 == 0 { n1 } else { n1.wrapping_rem_euclid(n2) }
+}
+
+/// Sum that wraps around on overflow, like the other operators do.
+pub fn add_values(n1: i64, n2: i64) -> i64 {
+    n1.wrapping_add(n2)
+}
+
+/// Difference that wraps around on overflow, like the other operators do.
+pub fn subtract_values(n1: i64, n2: i64) -> i64 {
+    n1.wrapping_sub(n2)
+}
+
+/// Product that wraps around on overflow, like division and remainder do.
+pub fn multiply_values(n1: i64, n2: i64) -> i64 {
+    n1.wrapping_mul(n2)
+}
+
 pub struct ExprHc {
     table: HcTable<Expr>,
 }
@@ -121,7 +526,7 @@ impl ExprHc {
         // If both are constants, return the sum
         if let Expr::Number(n1) = left.as_ref() {
             if let Expr::Number(n2) = right.as_ref() {
-                return self.number(n1 + n2);
+                return self.number(add_values(*n1, *n2));
             }
         }
         self.table.hashcons(Expr::Add(left, right))
@@ -131,12 +536,76 @@ impl ExprHc {
         // If both are constants, return the difference
         if let Expr::Number(n1) = left.as_ref() {
             if let Expr::Number(n2) = right.as_ref() {
-                return self.number(n1 - n2);
+                return self.number(subtract_values(*n1, *n2));
             }
         }
         self.table.hashcons(Expr::Subtract(left, right))
     }
 
+    pub fn multiply(&mut self, left: Hc<Expr>, right: Hc<Expr>) -> Hc<Expr> {
+        // If both are constants, return the product
+        if let (Expr::Number(n1), Expr::Number(n2)) = (left.as_ref(), right.as_ref()) {
+            return self.number(multiply_values(*n1, *n2));
+        }
+        self.table.hashcons(Expr::Multiply(left, right))
+    }
+
+    pub fn divide(&mut self, left: Hc<Expr>, right: Hc<Expr>) -> Hc<Expr> {
+        // If both are constants, return the quotient
+        if let (Expr::Number(n1), Expr::Number(n2)) = (left.as_ref(), right.as_ref()) {
+            return self.number(divide_values(*n1, *n2));
+        }
+        self.table.hashcons(Expr::Divide(left, right))
+    }
+
+    pub fn modulo(&mut self, left: Hc<Expr>, right: Hc<Expr>) -> Hc<Expr> {
+        // If both are constants, return the remainder
+        if let (Expr::Number(n1), Expr::Number(n2)) = (left.as_ref(), right.as_ref()) {
+            return self.number(modulo_values(*n1, *n2));
+        }
+        self.table.hashcons(Expr::Modulo(left, right))
+    }
+
+    pub fn not_equal(&mut self, left: Hc<Expr>, right: Hc<Expr>) -> Hc<Expr> {
+        // If both are constants, return 1 or 0
+        if let (Expr::Number(n1), Expr::Number(n2)) = (left.as_ref(), right.as_ref()) {
+            return self.number(if n1 != n2 { 1 } else { 0 });
+        }
+        self.table.hashcons(Expr::NotEqual(left, right))
+    }
+
+    pub fn less(&mut self, left: Hc<Expr>, right: Hc<Expr>) -> Hc<Expr> {
+        // If both are constants, return 1 or 0
+        if let (Expr::Number(n1), Expr::Number(n2)) = (left.as_ref(), right.as_ref()) {
+            return self.number(if n1 < n2 { 1 } else { 0 });
+        }
+        self.table.hashcons(Expr::Less(left, right))
+    }
+
+    pub fn less_equal(&mut self, left: Hc<Expr>, right: Hc<Expr>) -> Hc<Expr> {
+        // If both are constants, return 1 or 0
+        if let (Expr::Number(n1), Expr::Number(n2)) = (left.as_ref(), right.as_ref()) {
+            return self.number(if n1 <= n2 { 1 } else { 0 });
+        }
+        self.table.hashcons(Expr::LessEqual(left, right))
+    }
+
+    pub fn greater(&mut self, left: Hc<Expr>, right: Hc<Expr>) -> Hc<Expr> {
+        // If both are constants, return 1 or 0
+        if let (Expr::Number(n1), Expr::Number(n2)) = (left.as_ref(), right.as_ref()) {
+            return self.number(if n1 > n2 { 1 } else { 0 });
+        }
+        self.table.hashcons(Expr::Greater(left, right))
+    }
+
+    pub fn greater_equal(&mut self, left: Hc<Expr>, right: Hc<Expr>) -> Hc<Expr> {
+        // If both are constants, return 1 or 0
+        if let (Expr::Number(n1), Expr::Number(n2)) = (left.as_ref(), right.as_ref()) {
+            return self.number(if n1 >= n2 { 1 } else { 0 });
+        }
+        self.table.hashcons(Expr::GreaterEqual(left, right))
+    }
+
     pub fn not(&mut self, expr: Hc<Expr>) -> Hc<Expr> {
         // If expr is a constant, return 1 or 0
         if let Expr::Number(n) = expr.as_ref() {
@@ -209,6 +678,112 @@ impl ExprHc {
         self.table.hashcons(Expr::While(cond, body))
     }
 
+    pub fn iterate(&mut self, rest: Hc<Expr>, cond: Hc<Expr>, body: Hc<Expr>) -> Hc<Expr> {
+        self.table.hashcons(Expr::Iterate(rest, cond, body))
+    }
+
+    pub fn break_expr(&mut self) -> Hc<Expr> {
+        self.table.hashcons(Expr::Break)
+    }
+
+    pub fn continue_expr(&mut self) -> Hc<Expr> {
+        self.table.hashcons(Expr::Continue)
+    }
+
+    pub fn return_expr(&mut self, value: Hc<Expr>) -> Hc<Expr> {
+        self.table.hashcons(Expr::Return(value))
+    }
+
+    pub fn choose(&mut self, lo: i64, hi: i64) -> Hc<Expr> {
+        // A single value is no choice
+        if lo == hi {
+            return self.number(lo);
+        }
+        self.table.hashcons(Expr::Choose(lo, hi))
+    }
+
+    pub fn choice(&mut self, first: Hc<Expr>, second: Hc<Expr>) -> Hc<Expr> {
+        self.table.hashcons(Expr::Choice(first, second))
+    }
+
+    pub fn atomic(&mut self, body: Hc<Expr>) -> Hc<Expr> {
+        // A constant takes no steps, so there is nothing to protect
+        if matches!(body.as_ref(), Expr::Number(_)) {
+            return body;
+        }
+        self.table.hashcons(Expr::Atomic(body))
+    }
+
+    pub fn send(&mut self, name: String, channel: Channel, value: Hc<Expr>) -> Hc<Expr> {
+        self.table.hashcons(Expr::Send(name, channel, value))
+    }
+
+    pub fn recv(&mut self, name: String, channel: Channel) -> Hc<Expr> {
+        self.table.hashcons(Expr::Recv(name, channel))
+    }
+
+    pub fn transaction(&mut self, body: Hc<Expr>) -> Hc<Expr> {
+        self.table.hashcons(Expr::Transaction(body))
+    }
+
+    pub fn load(&mut self, array: String, indices: Indices, index: Hc<Expr>) -> Hc<Expr> {
+        self.table.hashcons(Expr::Load(array, indices, index))
+    }
+
+    pub fn store(
+        &mut self,
+        array: String,
+        indices: Indices,
+        index: Hc<Expr>,
+        value: Hc<Expr>,
+    ) -> Hc<Expr> {
+        self.table.hashcons(Expr::Store(array, indices, index, value))
+    }
+
+    pub fn lock(&mut self, lock: String) -> Hc<Expr> {
+        self.table.hashcons(Expr::Lock(lock))
+    }
+
+    pub fn unlock(&mut self, lock: String) -> Hc<Expr> {
+        self.table.hashcons(Expr::Unlock(lock))
+    }
+
+    pub fn acquire(&mut self, sem: String, capacity: i64) -> Hc<Expr> {
+        self.table.hashcons(Expr::Acquire(sem, capacity))
+    }
+
+    pub fn release(&mut self, sem: String) -> Hc<Expr> {
+        self.table.hashcons(Expr::Release(sem))
+    }
+
+    pub fn await_expr(&mut self, cond: Hc<Expr>) -> Hc<Expr> {
+        self.table.hashcons(Expr::Await(cond))
+    }
+
+    pub fn bounded(&mut self, var: String, domain: Domain, expr: Hc<Expr>) -> Hc<Expr> {
+        // A constant that is already in range needs no check
+        if matches!(expr.as_ref(), Expr::Number(n) if domain.lo <= *n && *n <= domain.hi) {
+            return expr;
+        }
+        self.table.hashcons(Expr::Bounded(var, domain, expr))
+    }
+
+    pub fn error(&mut self, message: String) -> Hc<Expr> {
+        self.table.hashcons(Expr::Error(message))
+    }
+
+    pub fn assume(&mut self, cond: Hc<Expr>) -> Hc<Expr> {
+        self.table.hashcons(Expr::Assume(cond))
+    }
+
+    pub fn assert(&mut self, message: String, cond: Hc<Expr>) -> Hc<Expr> {
+        self.table.hashcons(Expr::Assert(message, cond))
+    }
+
+    pub fn violation(&mut self, message: String) -> Hc<Expr> {
+        self.table.hashcons(Expr::Violation(message))
+    }
+
     pub fn yield_expr(&mut self) -> Hc<Expr> {
         self.table.hashcons(Expr::Yield)
     }
@@ -217,10 +792,30 @@ impl ExprHc {
         self.table.hashcons(Expr::Exit)
     }
 
+    pub fn crashed(&mut self) -> Hc<Expr> {
+        self.table.hashcons(Expr::Crashed)
+    }
+
+    pub fn abort(&mut self) -> Hc<Expr> {
+        self.table.hashcons(Expr::Abort)
+    }
+
     pub fn unknown(&mut self) -> Hc<Expr> {
         self.table.hashcons(Expr::Unknown)
     }
 
+    pub fn typed(&mut self, enum_name: String, constants: Vec<String>, body: Hc<Expr>) -> Hc<Expr> {
+        self.table.hashcons(Expr::Typed(enum_name, constants, body))
+    }
+
+    pub fn symbol(&mut self, name: String, value: i64) -> Hc<Expr> {
+        self.table.hashcons(Expr::Symbol(name, value))
+    }
+
+    pub fn tuple(&mut self, items: Vec<Hc<Expr>>) -> Hc<Expr> {
+        self.table.hashcons(Expr::Tuple(items))
+    }
+
     pub fn number(&mut self, n: i64) -> Hc<Expr> {
         self.table.hashcons(Expr::Number(n))
     }
@@ -233,60 +828,431 @@ impl ExprHc {
 #[derive(Debug)]
 pub struct Parser {
     tokens: Vec<Token>,
+    /// Source position of each token, if known
+    spans: Vec<Span>,
     current: usize,
+    /// Index of the furthest token looked at, where a parse error is reported
+    furthest: Cell<usize>,
+    /// Globals whose domain excludes 0, with the token of their declaration;
+    /// they must be given an initial value
+    zero_checks: Vec<(String, usize)>,
+    /// Domains of the variables declared so far; writes to them are checked
+    domains: HashMap<String, Domain>,
+    /// Indices and element domains of the global arrays declared so far
+    arrays: HashMap<String, (Indices, Domain)>,
+    /// Procedures defined so far; their bodies are re-parsed at every call
+    fns: HashMap<String, FnDef>,
+    /// Constants of the enums declared so far
+    enums: HashMap<String, Vec<String>>,
+    /// Value of every enum constant
+    constants: HashMap<String, i64>,
+    /// Renaming scopes of the procedure bodies being inlined, innermost last
+    scopes: Vec<FnScope>,
+    /// Procedures whose arguments are being parsed, outermost first
+    pending_calls: Vec<String>,
+    /// Number of enclosing atomic blocks
+    atomic_depth: usize,
+    /// Number of enclosing `await` conditions
+    await_depth: usize,
+    /// Number of enclosing loop bodies; `break` and `continue` need one
+    loop_depth: usize,
+    /// Whether a transaction is being parsed; `abort` needs one
+    in_transaction: bool,
+    /// Channels declared so far
+    channels: HashMap<String, Channel>,
+    /// Index of the first token of each file of a project, and the file names
+    file_starts: Vec<usize>,
+    file_names: Vec<String>,
+    /// File of a project that defines each top-level name
+    defined_in: HashMap<String, usize>,
+    /// Loops parsed so far with their source location
+    loops: Vec<(Hc<Expr>, String)>,
+}
+
+/// A `fn name(params) { body }` definition
+#[derive(Debug, Clone)]
+struct FnDef {
+    params: Vec<String>,
+    /// Index of the first token of the body
+    body: usize,
+}
+
+/// Locals of an inlined procedure body are renamed to `x@name` so they cannot
+/// clash with the locals of the caller
+#[derive(Debug)]
+struct FnScope {
+    suffix: String,
+    locals: HashSet<String>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Identifier(String),
     Number(i64),
+    /// String literal, used for the path of an `import`
+    Str(String),
+    /// `// text` up to the end of the line; only kept for the formatter
+    Comment(String),
     Assign,    // :=
     Equal,     // ==
+    NotEqual,  // !=
+    Less,      // <
+    LessEqual, // <=
+    Greater,   // >
+    GreaterEqual, // >=
     Plus,      // +
     Minus,     // -
+    Star,      // *
+    Slash,     // /
+    Percent,   // %
     Semicolon, // ;
+    Colon,     // :
+    DotDot,    // ..
     If,        // if
     Else,      // else
     While,     // while
+    Break,     // break
+    Continue,  // continue
+    Return,    // return
+    Atomic,    // atomic
+    Transaction, // transaction
+    Lock,      // lock
+    Unlock,    // unlock
+    Acquire,   // acquire
+    Release,   // release
+    Await,     // await
+    Send,      // send
+    Recv,      // recv
     Yield,     // yield
     Exit,      // exit
+    Abort,     // abort
+    Assume,    // assume
+    Assert,    // assert
     Question,  // ?
+    Choose,    // choose
     Request,   // request
     Not,       // !
     And,       // &&
     Or,        // ||
     LParen,    // (
     RParen,    // )
+    LBracket,  // [
+    RBracket,  // ]
+    Comma,     // ,
     LBrace,    // {
     RBrace,    // }
     Eof,
 }
 
+impl Token {
+    /// The token as it is written in the source
+    pub fn text(&self) -> String {
+        let text = match self {
+            Token::Identifier(name) => return name.clone(),
+            Token::Number(n) => return n.to_string(),
+            Token::Str(text) => return format!("\"{}\"", text),
+            Token::Comment(text) => return text.clone(),
+            Token::Eof => return String::new(),
+            Token::Assign => ":=",
+            Token::Equal => "==",
+            Token::NotEqual => "!=",
+            Token::Less => "<",
+            Token::LessEqual => "<=",
+            Token::Greater => ">",
+            Token::GreaterEqual => ">=",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Star => "*",
+            Token::Slash => "/",
+            Token::Percent => "%",
+            Token::Semicolon => ";",
+            Token::Colon => ":",
+            Token::DotDot => "..",
+            Token::If => "if",
+            Token::Else => "else",
+            Token::While => "while",
+            Token::Break => "break",
+            Token::Continue => "continue",
+            Token::Return => "return",
+            Token::Atomic => "atomic",
+            Token::Transaction => "transaction",
+            Token::Lock => "lock",
+            Token::Unlock => "unlock",
+            Token::Acquire => "acquire",
+            Token::Release => "release",
+            Token::Await => "await",
+            Token::Send => "send",
+            Token::Recv => "recv",
+            Token::Yield => "yield",
+            Token::Exit => "exit",
+            Token::Abort => "abort",
+            Token::Assume => "assume",
+            Token::Assert => "assert",
+            Token::Question => "?",
+            Token::Choose => "choose",
+            Token::Request => "request",
+            Token::Not => "!",
+            Token::And => "&&",
+            Token::Or => "||",
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::LBracket => "[",
+            Token::RBracket => "]",
+            Token::Comma => ",",
+            Token::LBrace => "{",
+            Token::RBrace => "}",
+        };
+        text.to_string()
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Eof => write!(f, "end of input"),
+            Token::Str(_) | Token::Comment(_) => write!(f, "{}", self.text()),
+            _ => write!(f, "'{}'", self.text()),
+        }
+    }
+}
+
+/// Line and column (both starting at 1) of a token in the source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A syntax error, with the position where it was detected if known
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+impl ParseError {
+    /// The error followed by the offending source line and a caret under the position
+    pub fn render(&self, source: &str) -> String {
+        let Some(span) = self.span else {
+            return self.message.clone();
+        };
+        let text = source.lines().nth(span.line - 1).unwrap_or("");
+        let number = span.line.to_string();
+        let gutter = " ".repeat(number.len());
+        // Keep tabs so the caret lines up with the source line
+        let indent: String = text
+            .chars()
+            .take(span.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        format!(
+            "{}\n{} |\n{} | {}\n{} | {}^",
+            self, gutter, number, text, gutter, indent
+        )
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "line {}, column {}: {}", span.line, span.column, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
 /// Parse a string directly into an expression
-pub fn parse(source: &str, table: &mut ExprHc) -> Result<Hc<Expr>, String> {
-    let tokens = tokenize(source)?;
-    let mut parser = Parser::new(tokens);
-    parser.parse(table)
+pub fn parse(source: &str, table: &mut ExprHc) -> Result<Hc<Expr>, ParseError> {
+    let mut parser = Parser::with_spans(tokenize_with_spans(source)?);
+    parser.parse(table).map_err(|message| parser.error(message))
 }
 
 /// Parse a string into a program containing multiple requests
-pub fn parse_program(source: &str, table: &mut ExprHc) -> Result<Program, String> {
-    let tokens = tokenize(source)?;
-    let mut parser = Parser::new(tokens);
-    parser.parse_program(table)
+pub fn parse_program(source: &str, table: &mut ExprHc) -> Result<Program, ParseError> {
+    let mut parser = Parser::with_spans(tokenize_with_spans(source)?);
+    parser.parse_program(table).map_err(|message| parser.error(message))
+}
+
+/// A .ser file of a project
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    /// Path of the file, as shown in error messages
+    pub path: String,
+    pub source: String,
+}
+
+/// Split the leading `import "file.ser";` statements off the tokens of a file
+fn split_imports(
+    mut tokens: Vec<(Token, Span)>,
+) -> Result<(Vec<(String, Span)>, Vec<(Token, Span)>), ParseError> {
+    let mut imports = Vec::new();
+    let mut current = 0;
+    while matches!(&tokens[current].0, Token::Identifier(name) if name == "import") {
+        let (path, span) = match &tokens[current + 1] {
+            (Token::Str(path), span) => (path.clone(), *span),
+            (_, span) => {
+                return Err(ParseError {
+                    message: "Expected a file name in quotes after 'import'".to_string(),
+                    span: Some(*span),
+                });
+            }
+        };
+        if tokens[current + 2].0 != Token::Semicolon {
+            return Err(ParseError {
+                message: "Expected ';' after import".to_string(),
+                span: Some(tokens[current + 2].1),
+            });
+        }
+        imports.push((path, span));
+        current += 3;
+    }
+    Ok((imports, tokens.split_off(current)))
+}
+
+/// Paths of the files imported by a source, with the position of each path
+pub fn imports(source: &str) -> Result<Vec<(String, Span)>, ParseError> {
+    Ok(split_imports(tokenize_with_spans(source)?)?.0)
+}
+
+/// Parse the files of a project into one program. Every file must come after
+/// the files it imports, so procedures, enums and globals are defined before
+/// they are used. An error comes with the index of the file it is in.
+pub fn parse_files(files: &[SourceFile], table: &mut ExprHc) -> Result<Program, (usize, ParseError)> {
+    let mut tokens = Vec::new();
+    let mut file_starts = Vec::new();
+    for (index, file) in files.iter().enumerate() {
+        let file_tokens = tokenize_with_spans(&file.source).map_err(|err| (index, err))?;
+        let (_, mut file_tokens) = split_imports(file_tokens).map_err(|err| (index, err))?;
+        // Only the last file ends the input
+        if index + 1 < files.len() {
+            file_tokens.pop();
+        }
+        file_starts.push(tokens.len());
+        tokens.extend(file_tokens);
+    }
+    let mut parser = Parser::with_spans(tokens);
+    parser.file_starts = file_starts;
+    parser.file_names = files.iter().map(|file| file.path.clone()).collect();
+    parser.parse_program(table).map_err(|message| {
+        let file = parser.file_of(parser.furthest.get());
+        (file, parser.error(message))
+    })
+}
+
+/// Parse the contents of a .ser file: a program, or a single expression that becomes
+/// one request named `request`. A file with requests is always read as a program, so
+/// its errors are reported rather than those of the expression fallback.
+pub fn parse_source(source: &str, table: &mut ExprHc) -> Result<Program, ParseError> {
+    let program_error = match parse_program(source, table) {
+        Ok(program) => return Ok(program),
+        Err(err) => err,
+    };
+    if tokenize(source)?.contains(&Token::Request) {
+        return Err(program_error);
+    }
+    let mut parser = Parser::with_spans(tokenize_with_spans(source)?);
+    let body = parser.parse(table).map_err(|message| parser.error(message))?;
+    Ok(Program {
+        enums: vec![],
+        declarations: vec![],
+        inits: vec![],
+        channels: vec![],
+        requests: vec![Request {
+            name: "request".to_string(),
+            params: vec![],
+            returns: None,
+            crashable: false,
+            body,
+        }],
+        loops: parser.loops,
+    })
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, current: 0 }
+        Parser {
+            tokens,
+            spans: Vec::new(),
+            current: 0,
+            furthest: Cell::new(0),
+            zero_checks: Vec::new(),
+            domains: HashMap::default(),
+            arrays: HashMap::default(),
+            fns: HashMap::default(),
+            enums: HashMap::default(),
+            constants: HashMap::default(),
+            scopes: Vec::new(),
+            pending_calls: Vec::new(),
+            atomic_depth: 0,
+            await_depth: 0,
+            loop_depth: 0,
+            in_transaction: false,
+            channels: HashMap::default(),
+            file_starts: Vec::new(),
+            file_names: Vec::new(),
+            defined_in: HashMap::default(),
+            loops: Vec::new(),
+        }
+    }
+
+    pub fn with_spans(tokens: Vec<(Token, Span)>) -> Self {
+        let (tokens, spans) = tokens.into_iter().unzip();
+        Parser {
+            spans,
+            ..Parser::new(tokens)
+        }
+    }
+
+    /// Attach the position of the furthest token reached to an error message
+    pub fn error(&self, message: String) -> ParseError {
+        ParseError {
+            message,
+            span: self.spans.get(self.furthest.get()).copied(),
+        }
+    }
+
+    /// Position of the token at `index`, with its file in a project
+    fn location(&self, index: usize) -> Option<String> {
+        let span = self.spans.get(index)?;
+        let position = format!("line {}, column {}", span.line, span.column);
+        match self.file_names.get(self.file_of(index)) {
+            Some(file) => Some(format!("{}, {}", file, position)),
+            None => Some(position),
+        }
+    }
+
+    /// File of a project that contains the token at `index`
+    fn file_of(&self, index: usize) -> usize {
+        self.file_starts.iter().filter(|start| **start <= index).count().saturating_sub(1)
+    }
+
+    /// Record that the current file defines the top-level `name`. Only a name that
+    /// another file of the project already defines is a clash; duplicates within
+    /// a file are reported by the declaration itself.
+    fn claim(&mut self, name: &str) -> Result<(), String> {
+        if self.file_names.is_empty() {
+            return Ok(());
+        }
+        let file = self.file_of(self.current.saturating_sub(1));
+        match self.defined_in.get(name) {
+            Some(&other) if other != file => Err(format!(
+                "'{}' is defined in both {} and {}",
+                name, self.file_names[other], self.file_names[file]
+            )),
+            _ => {
+                self.defined_in.insert(name.to_string(), file);
+                Ok(())
+            }
+        }
     }
 
     pub fn parse(&mut self, table: &mut ExprHc) -> Result<Hc<Expr>, String> {
         let expr = self.expression(table)?;
+        check_tuples(&expr, true, true)?;
 
         if self.current < self.tokens.len() && self.tokens[self.current] != Token::Eof {
             return Err(format!(
-                "Unexpected token after expression: {:?}",
+                "Unexpected {} after expression",
                 self.tokens[self.current]
             ));
         }
@@ -295,17 +1261,81 @@ impl Parser {
     }
 
     pub fn parse_program(&mut self, table: &mut ExprHc) -> Result<Program, String> {
+        let mut enums = Vec::new();
+        let mut declarations = Vec::new();
+        let mut inits = Vec::new();
+        let mut channels = Vec::new();
         let mut requests = Vec::new();
+        let mut all_crashable = false;
 
         while !self.is_at_end() {
             if self.check(&Token::Request) {
                 let request = self.parse_request(table)?;
                 requests.push(request);
+            } else if self.match_identifier("crashable") {
+                // `crashable;` makes every request crashable, `crashable request` one of them
+                if self.match_token(&[Token::Semicolon]) {
+                    all_crashable = true;
+                    continue;
+                }
+                if !self.check(&Token::Request) {
+                    return Err("Expected 'request' or ';' after 'crashable'".to_string());
+                }
+                let mut request = self.parse_request(table)?;
+                request.crashable = true;
+                requests.push(request);
+            } else if self.check_identifier("global") || self.check_identifier("local") {
+                if !requests.is_empty() {
+                    return Err("Declarations must appear before the first request".to_string());
+                }
+                let declared_at = self.current + 1;
+                let declaration = self.parse_declaration()?;
+                if inits.iter().any(|init: &Init| init.name == declaration.name) {
+                    self.furthest.set(declared_at);
+                    return Err(format!(
+                        "'{}' is declared after its initial value",
+                        declaration.name
+                    ));
+                }
+                let Domain { lo, hi, .. } = declaration.domain;
+                if declaration.indices.is_none() && (lo > 0 || hi < 0) {
+                    self.zero_checks.push((declaration.name.clone(), declared_at));
+                }
+                match declaration.indices {
+                    Some(indices) => {
+                        self.arrays
+                            .insert(declaration.name.clone(), (indices, declaration.domain));
+                    }
+                    None => {
+                        self.domains
+                            .insert(declaration.name.clone(), declaration.domain);
+                    }
+                }
+                declarations.push(declaration);
+            } else if self.check_identifier("channel") {
+                if !requests.is_empty() {
+                    return Err("Channels must appear before the first request".to_string());
+                }
+                channels.push(self.parse_channel()?);
+            } else if self.check_identifier("enum") {
+                if !requests.is_empty() {
+                    return Err("Enums must appear before the first request".to_string());
+                }
+                enums.push(self.parse_enum()?);
+            } else if self.check_identifier("init") {
+                if !requests.is_empty() {
+                    return Err("Initial values must appear before the first request".to_string());
+                }
+                self.parse_init(&mut inits)?;
+            } else if self.check_identifier("fn") {
+                self.parse_fn(table)?;
+            } else if self.check_identifier("import") {
+                return Err("Imports must come before everything else in a file".to_string());
             } else if self.is_at_end() {
                 break;
             } else {
                 return Err(format!(
-                    "Expected 'request' keyword, found {:?}",
+                    "Expected 'request' keyword, found {}",
                     self.tokens[self.current]
                 ));
             }
@@ -314,23 +1344,597 @@ impl Parser {
         if requests.is_empty() {
             return Err("No requests found in program".to_string());
         }
+        if all_crashable {
+            for request in &mut requests {
+                request.crashable = true;
+            }
+        }
+        // A global without an initial value starts at 0
+        for (name, declared_at) in &self.zero_checks {
+            let domain = self.domains[name];
+            if !inits.iter().any(|init| &init.name == name) {
+                self.furthest.set(*declared_at);
+                return Err(format!(
+                    "Domain {}..{} of '{}' does not contain its initial value 0",
+                    domain.lo, domain.hi, name
+                ));
+            }
+        }
 
-        Ok(Program { requests })
+        Ok(Program {
+            enums,
+            declarations,
+            inits,
+            channels,
+            requests,
+            loops: std::mem::take(&mut self.loops),
+        })
     }
 
-    fn parse_request(&mut self, table: &mut ExprHc) -> Result<Request, String> {
+    /// Parse `enum Color { RED, GREEN }`. The constants stand for 0, 1, ... in order.
+    fn parse_enum(&mut self) -> Result<EnumDecl, String> {
+        self.advance(); // consume 'enum'
+        let name = match self.advance() {
+            Some(Token::Identifier(name)) => name.clone(),
+            _ => return Err("Expected enum name".to_string()),
+        };
+        self.claim(&name)?;
+        if self.enums.contains_key(&name) {
+            return Err(format!("Enum '{}' is declared twice", name));
+        }
+        self.consume(Token::LBrace, &format!("Expected '{{' after 'enum {}'", name))?;
+        let mut constants: Vec<String> = Vec::new();
+        while !self.match_token(&[Token::RBrace]) {
+            let constant = match self.advance() {
+                Some(Token::Identifier(constant)) => constant.clone(),
+                _ => return Err(format!("Expected a constant in enum '{}'", name)),
+            };
+            self.claim(&constant)?;
+            if self.constants.contains_key(&constant) || constants.contains(&constant) {
+                return Err(format!("Constant '{}' is declared twice", constant));
+            }
+            if self.domains.contains_key(&constant) || self.arrays.contains_key(&constant) {
+                return Err(format!("Constant '{}' is already a variable", constant));
+            }
+            constants.push(constant);
+            if !self.match_token(&[Token::Comma]) {
+                self.consume(Token::RBrace, &format!("Expected ',' or '}}' in enum '{}'", name))?;
+                break;
+            }
+        }
+        if constants.is_empty() {
+            return Err(format!("Enum '{}' has no constants", name));
+        }
+        for (value, constant) in constants.iter().enumerate() {
+            self.constants.insert(constant.clone(), value as i64);
+        }
+        self.enums.insert(name.clone(), constants.clone());
+        self.match_token(&[Token::Semicolon]);
+        Ok(EnumDecl { name, constants })
+    }
+
+    /// Parse `init { X := 2; A[1] := 3 }` or `init X in {0, 1}` (also `init X in 0..3`).
+    /// Every combination of the listed values is an initial global state.
+    fn parse_init(&mut self, inits: &mut Vec<Init>) -> Result<(), String> {
+        self.advance(); // consume 'init'
+
+        if self.match_token(&[Token::LBrace]) {
+            while !self.match_token(&[Token::RBrace]) {
+                let (name, index) = self.init_target()?;
+                self.consume(Token::Assign, "Expected ':=' in init block")?;
+                let value = self.value()?;
+                let init = Init {
+                    name,
+                    index,
+                    values: vec![value],
+                };
+                self.check_init(&init, inits)?;
+                inits.push(init);
+                if !self.match_token(&[Token::Semicolon]) {
+                    self.consume(Token::RBrace, "Expected ';' or '}' in init block")?;
+                    break;
+                }
+            }
+            return Ok(());
+        }
+
+        let (name, index) = self.init_target()?;
+        if !self.match_identifier("in") {
+            return Err(format!("Expected 'in' after '{}' in init", name));
+        }
+        let mut values = Vec::new();
+        if self.match_token(&[Token::LBrace]) {
+            loop {
+                let value = self.value()?;
+                if !values.contains(&value) {
+                    values.push(value);
+                }
+                if self.match_token(&[Token::RBrace]) {
+                    break;
+                }
+                self.consume(Token::Comma, "Expected ',' or '}' in initial values")?;
+            }
+        } else {
+            let lo = self.value()?;
+            self.consume(Token::DotDot, "Expected '..' or '{' after 'in'")?;
+            let hi = self.value()?;
+            if lo > hi {
+                return Err(format!("Empty initial range {}..{} for '{}'", lo, hi, name));
+            }
+            values.extend(lo..=hi);
+        }
+        let init = Init {
+            name,
+            index,
+            values,
+        };
+        self.check_init(&init, inits)?;
+        inits.push(init);
+        self.match_token(&[Token::Semicolon]);
+        Ok(())
+    }
+
+    /// Parse the `X` or `A[i]` being initialized
+    fn init_target(&mut self) -> Result<(String, Option<i64>), String> {
+        let name = match self.advance() {
+            Some(Token::Identifier(name)) => name.clone(),
+            _ => return Err("Expected global variable in init".to_string()),
+        };
+        let index = if self.match_token(&[Token::LBracket]) {
+            let index = self.signed_number()?;
+            self.consume(Token::RBracket, "Expected ']' after index")?;
+            Some(index)
+        } else {
+            None
+        };
+        Ok((name, index))
+    }
+
+    /// Initial values must name globals, be given once, and lie in their declared domains
+    fn check_init(&self, init: &Init, earlier: &[Init]) -> Result<(), String> {
+        if self.channels.contains_key(&init.name) {
+            return Err(format!("Channel '{}' always starts empty", init.name));
+        }
+        if !init.name.chars().next().is_some_and(|c| c.is_uppercase()) {
+            return Err(format!(
+                "Only globals can be initialized, found '{}'",
+                init.name
+            ));
+        }
+        let domain = match (init.index, self.arrays.get(&init.name)) {
+            (None, Some(_)) => {
+                return Err(format!(
+                    "Array '{}' is initialized element by element",
+                    init.name
+                ));
+            }
+            (Some(index), Some((indices, _))) if !indices.contains(index) => {
+                return Err(format!(
+                    "Index {} is out of range {}..{} for '{}'",
+                    index, indices.lo, indices.hi, init.name
+                ));
+            }
+            (Some(_), Some((_, domain))) => Some(*domain),
+            (Some(_), None) => return Err(format!("'{}' is not an array", init.name)),
+            (None, None) => self.domains.get(&init.name).copied(),
+        };
+        if earlier
+            .iter()
+            .any(|other| other.name == init.name && other.index == init.index)
+        {
+            return Err(format!("'{}' is initialized twice", init.name));
+        }
+        let outside = domain.and_then(|d| {
+            let value = init.values.iter().find(|v| **v < d.lo || **v > d.hi)?;
+            Some((*value, d))
+        });
+        if let Some((value, domain)) = outside {
+            return Err(format!(
+                "Initial value {} of '{}' is outside its domain {}..{}",
+                value, init.name, domain.lo, domain.hi
+            ));
+        }
+        Ok(())
+    }
+
+    /// Parse `global X : lo..hi mode;`, `local x : lo..hi mode;` or `global A[lo..hi] : lo..hi mode;`.
+    /// The overflow mode is optional and defaults to `error`.
+    fn parse_declaration(&mut self) -> Result<VarDecl, String> {
+        let is_global = self.check_identifier("global");
+        self.advance(); // consume 'global' or 'local'
+
+        let name = match self.advance() {
+            Some(Token::Identifier(name)) => name.clone(),
+            _ => return Err("Expected variable name in declaration".to_string()),
+        };
+        self.claim(&name)?;
+        let starts_uppercase = name.chars().next().is_some_and(|c| c.is_uppercase());
+        if is_global && !starts_uppercase {
+            return Err(format!(
+                "Global variable '{}' must start with an uppercase letter",
+                name
+            ));
+        }
+        if !is_global && starts_uppercase {
+            return Err(format!(
+                "Local variable '{}' must start with a lowercase letter",
+                name
+            ));
+        }
+        if self.domains.contains_key(&name) || self.arrays.contains_key(&name) {
+            return Err(format!("Variable '{}' is declared twice", name));
+        }
+        if self.constants.contains_key(&name) {
+            return Err(format!("'{}' is an enum constant", name));
+        }
+
+        let indices = if self.match_token(&[Token::LBracket]) {
+            if !is_global {
+                return Err(format!("Array '{}' must be global", name));
+            }
+            let lo = self.signed_number()?;
+            self.consume(Token::DotDot, "Expected '..' in array indices")?;
+            let hi = self.signed_number()?;
+            self.consume(Token::RBracket, "Expected ']' after array indices")?;
+            if lo > hi {
+                return Err(format!("Empty indices {}..{} for '{}'", lo, hi, name));
+            }
+            Some(Indices { lo, hi })
+        } else {
+            None
+        };
+
+        self.consume(Token::Colon, "Expected ':' after variable name")?;
+        let enum_type = match self.peek() {
+            Some(Token::Identifier(enum_name)) => Some(enum_name.clone()),
+            _ => None,
+        };
+        // An enum type is the domain of its constants
+        let (lo, hi) = match &enum_type {
+            Some(enum_name) => {
+                self.advance();
+                match self.enums.get(enum_name) {
+                    Some(constants) => (0, constants.len() as i64 - 1),
+                    None => return Err(format!("Unknown enum '{}'", enum_name)),
+                }
+            }
+            None => {
+                let lo = self.signed_number()?;
+                self.consume(Token::DotDot, "Expected '..' in domain")?;
+                (lo, self.signed_number()?)
+            }
+        };
+        if lo > hi {
+            return Err(format!("Empty domain {}..{} for '{}'", lo, hi, name));
+        }
+        // Locals and array elements start at 0, so the initial value must be in range.
+        // A global may be given other initial values with `init`, checked later.
+        if (!is_global || indices.is_some()) && (lo > 0 || hi < 0) {
+            return Err(format!(
+                "Domain {}..{} of '{}' does not contain its initial value 0",
+                lo, hi, name
+            ));
+        }
+
+        let overflow = if self.match_identifier("wrap") {
+            Overflow::Wrap
+        } else if self.match_identifier("saturate") {
+            Overflow::Saturate
+        } else {
+            self.match_identifier("error");
+            Overflow::Error
+        };
+        self.consume(Token::Semicolon, "Expected ';' after declaration")?;
+
+        Ok(VarDecl {
+            name,
+            indices,
+            domain: Domain { lo, hi, overflow },
+            enum_type,
+        })
+    }
+
+    /// Parse `channel C[capacity] : lo..hi;`, optionally followed by `bag`
+    fn parse_channel(&mut self) -> Result<ChannelDecl, String> {
+        self.advance(); // consume 'channel'
+        let name = match self.advance() {
+            Some(Token::Identifier(name)) => name.clone(),
+            _ => return Err("Expected channel name".to_string()),
+        };
+        self.claim(&name)?;
+        if !name.chars().next().is_some_and(|c| c.is_uppercase()) {
+            return Err(format!("Channel '{}' must start with an uppercase letter", name));
+        }
+        if self.channels.contains_key(&name)
+            || self.domains.contains_key(&name)
+            || self.arrays.contains_key(&name)
+            || self.constants.contains_key(&name)
+        {
+            return Err(format!("'{}' is already declared", name));
+        }
+        self.consume(Token::LBracket, &format!("Expected the capacity of '{}' in brackets", name))?;
+        let capacity = match self.advance() {
+            Some(Token::Number(n)) if *n > 0 => *n,
+            _ => return Err(format!("Expected a positive capacity for '{}'", name)),
+        };
+        self.consume(Token::RBracket, "Expected ']' after the capacity")?;
+        self.consume(Token::Colon, "Expected ':' after channel capacity")?;
+        let lo = self.signed_number()?;
+        self.consume(Token::DotDot, "Expected '..' in domain")?;
+        let hi = self.signed_number()?;
+        if lo > hi {
+            return Err(format!("Empty domain {}..{} for '{}'", lo, hi, name));
+        }
+        let bag = self.match_identifier("bag");
+        self.consume(Token::Semicolon, "Expected ';' after declaration")?;
+
+        let channel = Channel { capacity, lo, hi, bag };
+        self.channels.insert(name.clone(), channel);
+        Ok(ChannelDecl { name, channel })
+    }
+
+    /// The channel in `send(C, v)` or `recv(C)`, after the opening parenthesis
+    fn channel(&mut self, primitive: &str) -> Result<(String, Channel), String> {
+        let name = match self.advance() {
+            Some(Token::Identifier(name)) => name.clone(),
+            _ => return Err(format!("Expected a channel in '{}'", primitive)),
+        };
+        let Some(channel) = self.channels.get(&name) else {
+            return Err(format!("'{}' is not a declared channel", name));
+        };
+        // An abort could not take back a message that another request may have received
+        if self.in_transaction {
+            return Err(format!("'{}' is not allowed inside a transaction", primitive));
+        }
+        Ok((name, *channel))
+    }
+
+    /// Parse `fn name(x, y) { body }`. The body is checked here and inlined at every call.
+    fn parse_fn(&mut self, table: &mut ExprHc) -> Result<(), String> {
+        self.advance(); // consume 'fn'
+        let name = match self.advance() {
+            Some(Token::Identifier(name)) => name.clone(),
+            _ => return Err("Expected function name after 'fn'".to_string()),
+        };
+        self.claim(&name)?;
+        if self.fns.contains_key(&name) {
+            return Err(format!("Function '{}' is defined twice", name));
+        }
+
+        self.consume(Token::LParen, "Expected '(' after function name")?;
+        let mut params: Vec<String> = Vec::new();
+        if !self.match_token(&[Token::RParen]) {
+            loop {
+                let param = match self.advance() {
+                    Some(Token::Identifier(param)) => param.clone(),
+                    _ => return Err(format!("Expected parameter name in function '{}'", name)),
+                };
+                if !param.chars().next().is_some_and(|c| c.is_lowercase()) {
+                    return Err(format!(
+                        "Parameter '{}' must start with a lowercase letter",
+                        param
+                    ));
+                }
+                if params.contains(&param) {
+                    return Err(format!("Parameter '{}' is declared twice", param));
+                }
+                params.push(param);
+                if self.match_token(&[Token::RParen]) {
+                    break;
+                }
+                self.consume(Token::Comma, "Expected ',' or ')' after parameter")?;
+            }
+        }
+
+        self.consume(Token::LBrace, "Expected '{' after function parameters")?;
+        let body = self.current;
+        // The function is not visible inside its own body, so recursion is rejected
+        self.scopes.push(FnScope {
+            suffix: format!("@{}", name),
+            locals: HashSet::default(),
+        });
+        let checked = self.expression(table);
+        self.scopes.pop();
+        checked?;
+        self.consume(Token::RBrace, "Expected '}' after function body")?;
+
+        self.fns.insert(name, FnDef { params, body });
+        Ok(())
+    }
+
+    /// Inline a call `name(args)`; the opening parenthesis has been consumed.
+    /// The arguments are assigned to the renamed parameters, the other locals
+    /// of the body start at 0, and the call evaluates to the value of the body.
+    fn call(&mut self, name: String, table: &mut ExprHc) -> Result<Hc<Expr>, String> {
+        let def = match self.fns.get(&name) {
+            Some(def) => def.clone(),
+            None => return Err(format!("Call to undefined function '{}'", name)),
+        };
+
+        self.pending_calls.push(name.clone());
+        let mut args = Vec::new();
+        if !self.match_token(&[Token::RParen]) {
+            loop {
+                args.push(self.expression(table)?);
+                if self.match_token(&[Token::RParen]) {
+                    break;
+                }
+                self.consume(Token::Comma, "Expected ',' or ')' after argument")?;
+            }
+        }
+        self.pending_calls.pop();
+        if args.len() != def.params.len() {
+            return Err(format!(
+                "Function '{}' expects {} arguments, found {}",
+                name,
+                def.params.len(),
+                args.len()
+            ));
+        }
+
+        // A call nested in the arguments of another call to the same function
+        // must not overwrite the parameters that are already assigned
+        let outer = self.pending_calls.iter().filter(|f| **f == name).count();
+        let suffix = if outer == 0 {
+            format!("@{}", name)
+        } else {
+            format!("@{}#{}", name, outer + 1)
+        };
+
+        let resume = self.current;
+        self.current = def.body;
+        self.scopes.push(FnScope {
+            suffix: suffix.clone(),
+            locals: HashSet::default(),
+        });
+        // The body was checked outside any loop, and a `break` in it cannot leave a loop of the caller
+        let loop_depth = std::mem::take(&mut self.loop_depth);
+        let body = self.expression(table);
+        self.loop_depth = loop_depth;
+        let scope = self.scopes.pop().unwrap();
+        self.current = resume;
+        let body = body.map_err(|err| format!("{} (in call to '{}')", err, name))?;
+
+        let params: Vec<String> = def.params.iter().map(|p| format!("{}{}", p, suffix)).collect();
+        let mut locals: Vec<String> = scope.locals.into_iter().collect();
+        locals.sort();
+        let mut expr = body;
+        for local in locals.iter().rev() {
+            if !params.contains(local) {
+                let zero = table.number(0);
+                let reset = table.assign(local.clone(), zero);
+                expr = table.sequence(reset, expr);
+            }
+        }
+        for (param, arg) in params.into_iter().zip(args).rev() {
+            let assign = table.assign(param, arg);
+            expr = table.sequence(assign, expr);
+        }
+        Ok(expr)
+    }
+
+    /// Rename a local variable inside an inlined procedure body
+    fn resolve(&mut self, name: String) -> String {
+        let is_local = name.chars().next().is_some_and(|c| c.is_lowercase());
+        match self.scopes.last_mut() {
+            Some(scope) if is_local => {
+                let renamed = format!("{}{}", name, scope.suffix);
+                scope.locals.insert(renamed.clone());
+                renamed
+            }
+            _ => name,
+        }
+    }
+
+    /// A signed number or an enum constant
+    fn value(&mut self) -> Result<i64, String> {
+        let constant = match self.peek() {
+            Some(Token::Identifier(name)) => Some(name.clone()),
+            _ => None,
+        };
+        let Some(constant) = constant else {
+            return self.signed_number();
+        };
+        self.advance();
+        match self.constants.get(&constant) {
+            Some(value) => Ok(*value),
+            None => Err(format!("'{}' is not an enum constant", constant)),
+        }
+    }
+
+    fn signed_number(&mut self) -> Result<i64, String> {
+        let negative = self.match_token(&[Token::Minus]);
+        match self.advance() {
+            Some(Token::Number(n)) => Ok(if negative { -n } else { *n }),
+            _ => Err("Expected a number".to_string()),
+        }
+    }
+
+    fn parse_request(&mut self, table: &mut ExprHc) -> Result<Request, String> {
         self.consume(Token::Request, "Expected 'request' keyword")?;
 
         let name = match self.advance() {
             Some(Token::Identifier(name)) => name.clone(),
             _ => return Err("Expected request name".to_string()),
         };
+        self.claim(&name)?;
+
+        let params = if self.match_token(&[Token::LParen]) {
+            self.parse_params()?
+        } else {
+            vec![]
+        };
+
+        let returns = if self.match_token(&[Token::Colon]) {
+            let enum_name = match self.advance() {
+                Some(Token::Identifier(enum_name)) => enum_name.clone(),
+                _ => return Err(format!("Expected the response enum of '{}'", name)),
+            };
+            if !self.enums.contains_key(&enum_name) {
+                return Err(format!("Unknown enum '{}'", enum_name));
+            }
+            Some(enum_name)
+        } else {
+            None
+        };
 
         self.consume(Token::LBrace, "Expected '{' after request name")?;
         let body = self.expression(table)?;
         self.consume(Token::RBrace, "Expected '}' after request body")?;
+        check_tuples(&body, true, returns.is_none())?;
+
+        Ok(Request {
+            name,
+            params,
+            returns,
+            crashable: false,
+            body,
+        })
+    }
+
+    /// Parse `x in lo..hi, y in lo..hi)` after the opening parenthesis of a request.
+    fn parse_params(&mut self) -> Result<Vec<Param>, String> {
+        let mut params: Vec<Param> = Vec::new();
+        if self.match_token(&[Token::RParen]) {
+            return Ok(params);
+        }
+        loop {
+            let name = match self.advance() {
+                Some(Token::Identifier(name)) => name.clone(),
+                _ => return Err("Expected parameter name".to_string()),
+            };
+            if !name.chars().next().is_some_and(|c| c.is_lowercase()) {
+                return Err(format!(
+                    "Parameter '{}' must start with a lowercase letter",
+                    name
+                ));
+            }
+            if params.iter().any(|param| param.name == name) {
+                return Err(format!("Parameter '{}' is declared twice", name));
+            }
+            if !self.match_identifier("in") {
+                return Err(format!("Expected 'in' after parameter '{}'", name));
+            }
+            let lo = self.signed_number()?;
+            self.consume(Token::DotDot, "Expected '..' in parameter range")?;
+            let hi = self.signed_number()?;
+            if lo > hi {
+                return Err(format!("Empty range {}..{} for parameter '{}'", lo, hi, name));
+            }
+            let declared = self.domains.get(&name);
+            if let Some(domain) = declared.filter(|d| lo < d.lo || hi > d.hi) {
+                return Err(format!(
+                    "Range {}..{} of parameter '{}' exceeds its declared domain {}",
+                    lo, hi, name, domain
+                ));
+            }
+            params.push(Param { name, lo, hi });
 
-        Ok(Request { name, body })
+            if self.match_token(&[Token::RParen]) {
+                return Ok(params);
+            }
+            self.consume(Token::Comma, "Expected ',' or ')' after parameter")?;
+        }
     }
 
     fn expression(&mut self, table: &mut ExprHc) -> Result<Hc<Expr>, String> {
@@ -351,10 +1955,38 @@ impl Parser {
     fn assignment(&mut self, table: &mut ExprHc) -> Result<Hc<Expr>, String> {
         if let Some(Token::Identifier(name)) = self.peek() {
             let name = name.clone();
+            if self.peek_next() == Some(&Token::LBracket) {
+                // `A[i] := e` or an expression that starts with `A[i]`
+                let start = self.current;
+                self.advance(); // consume the identifier
+                let (indices, domain) = self.array(&name)?;
+                self.advance(); // consume the [
+                let index = self.expression(table)?;
+                self.consume(Token::RBracket, "Expected ']' after index")?;
+                if self.match_token(&[Token::Assign]) {
+                    let value = self.assignment(table)?;
+                    let value = table.bounded(name.clone(), domain, value);
+                    return Ok(table.store(name, indices, index, value));
+                }
+                self.current = start;
+            }
             if self.peek_next() == Some(&Token::Assign) {
+                if self.arrays.contains_key(&name) {
+                    return Err(format!("Array '{}' needs an index", name));
+                }
+                if self.channels.contains_key(&name) {
+                    return Err(format!("Channel '{}' can only be used with send and recv", name));
+                }
+                if self.constants.contains_key(&name) {
+                    return Err(format!("Cannot assign to enum constant '{}'", name));
+                }
                 self.advance(); // consume the identifier
                 self.advance(); // consume the :=
-                let value = self.assignment(table)?;
+                let mut value = self.assignment(table)?;
+                if let Some(domain) = self.domains.get(&name) {
+                    value = table.bounded(name.clone(), *domain, value);
+                }
+                let name = self.resolve(name);
                 return Ok(table.assign(name, value));
             }
         }
@@ -385,25 +2017,48 @@ impl Parser {
     }
 
     fn equality(&mut self, table: &mut ExprHc) -> Result<Hc<Expr>, String> {
-        let mut expr = self.term(table)?;
+        let mut expr = self.comparison(table)?;
 
         if self.match_token(&[Token::Equal]) {
-            let right = self.term(table)?;
+            let right = self.comparison(table)?;
             expr = table.equal(expr, right);
+        } else if self.match_token(&[Token::NotEqual]) {
+            let right = self.comparison(table)?;
+            expr = table.not_equal(expr, right);
+        }
+
+        Ok(expr)
+    }
+
+    fn comparison(&mut self, table: &mut ExprHc) -> Result<Hc<Expr>, String> {
+        let mut expr = self.term(table)?;
+
+        if self.match_token(&[Token::Less]) {
+            let right = self.term(table)?;
+            expr = table.less(expr, right);
+        } else if self.match_token(&[Token::LessEqual]) {
+            let right = self.term(table)?;
+            expr = table.less_equal(expr, right);
+        } else if self.match_token(&[Token::Greater]) {
+            let right = self.term(table)?;
+            expr = table.greater(expr, right);
+        } else if self.match_token(&[Token::GreaterEqual]) {
+            let right = self.term(table)?;
+            expr = table.greater_equal(expr, right);
         }
 
         Ok(expr)
     }
 
     fn term(&mut self, table: &mut ExprHc) -> Result<Hc<Expr>, String> {
-        let mut expr = self.unary(table)?;
+        let mut expr = self.factor(table)?;
 
         loop {
             if self.match_token(&[Token::Plus]) {
-                let right = self.unary(table)?;
+                let right = self.factor(table)?;
                 expr = table.add(expr, right);
             } else if self.match_token(&[Token::Minus]) {
-                let right = self.unary(table)?;
+                let right = self.factor(table)?;
                 expr = table.subtract(expr, right);
             } else {
                 break;
@@ -413,6 +2068,27 @@ impl Parser {
         Ok(expr)
     }
 
+    fn factor(&mut self, table: &mut ExprHc) -> Result<Hc<Expr>, String> {
+        let mut expr = self.unary(table)?;
+
+        loop {
+            if self.match_token(&[Token::Star]) {
+                let right = self.unary(table)?;
+                expr = table.multiply(expr, right);
+            } else if self.match_token(&[Token::Slash]) {
+                let right = self.unary(table)?;
+                expr = table.divide(expr, right);
+            } else if self.match_token(&[Token::Percent]) {
+                let right = self.unary(table)?;
+                expr = table.modulo(expr, right);
+            } else {
+                break;
+            }
+        }
+
+        Ok(expr)
+    }
+
     fn unary(&mut self, table: &mut ExprHc) -> Result<Hc<Expr>, String> {
         if self.match_token(&[Token::Not]) {
             let expr = self.unary(table)?;
@@ -422,48 +2098,292 @@ impl Parser {
         self.primary(table)
     }
 
+    /// Parse a `{ e }` branch of `choose`
+    fn choice_branch(&mut self, table: &mut ExprHc) -> Result<Hc<Expr>, String> {
+        self.consume(Token::LBrace, "Expected '{' before a 'choose' branch")?;
+        let branch = self.expression(table)?;
+        self.consume(Token::RBrace, "Expected '}' after a 'choose' branch")?;
+        Ok(branch)
+    }
+
+    /// Parse the rest of `if(c){e}`, `if(c){e}else{e}` or `if(c){e}else if ...`
+    /// after the `if`. A missing else branch is `0`.
+    fn if_rest(&mut self, table: &mut ExprHc) -> Result<Hc<Expr>, String> {
+        self.consume(Token::LParen, "Expected '(' after 'if'")?;
+        let condition = self.expression(table)?;
+        self.consume(Token::RParen, "Expected ')' after condition")?;
+        self.consume(Token::LBrace, "Expected '{' after condition")?;
+        let then_branch = self.expression(table)?;
+        self.consume(Token::RBrace, "Expected '}' after then branch")?;
+
+        let else_branch = if !self.match_token(&[Token::Else]) {
+            table.number(0)
+        } else if self.match_token(&[Token::If]) {
+            self.if_rest(table)?
+        } else {
+            self.consume(Token::LBrace, "Expected '{' or 'if' after 'else'")?;
+            let else_branch = self.expression(table)?;
+            self.consume(Token::RBrace, "Expected '}' after else branch")?;
+            else_branch
+        };
+
+        Ok(table.if_expr(condition, then_branch, else_branch))
+    }
+
     fn primary(&mut self, table: &mut ExprHc) -> Result<Hc<Expr>, String> {
+        // At the end, advance would hand back the previous token
+        if self.is_at_end() {
+            self.reach();
+            return Err("Unexpected end of input".to_string());
+        }
         let token = self.advance();
 
         match token {
             Some(Token::Number(n)) => Ok(table.number(*n)),
-            Some(Token::Identifier(name)) => Ok(table.variable(name.clone())),
+            Some(Token::Identifier(name)) => {
+                let name = name.clone();
+                if let Some(value) = self.constants.get(&name) {
+                    return Ok(table.symbol(name, *value));
+                }
+                if self.match_token(&[Token::LParen]) {
+                    return self.call(name, table);
+                }
+                if self.channels.contains_key(&name) {
+                    return Err(format!("Channel '{}' can only be used with send and recv", name));
+                }
+                if self.check(&Token::LBracket) || self.arrays.contains_key(&name) {
+                    let (indices, _) = self.array(&name)?;
+                    self.consume(Token::LBracket, &format!("Array '{}' needs an index", name))?;
+                    let index = self.expression(table)?;
+                    self.consume(Token::RBracket, "Expected ']' after index")?;
+                    return Ok(table.load(name, indices, index));
+                }
+                let name = self.resolve(name);
+                Ok(table.variable(name))
+            }
             Some(Token::Question) => Ok(table.unknown()),
-            Some(Token::Yield) => Ok(table.yield_expr()),
+            Some(Token::Yield) => {
+                if self.atomic_depth > 0 {
+                    return Err("'yield' is not allowed inside an atomic block".to_string());
+                }
+                if self.await_depth > 0 {
+                    return Err("'yield' is not allowed inside an 'await' condition".to_string());
+                }
+                Ok(table.yield_expr())
+            }
             Some(Token::Exit) => Ok(table.exit()),
-            Some(Token::If) => {
-                self.consume(Token::LParen, "Expected '(' after 'if'")?;
+            Some(Token::Abort) => {
+                if !self.in_transaction {
+                    return Err("'abort' outside of a transaction".to_string());
+                }
+                Ok(table.abort())
+            }
+            Some(Token::Assume) => {
+                self.consume(Token::LParen, "Expected '(' after 'assume'")?;
                 let condition = self.expression(table)?;
-                self.consume(Token::RParen, "Expected ')' after condition")?;
-                self.consume(Token::LBrace, "Expected '{' after condition")?;
-                let then_branch = self.expression(table)?;
-                self.consume(Token::RBrace, "Expected '}' after then branch")?;
-                self.consume(Token::Else, "Expected 'else' after then branch")?;
-                self.consume(Token::LBrace, "Expected '{' after 'else'")?;
-                let else_branch = self.expression(table)?;
-                self.consume(Token::RBrace, "Expected '}' after else branch")?;
-
-                Ok(table.if_expr(condition, then_branch, else_branch))
+                self.consume(Token::RParen, "Expected ')' after 'assume' condition")?;
+                Ok(table.assume(condition))
+            }
+            Some(Token::Assert) => {
+                self.consume(Token::LParen, "Expected '(' after 'assert'")?;
+                let condition = self.expression(table)?;
+                self.consume(Token::RParen, "Expected ')' after 'assert' condition")?;
+                Ok(table.assert(condition.to_string(), condition))
             }
+            Some(Token::If) => self.if_rest(table),
             Some(Token::While) => {
+                let at = self.current - 1;
                 self.consume(Token::LParen, "Expected '(' after 'while'")?;
                 let condition = self.expression(table)?;
                 self.consume(Token::RParen, "Expected ')' after condition")?;
                 self.consume(Token::LBrace, "Expected '{' after condition")?;
-                let body = self.expression(table)?;
+                self.loop_depth += 1;
+                let body = self.expression(table);
+                self.loop_depth -= 1;
+                let body = body?;
                 self.consume(Token::RBrace, "Expected '}' after body")?;
 
-                Ok(table.while_expr(condition, body))
+                let loop_expr = table.while_expr(condition, body);
+                if let Some(location) = self.location(at) {
+                    self.loops.push((loop_expr.clone(), location));
+                }
+                Ok(loop_expr)
+            }
+            Some(Token::Choose) => {
+                if self.match_token(&[Token::LParen]) {
+                    let lo = self.signed_number()?;
+                    self.consume(Token::DotDot, "Expected '..' in 'choose'")?;
+                    let hi = self.signed_number()?;
+                    self.consume(Token::RParen, "Expected ')' after 'choose' range")?;
+                    if lo > hi {
+                        return Err(format!("Empty range {}..{} in 'choose'", lo, hi));
+                    }
+                    return Ok(table.choose(lo, hi));
+                }
+                // `choose { A } or { B } or ...`, nested to the right
+                let mut branches = vec![self.choice_branch(table)?];
+                if !self.check_identifier("or") {
+                    return Err("Expected 'or' after the first 'choose' branch".to_string());
+                }
+                while self.match_identifier("or") {
+                    branches.push(self.choice_branch(table)?);
+                }
+                let mut expr = branches.pop().unwrap();
+                while let Some(branch) = branches.pop() {
+                    expr = table.choice(branch, expr);
+                }
+                Ok(expr)
+            }
+            Some(Token::Break) => {
+                if self.loop_depth == 0 {
+                    return Err("'break' outside of a loop".to_string());
+                }
+                Ok(table.break_expr())
+            }
+            Some(Token::Continue) => {
+                if self.loop_depth == 0 {
+                    return Err("'continue' outside of a loop".to_string());
+                }
+                Ok(table.continue_expr())
+            }
+            Some(Token::Return) => {
+                if !self.scopes.is_empty() {
+                    return Err("'return' is not allowed inside a procedure".to_string());
+                }
+                // A bare `return` finishes with 0
+                let value = if matches!(
+                    self.peek(),
+                    None | Some(Token::Semicolon) | Some(Token::RBrace)
+                ) {
+                    table.number(0)
+                } else {
+                    self.assignment(table)?
+                };
+                Ok(table.return_expr(value))
+            }
+            Some(Token::Atomic) => {
+                self.consume(Token::LBrace, "Expected '{' after 'atomic'")?;
+                self.atomic_depth += 1;
+                let body = self.expression(table);
+                self.atomic_depth -= 1;
+                let body = body?;
+                self.consume(Token::RBrace, "Expected '}' after atomic block")?;
+
+                Ok(table.atomic(body))
+            }
+            Some(Token::Transaction) => {
+                if self.in_transaction {
+                    return Err("Transactions cannot be nested".to_string());
+                }
+                self.consume(Token::LBrace, "Expected '{' after 'transaction'")?;
+                self.in_transaction = true;
+                let body = self.expression(table);
+                self.in_transaction = false;
+                let body = body?;
+                self.consume(Token::RBrace, "Expected '}' after transaction")?;
+
+                Ok(table.transaction(body))
+            }
+            Some(Token::Lock) => {
+                let lock = self.sync_operand("lock")?;
+                Ok(table.lock(lock))
+            }
+            Some(Token::Unlock) => {
+                let lock = self.sync_operand("unlock")?;
+                Ok(table.unlock(lock))
+            }
+            Some(Token::Acquire) => {
+                let sem = self.sync_operand("acquire")?;
+                // The semaphore counts the permits in use; its capacity is the top of its domain
+                match self.domains.get(&sem) {
+                    Some(domain) if domain.hi > 0 => Ok(table.acquire(sem, domain.hi)),
+                    _ => Err(format!(
+                        "Semaphore '{}' needs a declared capacity, e.g. 'global {} : 0..2;'",
+                        sem, sem
+                    )),
+                }
+            }
+            Some(Token::Release) => {
+                let sem = self.sync_operand("release")?;
+                Ok(table.release(sem))
+            }
+            Some(Token::Send) => {
+                self.consume(Token::LParen, "Expected '(' after 'send'")?;
+                let (name, channel) = self.channel("send")?;
+                self.consume(Token::Comma, &format!("Expected ',' after 'send({}'", name))?;
+                let value = self.expression(table)?;
+                self.consume(Token::RParen, "Expected ')' after 'send' value")?;
+                Ok(table.send(name, channel, value))
+            }
+            Some(Token::Recv) => {
+                self.consume(Token::LParen, "Expected '(' after 'recv'")?;
+                let (name, channel) = self.channel("recv")?;
+                self.consume(Token::RParen, &format!("Expected ')' after 'recv({}'", name))?;
+                Ok(table.recv(name, channel))
+            }
+            Some(Token::Await) => {
+                self.consume(Token::LParen, "Expected '(' after 'await'")?;
+                self.await_depth += 1;
+                let condition = self.expression(table);
+                self.await_depth -= 1;
+                let condition = condition?;
+                self.consume(Token::RParen, "Expected ')' after 'await' condition")?;
+                Ok(table.await_expr(condition))
             }
             Some(Token::LParen) => {
                 let expr = self.expression(table)?;
-                self.consume(Token::RParen, "Expected ')' after expression")?;
-                Ok(expr)
+                if !self.match_token(&[Token::Comma]) {
+                    self.consume(Token::RParen, "Expected ')' after expression")?;
+                    return Ok(expr);
+                }
+                // `(a, b, ...)` is a tuple
+                let mut items = vec![expr];
+                loop {
+                    items.push(self.expression(table)?);
+                    if self.match_token(&[Token::RParen]) {
+                        break;
+                    }
+                    self.consume(Token::Comma, "Expected ',' or ')' in tuple")?;
+                }
+                Ok(table.tuple(items))
             }
-            _ => Err(format!("Unexpected token: {:?}", token)),
+            Some(token) => Err(format!("Unexpected {}", token)),
+            None => unreachable!("checked above"),
         }
     }
 
+    /// Indices and element domain of a declared array
+    fn array(&self, name: &str) -> Result<(Indices, Domain), String> {
+        match self.arrays.get(name) {
+            Some(array) => Ok(*array),
+            None => Err(format!("'{}' is not a declared array", name)),
+        }
+    }
+
+    /// Parse the `(L)` after `lock`, `unlock`, `acquire` or `release`
+    fn sync_operand(&mut self, primitive: &str) -> Result<String, String> {
+        self.consume(Token::LParen, &format!("Expected '(' after '{}'", primitive))?;
+        let name = match self.advance() {
+            Some(Token::Identifier(name)) => name.clone(),
+            _ => return Err(format!("Expected a global variable in '{}'", primitive)),
+        };
+        if !name.chars().next().is_some_and(|c| c.is_uppercase()) {
+            return Err(format!(
+                "'{}' needs a global variable, but '{}' is local",
+                primitive, name
+            ));
+        }
+        if self.arrays.contains_key(&name) {
+            return Err(format!("'{}' needs a variable, but '{}' is an array", primitive, name));
+        }
+        // An abort could not give back what the transaction took
+        if self.in_transaction {
+            return Err(format!("'{}' is not allowed inside a transaction", primitive));
+        }
+        self.consume(Token::RParen, &format!("Expected ')' after '{}({}'", primitive, name))?;
+        Ok(name)
+    }
+
     fn match_token(&mut self, types: &[Token]) -> bool {
         for t in types {
             if self.check(t) {
@@ -474,7 +2394,21 @@ impl Parser {
         false
     }
 
+    /// Check for an identifier used as a contextual keyword (e.g. `global`)
+    fn check_identifier(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Identifier(name)) if name == keyword)
+    }
+
+    fn match_identifier(&mut self, keyword: &str) -> bool {
+        if self.check_identifier(keyword) {
+            self.advance();
+            return true;
+        }
+        false
+    }
+
     fn check(&self, token_type: &Token) -> bool {
+        self.reach();
         if self.is_at_end() {
             return false;
         }
@@ -482,12 +2416,20 @@ impl Parser {
     }
 
     fn advance(&mut self) -> Option<&Token> {
+        self.reach();
         if !self.is_at_end() {
             self.current += 1;
         }
         self.previous()
     }
 
+    /// Record that the current token has been looked at
+    fn reach(&self) {
+        if self.current > self.furthest.get() {
+            self.furthest.set(self.current);
+        }
+    }
+
     fn is_at_end(&self) -> bool {
         self.current >= self.tokens.len() || self.tokens[self.current] == Token::Eof
     }
@@ -509,6 +2451,7 @@ impl Parser {
     }
 
     fn peek(&self) -> Option<&Token> {
+        self.reach();
         if self.is_at_end() {
             None
         } else {
@@ -526,34 +2469,66 @@ impl Parser {
 }
 
 // Lexer implementation
-pub fn tokenize(source: &str) -> Result<Vec<Token>, String> {
-    let mut tokens = Vec::new();
-    let mut chars = source.chars().peekable();
+pub fn tokenize(source: &str) -> Result<Vec<Token>, ParseError> {
+    let tokens = tokenize_with_spans(source)?;
+    Ok(tokens.into_iter().map(|(token, _)| token).collect())
+}
 
-    while let Some(&c) = chars.peek() {
+/// Split the source into tokens, each with the position where it starts
+pub fn tokenize_with_spans(source: &str) -> Result<Vec<(Token, Span)>, ParseError> {
+    lex(source, false)
+}
+
+/// Like `tokenize_with_spans`, but comments are kept as `Token::Comment`
+pub fn tokenize_with_comments(source: &str) -> Result<Vec<(Token, Span)>, ParseError> {
+    lex(source, true)
+}
+
+fn lex(source: &str, keep_comments: bool) -> Result<Vec<(Token, Span)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    // Line number and byte offset of the start of the current line
+    let mut line = 1;
+    let mut line_start = 0;
+
+    while let Some(&(offset, c)) = chars.peek() {
+        let span = Span {
+            line,
+            column: source[line_start..offset].chars().count() + 1,
+        };
         match c {
             ' ' | '\t' | '\n' | '\r' => {
                 chars.next();
+                if c == '\n' {
+                    line += 1;
+                    line_start = offset + 1;
+                }
             }
             '/' => {
                 chars.next(); // consume the first '/'
-                if let Some(&'/') = chars.peek() {
+                if let Some(&(_, '/')) = chars.peek() {
                     // This is a comment, consume the second '/'
                     chars.next();
                     // Consume all characters until the end of the line
-                    while let Some(&c) = chars.peek() {
+                    let mut end = source.len();
+                    while let Some(&(at, c)) = chars.peek() {
                         if c == '\n' {
+                            end = at;
                             break;
                         }
                         chars.next();
                     }
+                    if keep_comments {
+                        let text = source[offset..end].trim_end().to_string();
+                        tokens.push((Token::Comment(text), span));
+                    }
                 } else {
-                    return Err("Unexpected character: /".to_string());
+                    tokens.push((Token::Slash, span));
                 }
             }
             '0'..='9' => {
                 let mut number = String::new();
-                while let Some(&c) = chars.peek() {
+                while let Some(&(_, c)) = chars.peek() {
                     if c.is_ascii_digit() {
                         number.push(c);
                         chars.next();
@@ -561,11 +2536,11 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, String> {
                         break;
                     }
                 }
-                tokens.push(Token::Number(number.parse().unwrap()));
+                tokens.push((Token::Number(number.parse().unwrap()), span));
             }
             'a'..='z' | 'A'..='Z' | '_' => {
                 let mut identifier = String::new();
-                while let Some(&c) = chars.peek() {
+                while let Some(&(_, c)) = chars.peek() {
                     if c.is_alphanumeric() || c == '_' {
                         identifier.push(c);
                         chars.next();
@@ -574,95 +2549,202 @@ pub fn tokenize(source: &str) -> Result<Vec<Token>, String> {
                     }
                 }
 
-                match identifier.as_str() {
-                    "if" => tokens.push(Token::If),
-                    "else" => tokens.push(Token::Else),
-                    "while" => tokens.push(Token::While),
-                    "yield" => tokens.push(Token::Yield),
-                    "exit" => tokens.push(Token::Exit),
-                    "request" => tokens.push(Token::Request),
-                    _ => tokens.push(Token::Identifier(identifier)),
-                }
+                let token = match identifier.as_str() {
+                    "if" => Token::If,
+                    "else" => Token::Else,
+                    "while" => Token::While,
+                    "choose" => Token::Choose,
+                    "break" => Token::Break,
+                    "continue" => Token::Continue,
+                    "return" => Token::Return,
+                    "atomic" => Token::Atomic,
+                    "transaction" => Token::Transaction,
+                    "lock" => Token::Lock,
+                    "unlock" => Token::Unlock,
+                    "acquire" => Token::Acquire,
+                    "release" => Token::Release,
+                    "await" => Token::Await,
+                    "send" => Token::Send,
+                    "recv" => Token::Recv,
+                    "yield" => Token::Yield,
+                    "exit" => Token::Exit,
+                    "abort" => Token::Abort,
+                    "assume" => Token::Assume,
+                    "assert" => Token::Assert,
+                    "request" => Token::Request,
+                    _ => Token::Identifier(identifier),
+                };
+                tokens.push((token, span));
             }
             ':' => {
                 chars.next();
-                if let Some(&'=') = chars.peek() {
+                if let Some(&(_, '=')) = chars.peek() {
                     chars.next();
-                    tokens.push(Token::Assign);
+                    tokens.push((Token::Assign, span));
                 } else {
-                    return Err("Expected '=' after ':'".to_string());
+                    tokens.push((Token::Colon, span));
+                }
+            }
+            '.' => {
+                chars.next();
+                if let Some(&(_, '.')) = chars.peek() {
+                    chars.next();
+                    tokens.push((Token::DotDot, span));
+                } else {
+                    return Err(ParseError {
+                        message: "Expected '.' after '.'".to_string(),
+                        span: Some(span),
+                    });
                 }
             }
             '=' => {
                 chars.next();
-                if let Some(&'=') = chars.peek() {
+                if let Some(&(_, '=')) = chars.peek() {
+                    chars.next();
+                    tokens.push((Token::Equal, span));
+                } else {
+                    return Err(ParseError {
+                        message: "Expected '=' after '='".to_string(),
+                        span: Some(span),
+                    });
+                }
+            }
+            '<' => {
+                chars.next();
+                if let Some(&(_, '=')) = chars.peek() {
                     chars.next();
-                    tokens.push(Token::Equal);
+                    tokens.push((Token::LessEqual, span));
                 } else {
-                    return Err("Expected '=' after '='".to_string());
+                    tokens.push((Token::Less, span));
+                }
+            }
+            '>' => {
+                chars.next();
+                if let Some(&(_, '=')) = chars.peek() {
+                    chars.next();
+                    tokens.push((Token::GreaterEqual, span));
+                } else {
+                    tokens.push((Token::Greater, span));
                 }
             }
             '+' => {
                 chars.next();
-                tokens.push(Token::Plus);
+                tokens.push((Token::Plus, span));
             }
             '-' => {
                 chars.next();
-                tokens.push(Token::Minus);
+                tokens.push((Token::Minus, span));
+            }
+            '*' => {
+                chars.next();
+                tokens.push((Token::Star, span));
+            }
+            '%' => {
+                chars.next();
+                tokens.push((Token::Percent, span));
             }
             '!' => {
                 chars.next();
-                tokens.push(Token::Not);
+                if let Some(&(_, '=')) = chars.peek() {
+                    chars.next();
+                    tokens.push((Token::NotEqual, span));
+                } else {
+                    tokens.push((Token::Not, span));
+                }
             }
             '&' => {
                 chars.next();
-                if let Some(&'&') = chars.peek() {
+                if let Some(&(_, '&')) = chars.peek() {
                     chars.next();
-                    tokens.push(Token::And);
+                    tokens.push((Token::And, span));
                 } else {
-                    return Err("Expected '&' after '&'".to_string());
+                    return Err(ParseError {
+                        message: "Expected '&' after '&'".to_string(),
+                        span: Some(span),
+                    });
                 }
             }
             '|' => {
                 chars.next();
-                if let Some(&'|') = chars.peek() {
+                if let Some(&(_, '|')) = chars.peek() {
                     chars.next();
-                    tokens.push(Token::Or);
+                    tokens.push((Token::Or, span));
                 } else {
-                    return Err("Expected '|' after '|'".to_string());
+                    return Err(ParseError {
+                        message: "Expected '|' after '|'".to_string(),
+                        span: Some(span),
+                    });
                 }
             }
             ';' => {
                 chars.next();
-                tokens.push(Token::Semicolon);
+                tokens.push((Token::Semicolon, span));
             }
             '(' => {
                 chars.next();
-                tokens.push(Token::LParen);
+                tokens.push((Token::LParen, span));
             }
             ')' => {
                 chars.next();
-                tokens.push(Token::RParen);
+                tokens.push((Token::RParen, span));
+            }
+            '[' => {
+                chars.next();
+                tokens.push((Token::LBracket, span));
+            }
+            ']' => {
+                chars.next();
+                tokens.push((Token::RBracket, span));
+            }
+            ',' => {
+                chars.next();
+                tokens.push((Token::Comma, span));
             }
             '{' => {
                 chars.next();
-                tokens.push(Token::LBrace);
+                tokens.push((Token::LBrace, span));
             }
             '}' => {
                 chars.next();
-                tokens.push(Token::RBrace);
+                tokens.push((Token::RBrace, span));
             }
             '?' => {
                 chars.next();
-                tokens.push(Token::Question);
+                tokens.push((Token::Question, span));
+            }
+            '"' => {
+                chars.next(); // consume the opening quote
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\n')) | None => {
+                            return Err(ParseError {
+                                message: "Unterminated string".to_string(),
+                                span: Some(span),
+                            });
+                        }
+                        Some((_, c)) => text.push(c),
+                    }
+                }
+                tokens.push((Token::Str(text), span));
             }
             _ => {
-                return Err(format!("Unexpected character: {}", c));
+                return Err(ParseError {
+                    message: format!("Unexpected character: {}", c),
+                    span: Some(span),
+                });
             }
         }
     }
 
-    tokens.push(Token::Eof);
+    // The end of input is reported just after the last line with text
+    let text = source.trim_end();
+    let span = Span {
+        line: text.lines().count().max(1),
+        column: text.lines().last().map_or(0, |l| l.chars().count()) + 1,
+    };
+    tokens.push((Token::Eof, span));
     Ok(tokens)
 }
 
@@ -906,8 +2988,10 @@ mod tests {
     }
 
     #[test]
-    fn test_tokenize_error_incomplete_assign() {
-        let result = tokenize("x :");
+    fn test_parse_error_incomplete_assign() {
+        // A lone ':' is the declaration separator, so "x :" tokenizes but does not parse
+        let mut table = ExprHc::new();
+        let result = parse("x :", &mut table);
         assert!(result.is_err());
     }
 
@@ -917,6 +3001,34 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_tokenize_spans() {
+        let tokens = tokenize_with_spans("x := 1;\n  // note\n  Y").unwrap();
+        let spans: Vec<_> = tokens.iter().map(|(_, span)| (span.line, span.column)).collect();
+        assert_eq!(spans, vec![(1, 1), (1, 3), (1, 6), (1, 7), (3, 3), (3, 4)]);
+
+        let err = tokenize("x := 1;\ny = 2").unwrap_err();
+        assert_eq!(err.span, Some(Span { line: 2, column: 3 }));
+    }
+
+    #[test]
+    fn test_parse_error_position() {
+        let mut table = ExprHc::new();
+        let source = "request foo {\n  X := 1;\n  Y := ;\n}";
+        let err = parse_program(source, &mut table).unwrap_err();
+        assert_eq!(err.span, Some(Span { line: 3, column: 8 }));
+        assert_eq!(
+            err.render(source),
+            format!("{}\n  |\n3 |   Y := ;\n  |        ^", err)
+        );
+
+        // The error of the program is reported, not that of the expression fallback
+        let err = parse_source(source, &mut table).unwrap_err();
+        assert_eq!(err.span, Some(Span { line: 3, column: 8 }));
+        let program = parse_source("X := 1; X", &mut table).unwrap();
+        assert_eq!(program.requests[0].name, "request");
+    }
+
     // Parser tests
     #[test]
     fn test_parse_assignment() {
@@ -1001,6 +3113,59 @@ mod tests {
         assert_eq!(expr, expected);
     }
 
+    #[test]
+    fn test_parse_channels() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "channel JOBS[2] : 0..3; channel DONE[1] : 0..1 bag;
+             request client { send(JOBS, 2); recv(DONE) }
+             request worker { j := recv(JOBS); send(DONE, j % 2) }",
+            &mut table,
+        )
+        .unwrap();
+        let fifo = Channel { capacity: 2, lo: 0, hi: 3, bag: false };
+        let bag = Channel { capacity: 1, lo: 0, hi: 1, bag: true };
+        assert_eq!(
+            program.channels,
+            vec![
+                ChannelDecl { name: "JOBS".to_string(), channel: fifo },
+                ChannelDecl { name: "DONE".to_string(), channel: bag },
+            ]
+        );
+        assert_eq!(program.requests[1].body.to_string(), "j := recv(JOBS); send(DONE, j % 2)");
+
+        let errors = [
+            ("request r { send(C, 1) }", "not a declared channel"),
+            ("channel C[0] : 0..1; request r { 0 }", "positive capacity"),
+            ("channel c[1] : 0..1; request r { 0 }", "uppercase"),
+            ("channel C[1] : 0..1; request r { C := 1 }", "only be used with send and recv"),
+            ("channel C[1] : 0..1; request r { recv(C) + C }", "only be used with send and recv"),
+            ("channel C[1] : 0..1; init { C := 1 } request r { 0 }", "starts empty"),
+            ("channel C[1] : 0..1; request r { transaction { send(C, 1) } }", "inside a transaction"),
+        ];
+        for (source, message) in errors {
+            let err = parse_program(source, &mut table).unwrap_err();
+            assert!(err.message.contains(message), "{}: {}", source, err);
+        }
+    }
+
+    #[test]
+    fn test_parse_transaction() {
+        let mut table = ExprHc::new();
+        let expr = parse("transaction { X := 1; yield; if (Y == 1) { abort } }; 0", &mut table).unwrap();
+        assert_eq!(expr.to_string(), "transaction{ X := 1; yield; if(Y == 1){abort}else{0} }; 0");
+
+        let errors = [
+            ("abort", "outside of a transaction"),
+            ("transaction { transaction { 0 } }", "cannot be nested"),
+            ("transaction { lock(L); abort }", "not allowed inside a transaction"),
+        ];
+        for (source, message) in errors {
+            let err = parse(source, &mut table).unwrap_err();
+            assert!(err.message.contains(message), "{}: {}", source, err);
+        }
+    }
+
     #[test]
     fn test_parse_number() {
         let mut table = ExprHc::new();
@@ -1065,6 +3230,86 @@ mod tests {
         assert_eq!(expr, expected);
     }
 
+    #[test]
+    fn test_tokenize_relational_and_multiplicative() {
+        let tokens = tokenize("a < b <= c > d >= e != f * g / h % i").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Identifier("a".to_string()),
+                Token::Less,
+                Token::Identifier("b".to_string()),
+                Token::LessEqual,
+                Token::Identifier("c".to_string()),
+                Token::Greater,
+                Token::Identifier("d".to_string()),
+                Token::GreaterEqual,
+                Token::Identifier("e".to_string()),
+                Token::NotEqual,
+                Token::Identifier("f".to_string()),
+                Token::Star,
+                Token::Identifier("g".to_string()),
+                Token::Slash,
+                Token::Identifier("h".to_string()),
+                Token::Percent,
+                Token::Identifier("i".to_string()),
+                Token::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_operator_precedence() {
+        // * binds tighter than +, which binds tighter than <, which binds tighter than !=
+        let mut table = ExprHc::new();
+        let expr = parse("x + y * 2 < 3 != z", &mut table).unwrap();
+        let x = table.variable("x".to_string());
+        let y = table.variable("y".to_string());
+        let two = table.number(2);
+        let three = table.number(3);
+        let z = table.variable("z".to_string());
+        let mul = table.multiply(y, two);
+        let add = table.add(x, mul);
+        let less = table.less(add, three);
+        let expected = table.not_equal(less, z);
+        assert_eq!(expr, expected);
+    }
+
+    #[test]
+    fn test_comparison_constant_folding() {
+        let mut table = ExprHc::new();
+        assert_eq!(parse("1 < 2", &mut table).unwrap(), table.number(1));
+        assert_eq!(parse("2 <= 2", &mut table).unwrap(), table.number(1));
+        assert_eq!(parse("1 > 2", &mut table).unwrap(), table.number(0));
+        assert_eq!(parse("1 >= 2", &mut table).unwrap(), table.number(0));
+        assert_eq!(parse("1 != 2", &mut table).unwrap(), table.number(1));
+    }
+
+    #[test]
+    fn test_division_and_modulo_semantics() {
+        let mut table = ExprHc::new();
+        assert_eq!(parse("7 * 3", &mut table).unwrap(), table.number(21));
+        assert_eq!(parse("7 / 2", &mut table).unwrap(), table.number(3));
+        assert_eq!(parse("7 % 3", &mut table).unwrap(), table.number(1));
+        // Euclidean: the remainder is never negative
+        assert_eq!(parse("0 - 7 / 2", &mut table).unwrap(), table.number(-3));
+        assert_eq!(parse("(0 - 7) / 2", &mut table).unwrap(), table.number(-4));
+        assert_eq!(parse("(0 - 7) % 3", &mut table).unwrap(), table.number(2));
+        assert_eq!(parse("7 % (0 - 3)", &mut table).unwrap(), table.number(1));
+        // Division by zero yields 0, and the remainder is the dividend
+        assert_eq!(parse("5 / 0", &mut table).unwrap(), table.number(0));
+        assert_eq!(parse("5 % 0", &mut table).unwrap(), table.number(5));
+    }
+
+    #[test]
+    fn test_slash_is_not_a_comment() {
+        let mut table = ExprHc::new();
+        let expr = parse("x / 2 // halve it", &mut table).unwrap();
+        let x = table.variable("x".to_string());
+        let two = table.number(2);
+        assert_eq!(expr, table.divide(x, two));
+    }
+
     #[test]
     fn test_parse_not() {
         let mut table = ExprHc::new();
@@ -1249,6 +3494,485 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_parse_declarations() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "global X : 0..3 wrap; local y : -1..10; request foo { X := y + 1; y := 2 }",
+            &mut table,
+        )
+        .unwrap();
+        assert_eq!(
+            program.declarations,
+            vec![
+                VarDecl {
+                    name: "X".to_string(),
+                    indices: None,
+                    domain: Domain { lo: 0, hi: 3, overflow: Overflow::Wrap },
+                    enum_type: None,
+                },
+                VarDecl {
+                    name: "y".to_string(),
+                    indices: None,
+                    domain: Domain { lo: -1, hi: 10, overflow: Overflow::Error },
+                    enum_type: None,
+                },
+            ]
+        );
+
+        // Writes to declared variables are checked; constants in range are not
+        let y = table.variable("y".to_string());
+        let one = table.number(1);
+        let sum = table.add(y, one);
+        let x_domain = program.declarations[0].domain;
+        let checked = table.bounded("X".to_string(), x_domain, sum);
+        let assign_x = table.assign("X".to_string(), checked);
+        let two = table.number(2);
+        let assign_y = table.assign("y".to_string(), two);
+        let expected = table.sequence(assign_x, assign_y);
+        assert_eq!(program.requests[0].body, expected);
+    }
+
+    #[test]
+    fn test_parse_declaration_errors() {
+        let mut table = ExprHc::new();
+        // Case must match the scope
+        assert!(parse_program("global x : 0..3; request foo { 0 }", &mut table).is_err());
+        assert!(parse_program("local X : 0..3; request foo { 0 }", &mut table).is_err());
+        // The implicit initial value 0 must be in the domain
+        assert!(parse_program("global X : 1..3; request foo { 0 }", &mut table).is_err());
+        // Declarations come before requests
+        assert!(parse_program("request foo { 0 } global X : 0..3;", &mut table).is_err());
+        assert!(parse_program("global X : 0..3; global X : 0..1; request foo { 0 }", &mut table).is_err());
+    }
+
+    #[test]
+    fn test_parse_request_parameters() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "request transfer(amount in 0..3, dir in -1..1) { amount } request reset() { 0 }",
+            &mut table,
+        )
+        .unwrap();
+        let transfer = &program.requests[0];
+        assert_eq!(
+            transfer.params,
+            vec![
+                Param { name: "amount".to_string(), lo: 0, hi: 3 },
+                Param { name: "dir".to_string(), lo: -1, hi: 1 },
+            ]
+        );
+        let instances = transfer.instances();
+        assert_eq!(instances.len(), 12);
+        assert_eq!(instances[0], vec![0, -1]);
+        assert_eq!(instances[11], vec![3, 1]);
+        assert_eq!(program.requests[1].instances(), vec![Vec::<i64>::new()]);
+
+        assert!(parse_program("request foo(X in 0..1) { 0 }", &mut table).is_err());
+        assert!(parse_program("request foo(x in 2..1) { 0 }", &mut table).is_err());
+        assert!(parse_program("request foo(x in 0..1, x in 0..1) { 0 }", &mut table).is_err());
+        assert!(parse_program("local x : 0..1; request foo(x in 0..2) { 0 }", &mut table).is_err());
+    }
+
+    #[test]
+    fn test_fn_calls_are_inlined() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "fn spin() { while(STOP == 1) { yield } }
+             fn inc3() { X := (X + 1) % 3 }
+             request foo { spin(); inc3(); yield; inc3() }",
+            &mut table,
+        )
+        .unwrap();
+        let expected = parse(
+            "while(STOP == 1) { yield }; X := (X + 1) % 3; yield; X := (X + 1) % 3",
+            &mut table,
+        )
+        .unwrap();
+        assert_eq!(program.requests[0].body, expected);
+    }
+
+    #[test]
+    fn test_fn_locals_are_renamed() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "fn add(a, b) { t := a + b; t } request foo { t := 5; add(1, t) }",
+            &mut table,
+        )
+        .unwrap();
+        let t = table.variable("t".to_string());
+        let a = table.variable("a@add".to_string());
+        let b = table.variable("b@add".to_string());
+        let t_add = table.variable("t@add".to_string());
+        let one = table.number(1);
+        let five = table.number(5);
+        let zero = table.number(0);
+        let sum = table.add(a, b);
+        let assign_t_add = table.assign("t@add".to_string(), sum);
+        let body = table.sequence(assign_t_add, t_add);
+        let reset = table.assign("t@add".to_string(), zero);
+        let body = table.sequence(reset, body);
+        let assign_b = table.assign("b@add".to_string(), t);
+        let body = table.sequence(assign_b, body);
+        let assign_a = table.assign("a@add".to_string(), one);
+        let body = table.sequence(assign_a, body);
+        let assign_t = table.assign("t".to_string(), five);
+        let expected = table.sequence(assign_t, body);
+        assert_eq!(program.requests[0].body, expected);
+    }
+
+    #[test]
+    fn test_nested_call_to_same_fn_gets_fresh_parameters() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "fn sub(a, b) { a - b } request foo { sub(1, sub(2, 3)) }",
+            &mut table,
+        )
+        .unwrap();
+        let body = program.requests[0].body.to_string();
+        assert!(body.contains("a@sub#2"));
+        assert!(body.contains("a@sub :="));
+    }
+
+    #[test]
+    fn test_fn_errors() {
+        let mut table = ExprHc::new();
+        let cases = [
+            ("request foo { f() }", "undefined function 'f'"),
+            ("fn f(a) { a } request foo { f(1, 2) }", "expects 1 arguments, found 2"),
+            ("fn f() { f() } request foo { 0 }", "undefined function 'f'"),
+            ("fn f() { 0 } fn f() { 1 } request foo { 0 }", "defined twice"),
+            ("fn f(a, a) { 0 } request foo { 0 }", "declared twice"),
+        ];
+        for (source, message) in cases {
+            let err = parse_program(source, &mut table).unwrap_err();
+            assert!(err.message.contains(message), "{}: {}", source, err);
+        }
+    }
+
+    #[test]
+    fn test_parse_atomic() {
+        let mut table = ExprHc::new();
+        let expr = parse("atomic { t := X; X := t + 1 }; yield", &mut table).unwrap();
+        assert_eq!(expr.to_string(), "atomic{ t := X; X := t + 1 }; yield");
+
+        // Nothing to protect in a constant
+        assert_eq!(parse("atomic { 3 }", &mut table).unwrap(), table.number(3));
+    }
+
+    #[test]
+    fn test_yield_inside_atomic_is_rejected() {
+        let mut table = ExprHc::new();
+        let err = parse("atomic { X := 1; yield; X := 2 }", &mut table).unwrap_err();
+        assert!(err.message.contains("atomic"), "{}", err);
+
+        // A procedure that yields may be defined, but not called inside an atomic block
+        let source = "fn spin() { while(STOP == 1) { yield } } request foo { atomic { spin() } }";
+        let err = parse_program(source, &mut table).unwrap_err();
+        assert!(err.message.contains("in call to 'spin'"), "{}", err);
+        let source = "fn spin() { while(STOP == 1) { yield } } request foo { spin(); atomic { 0 } }";
+        assert!(parse_program(source, &mut table).is_ok());
+    }
+
+    #[test]
+    fn test_parse_lock_primitives() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "global S : 0..2; request foo { lock(L); acquire(S); release(S); unlock(L) }",
+            &mut table,
+        )
+        .unwrap();
+        let lock = table.lock("L".to_string());
+        let acquire = table.acquire("S".to_string(), 2);
+        let release = table.release("S".to_string());
+        let unlock = table.unlock("L".to_string());
+        let tail = table.sequence(release, unlock);
+        let tail = table.sequence(acquire, tail);
+        let expected = table.sequence(lock, tail);
+        assert_eq!(program.requests[0].body, expected);
+        assert_eq!(expected.to_string(), "lock(L); acquire(S); release(S); unlock(L)");
+
+        assert!(parse("lock(l)", &mut table).is_err());
+        // A semaphore needs a capacity
+        assert!(parse("acquire(S)", &mut table).is_err());
+    }
+
+    #[test]
+    fn test_parse_arrays() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "global A[0..3] : 0..1; request put(k in 0..3) { A[k] := 1; A[k + 1] == A[k] }",
+            &mut table,
+        )
+        .unwrap();
+        let indices = Indices { lo: 0, hi: 3 };
+        assert_eq!(program.declarations[0].indices, Some(indices));
+        assert_eq!(
+            program.requests[0].body.to_string(),
+            "A[k] := 1; A[k + 1] == A[k]"
+        );
+        let k = table.variable("k".to_string());
+        let one = table.number(1);
+        let store = table.store("A".to_string(), indices, k.clone(), one);
+        let Expr::Sequence(first, _) = program.requests[0].body.as_ref() else {
+            panic!("expected a sequence");
+        };
+        assert_eq!(*first, store);
+
+        assert!(parse_program("global A[0..3] : 0..1; request foo { A }", &mut table).is_err());
+        assert!(parse_program("global A[0..3] : 0..1; request foo { A := 1 }", &mut table).is_err());
+        assert!(parse_program("request foo { B[0] := 1 }", &mut table).is_err());
+        assert!(parse_program("local a[0..3] : 0..1; request foo { 0 }", &mut table).is_err());
+    }
+
+    #[test]
+    fn test_parse_init() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "global X : 1..3; global A[0..1] : 0..3;
+             init { X := 2; STOP := 0; A[1] := 3 }
+             init Y in {0, 1}
+             request foo { X }",
+            &mut table,
+        )
+        .unwrap();
+        let init = |name: &str, index, values: Vec<i64>| Init {
+            name: name.to_string(),
+            index,
+            values,
+        };
+        assert_eq!(
+            program.inits,
+            vec![
+                init("X", None, vec![2]),
+                init("STOP", None, vec![0]),
+                init("A", Some(1), vec![3]),
+                init("Y", None, vec![0, 1]),
+            ]
+        );
+        let program = parse_program("init X in -1..1; request foo { X }", &mut table).unwrap();
+        assert_eq!(program.inits[0].values, vec![-1, 0, 1]);
+    }
+
+    #[test]
+    fn test_init_errors() {
+        let mut table = ExprHc::new();
+        // Only globals can be initialized
+        assert!(parse_program("init { x := 1 } request foo { 0 }", &mut table).is_err());
+        // Initial values must be in the domain
+        assert!(parse_program("global X : 0..3; init X in {2, 4} request foo { 0 }", &mut table).is_err());
+        // A global whose domain excludes 0 needs an initial value
+        assert!(parse_program("global X : 1..3; request foo { 0 }", &mut table).is_err());
+        assert!(parse_program("global X : 1..3; init { X := 1 } request foo { 0 }", &mut table).is_ok());
+        // Arrays are initialized element by element, within their indices
+        assert!(parse_program("global A[0..1] : 0..3; init { A := 1 } request foo { 0 }", &mut table).is_err());
+        assert!(parse_program("global A[0..1] : 0..3; init { A[2] := 1 } request foo { 0 }", &mut table).is_err());
+        assert!(parse_program("init { B[0] := 1 } request foo { 0 }", &mut table).is_err());
+        // Each global is initialized once, before the requests
+        assert!(parse_program("init { X := 1 } init X in {0, 1} request foo { 0 }", &mut table).is_err());
+        assert!(parse_program("request foo { 0 } init { X := 1 }", &mut table).is_err());
+        assert!(parse_program("init X in {} request foo { 0 }", &mut table).is_err());
+    }
+
+    #[test]
+    fn test_parse_control_flow_sugar() {
+        let mut table = ExprHc::new();
+        // A missing else branch is 0, and else-if chains nest
+        assert_eq!(
+            parse("if(x == 1){y := 2}", &mut table).unwrap(),
+            parse("if(x == 1){y := 2}else{0}", &mut table).unwrap()
+        );
+        assert_eq!(
+            parse("if(x == 1){1}else if(x == 2){2}else{3}", &mut table).unwrap(),
+            parse("if(x == 1){1}else{if(x == 2){2}else{3}}", &mut table).unwrap()
+        );
+
+        let expr = parse("while(1){ if(x == 2){break}; continue }; return x + 1", &mut table).unwrap();
+        assert_eq!(
+            expr.to_string(),
+            "while(1){ if(x == 2){break}else{0}; continue }; return x + 1"
+        );
+        let bare = parse("return", &mut table).unwrap();
+        let zero = table.number(0);
+        assert_eq!(bare, table.return_expr(zero));
+
+        assert!(parse("break", &mut table).is_err());
+        assert!(parse("while(1){ 0 }; continue", &mut table).is_err());
+        let source = "fn f() { return 1 } request foo { f() }";
+        assert!(parse_program(source, &mut table).is_err());
+        // A loop in a procedure may be left, but not the loop around the call
+        let source = "fn f() { while(1){ break } } request foo { while(1){ f(); break } }";
+        assert!(parse_program(source, &mut table).is_ok());
+        let source = "fn f() { break } request foo { while(1){ f() } }";
+        assert!(parse_program(source, &mut table).is_err());
+    }
+
+    #[test]
+    fn test_parse_choose() {
+        let mut table = ExprHc::new();
+        let expr = parse("x := choose(-1..2); choose { X := x } or { 0 } or { yield }", &mut table).unwrap();
+        assert_eq!(
+            expr.to_string(),
+            "x := choose(-1..2); choose{ X := x }or{ 0 }or{ yield }"
+        );
+        assert_eq!(parse(&expr.to_string(), &mut table).unwrap(), expr);
+        assert_eq!(parse("choose(3..3)", &mut table).unwrap(), table.number(3));
+
+        assert!(parse("choose(2..1)", &mut table).is_err());
+        assert!(parse("choose { 1 }", &mut table).is_err());
+        assert!(parse("choose(x..2)", &mut table).is_err());
+    }
+
+    #[test]
+    fn test_parse_assume_assert() {
+        let mut table = ExprHc::new();
+        let expr = parse("assume(X == 0); X := 1; assert(X == 1)", &mut table).unwrap();
+        assert_eq!(expr.to_string(), "assume(X == 0); X := 1; assert(X == 1)");
+        assert_eq!(parse(&expr.to_string(), &mut table).unwrap(), expr);
+
+        assert!(parse("assert X == 1", &mut table).is_err());
+        assert!(parse("assume(X == 1", &mut table).is_err());
+    }
+
+    #[test]
+    fn test_parse_await() {
+        let mut table = ExprHc::new();
+        let expr = parse("await(STOP == 0); X := 1", &mut table).unwrap();
+        assert_eq!(expr.to_string(), "await(STOP == 0); X := 1");
+        assert_eq!(parse(&expr.to_string(), &mut table).unwrap(), expr);
+
+        let err = parse("await(yield; STOP == 0)", &mut table).unwrap_err();
+        assert!(err.message.contains("'await' condition"), "{}", err);
+        assert!(parse("await STOP == 0", &mut table).is_err());
+    }
+
+    #[test]
+    fn test_parse_enum() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "enum Color { RED, GREEN, BLUE }
+             global C : Color wrap;
+             init C in {RED, BLUE}
+             request next : Color { C := C + 1; if (C == RED) { GREEN } else { C } }",
+            &mut table,
+        )
+        .unwrap();
+        assert_eq!(
+            program.enums,
+            vec![EnumDecl {
+                name: "Color".to_string(),
+                constants: vec!["RED".to_string(), "GREEN".to_string(), "BLUE".to_string()],
+            }]
+        );
+        assert_eq!(program.declarations[0].enum_type, Some("Color".to_string()));
+        assert_eq!(program.declarations[0].domain, Domain { lo: 0, hi: 2, overflow: Overflow::Wrap });
+        assert_eq!(program.inits[0].values, vec![0, 2]);
+        assert_eq!(program.requests[0].returns, Some("Color".to_string()));
+        assert_eq!(
+            program.requests[0].body.to_string(),
+            "C := C + 1; if(C == RED){GREEN}else{C}"
+        );
+
+        let errors = [
+            ("enum E { A } enum E { B } request r { 0 }", "declared twice"),
+            ("enum E { A, A } request r { 0 }", "declared twice"),
+            ("enum E { } request r { 0 }", "no constants"),
+            ("enum E { A } request r { A := 1 }", "Cannot assign"),
+            ("enum E { A } global A : 0..1; request r { 0 }", "is an enum constant"),
+            ("global X : Color; request r { 0 }", "Unknown enum"),
+            ("request r : Color { 0 }", "Unknown enum"),
+            ("enum E { A } init X in {A, B} request r { 0 }", "not an enum constant"),
+        ];
+        for (source, message) in errors {
+            let err = parse_program(source, &mut table).unwrap_err();
+            assert!(err.message.contains(message), "{}: {}", source, err);
+        }
+    }
+
+    #[test]
+    fn test_parse_tuple() {
+        let mut table = ExprHc::new();
+        let program = parse_program(
+            "request r { x := 1; if (x == 1) { (x, 2 + 3) } else { return (0, 0, 0) } }",
+            &mut table,
+        )
+        .unwrap();
+        assert_eq!(
+            program.requests[0].body.to_string(),
+            "x := 1; if(x == 1){(x, 5)}else{return (0, 0, 0)}"
+        );
+        // Parentheses around a single expression still only group
+        let grouped = parse("(X + 2) * 3", &mut table).unwrap();
+        assert!(matches!(grouped.get(), Expr::Multiply(_, _)));
+
+        let errors = [
+            ("request r { x := (1, 2); x }", "only be the result"),
+            ("request r { (1, 2); 3 }", "only be the result"),
+            ("request r { (1, 2) + 1 }", "only be the result"),
+            ("request r { ((1, 2), 3) }", "only be the result"),
+            ("enum E { A } request r : E { (A, A) }", "responds with an enum"),
+        ];
+        for (source, message) in errors {
+            let err = parse_program(source, &mut table).unwrap_err();
+            assert!(err.message.contains(message), "{}: {}", source, err);
+        }
+    }
+
+    #[test]
+    fn test_parse_crashable() {
+        let mut table = ExprHc::new();
+        let program = parse_program("crashable request a { 0 } request b { 1 }", &mut table).unwrap();
+        let crashable: Vec<bool> = program.requests.iter().map(|r| r.crashable).collect();
+        assert_eq!(crashable, vec![true, false]);
+
+        let program = parse_program("crashable; request a { 0 } request b { 1 }", &mut table).unwrap();
+        assert!(program.requests.iter().all(|r| r.crashable));
+
+        let err = parse_program("crashable fn f() { 0 } request a { 0 }", &mut table).unwrap_err();
+        assert!(err.message.contains("after 'crashable'"), "{}", err);
+    }
+
+    #[test]
+    fn test_imports() {
+        let found = imports("import \"common.ser\";\nimport \"lib/a.ser\"; request r { 0 }").unwrap();
+        let paths: Vec<_> = found.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths, vec!["common.ser", "lib/a.ser"]);
+        assert_eq!(found[1].1, Span { line: 2, column: 8 });
+
+        let errors = [
+            ("import common; request r { 0 }", "file name in quotes"),
+            ("import \"common.ser\" request r { 0 }", "Expected ';'"),
+            ("import \"common.ser", "Unterminated string"),
+        ];
+        for (source, message) in errors {
+            let err = imports(source).unwrap_err();
+            assert!(err.message.contains(message), "{}: {}", source, err);
+        }
+    }
+
+    #[test]
+    fn test_domain_overflow_modes() {
+        let wrap = Domain { lo: 0, hi: 3, overflow: Overflow::Wrap };
+        assert_eq!(wrap.apply(4), Some(0));
+        assert_eq!(wrap.apply(-1), Some(3));
+        let saturate = Domain { lo: 0, hi: 3, overflow: Overflow::Saturate };
+        assert_eq!(saturate.apply(7), Some(3));
+        assert_eq!(saturate.apply(-2), Some(0));
+        let error = Domain { lo: 0, hi: 3, overflow: Overflow::Error };
+        assert_eq!(error.apply(3), Some(3));
+        assert_eq!(error.apply(4), None);
+    }
+
+    #[test]
+    fn test_domain_wrap_with_extreme_bounds() {
+        let wide = Domain { lo: -2, hi: i64::MAX - 1, overflow: Overflow::Wrap };
+        assert_eq!(wide.apply(i64::MAX), Some(-2));
+        assert_eq!(wide.apply(-3), Some(i64::MAX - 1));
+        let natural = Domain { lo: 0, hi: i64::MAX, overflow: Overflow::Wrap };
+        assert_eq!(natural.apply(-1), Some(i64::MAX));
+        assert_eq!(natural.apply(i64::MIN), Some(0));
+    }
+
     #[test]
     fn test_tokens_to_string() {
         // Test that tokens can be converted back to strings correctly via Display
@@ -1329,13 +4053,24 @@ mod tests {
         let body = table.assign("x".to_string(), one);
         
         let program = Program {
+            enums: vec![],
+            declarations: vec![],
+            inits: vec![],
+            channels: vec![],
+            loops: vec![],
             requests: vec![
                 Request {
                     name: "foo".to_string(),
+                    params: vec![],
+                    returns: None,
+                    crashable: false,
                     body: body.clone(),
                 },
                 Request {
                     name: "bar".to_string(),
+                    params: vec![],
+                    returns: None,
+                    crashable: true,
                     body: x.clone(),
                 },
             ],
@@ -1350,5 +4085,6 @@ mod tests {
         assert_eq!(*program.requests[0].body, *deserialized.requests[0].body);
         assert_eq!(program.requests[1].name, deserialized.requests[1].name);
         assert_eq!(*program.requests[1].body, *deserialized.requests[1].body);
+        assert!(deserialized.requests[1].crashable);
     }
 }
@@ -73,7 +73,7 @@ pub struct SerializabilityStats {
     pub timestamp: DateTime<Utc>,
     pub example: String,
     pub options: OptimizationOptions,
-    pub result: String, // "serializable", "not_serializable", "error", "timeout"
+    pub result: String, // "serializable", "not_serializable", "assertion_violation", "error", "timeout"
     pub certificate_creation_time_ms: Option<u64>,
     pub certificate_checking_time_ms: Option<u64>,
     pub num_disjuncts: usize,
@@ -108,6 +108,10 @@ pub struct SemilinearComponent {
 pub struct PetriNetStats {
     pub places_before: usize,
     pub transitions_before: usize,
+    /// Self-loops of requests blocked in `await`, `lock`, `acquire`, `send` or `recv`
+    /// that were never added to the network system
+    #[serde(default)]
+    pub skipped_spin_transitions: usize,
     pub disjuncts: Vec<DisjunctStats>,
 }
 
@@ -164,6 +168,7 @@ impl StatsCollector {
             petri_net: PetriNetStats {
                 places_before: 0,
                 transitions_before: 0,
+                skipped_spin_transitions: 0,
                 disjuncts: vec![],
             },
             total_time_ms: 0,
@@ -209,6 +214,12 @@ impl StatsCollector {
         }
     }
 
+    pub fn set_skipped_spin_transitions(&mut self, skipped: usize) {
+        if let Some(stats) = &mut self.current_stats {
+            stats.petri_net.skipped_spin_transitions = skipped;
+        }
+    }
+
     pub fn add_disjunct_stats(&mut self, disjunct: DisjunctStats) {
         if let Some(stats) = &mut self.current_stats {
             stats.petri_net.disjuncts.push(disjunct);
@@ -313,6 +324,12 @@ pub fn set_petri_net_sizes(places: usize, transitions: usize) {
     }
 }
 
+pub fn set_skipped_spin_transitions(skipped: usize) {
+    if let Ok(mut collector) = STATS_COLLECTOR.lock() {
+        collector.set_skipped_spin_transitions(skipped);
+    }
+}
+
 pub fn add_disjunct_stats(stats: DisjunctStats) {
     if let Ok(mut collector) = STATS_COLLECTOR.lock() {
         collector.add_disjunct_stats(stats);